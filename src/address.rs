@@ -0,0 +1,484 @@
+#[cfg(feature = "json")]
+use crate::device::Device;
+#[cfg(feature = "json")]
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+#[cfg(feature = "json")]
+use std::fs::{self, File, OpenOptions};
+use std::net::{IpAddr, SocketAddr};
+#[cfg(feature = "json")]
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+#[cfg(feature = "json")]
+use time::OffsetDateTime;
+
+/// Where a device can be reached: an IP address, a DNS hostname or an mDNS `.local` name,
+/// with an optional port.
+///
+/// Parses from and displays as `host`, `host:port` or, for IPv6 with a port, `[host]:port`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Address {
+    pub host: Host,
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Host {
+    Ip(IpAddr),
+    Hostname(String),
+    /// A multicast DNS name, ending in `.local`.
+    Mdns(String),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AddressError {
+    #[error("address is empty")]
+    Empty,
+    #[error("invalid port {0:?}")]
+    InvalidPort(String),
+    #[error("invalid hostname {hostname:?}: {reason}")]
+    InvalidHostname {
+        hostname: String,
+        reason: &'static str,
+    },
+}
+
+impl Address {
+    pub fn new(host: Host, port: Option<u16>) -> Self {
+        Self { host, port }
+    }
+    pub fn is_mdns(&self) -> bool {
+        matches!(self.host, Host::Mdns(_))
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(address: SocketAddr) -> Self {
+        Self::new(Host::Ip(address.ip()), Some(address.port()))
+    }
+}
+
+impl From<IpAddr> for Address {
+    fn from(ip: IpAddr) -> Self {
+        Self::new(Host::Ip(ip), None)
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, AddressError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(AddressError::Empty);
+        }
+        if let Ok(address) = s.parse::<SocketAddr>() {
+            if address.port() == 0 {
+                return Err(AddressError::InvalidPort("0".to_string()));
+            }
+            return Ok(address.into());
+        }
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            return Ok(ip.into());
+        }
+        let (host, port) = match s.rsplit_once(':') {
+            Some((host, port)) => match port.parse::<u16>() {
+                Ok(port) if port > 0 => (host, Some(port)),
+                _ => return Err(AddressError::InvalidPort(port.to_string())),
+            },
+            None => (s, None),
+        };
+        validate_hostname(host)?;
+        let host = if host.to_ascii_lowercase().ends_with(".local") {
+            Host::Mdns(host.to_string())
+        } else {
+            Host::Hostname(host.to_string())
+        };
+        Ok(Self::new(host, port))
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = AddressError;
+
+    fn try_from(s: String) -> Result<Self, AddressError> {
+        s.parse()
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> Self {
+        address.to_string()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.host, self.port) {
+            (Host::Ip(IpAddr::V6(ip)), Some(port)) => write!(f, "[{ip}]:{port}"),
+            (host, Some(port)) => write!(f, "{host}:{port}"),
+            (host, None) => write!(f, "{host}"),
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Host::Ip(ip) => write!(f, "{ip}"),
+            Host::Hostname(name) | Host::Mdns(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Checks `hostname` against RFC 1123: dot-separated labels of 1 to 63 letters, digits
+/// and hyphens, not starting or ending with a hyphen, 253 characters in all.
+fn validate_hostname(hostname: &str) -> Result<(), AddressError> {
+    let invalid = |reason| AddressError::InvalidHostname {
+        hostname: hostname.to_string(),
+        reason,
+    };
+    if hostname.len() > 253 {
+        return Err(invalid("longer than 253 characters"));
+    }
+    for label in hostname.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid("labels must be 1 to 63 characters long"));
+        }
+        if !label
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+        {
+            return Err(invalid("only letters, digits and hyphens are allowed"));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(invalid("labels can't start or end with a hyphen"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+/// A device seen at an address, from `first_seen` until `last_seen`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressRecord {
+    pub device: Device,
+    pub address: Address,
+    #[serde(with = "time::serde::rfc3339")]
+    pub first_seen: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_seen: OffsetDateTime,
+}
+
+#[cfg(feature = "json")]
+#[derive(Default, Serialize, Deserialize)]
+struct AddressBookFile {
+    records: Vec<AddressRecord>,
+}
+
+/// How many addresses each device's history keeps by default.
+#[cfg(feature = "json")]
+pub const DEFAULT_MAX_HISTORY: usize = 32;
+
+#[cfg(feature = "json")]
+/// A local record of where devices were last seen, stored as JSON, so tools can reach a
+/// device when the backend is down.
+///
+/// Each device keeps its latest `max_history` addresses; seeing a device again at its
+/// current address only moves `last_seen`. Recording holds a lock on a `.lock` file next
+/// to the book and replaces the book in one rename, so concurrent writers don't lose each
+/// other's records and readers never see a half-written file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressBook {
+    path: PathBuf,
+    max_history: usize,
+}
+
+#[cfg(feature = "json")]
+impl AddressBook {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_history: DEFAULT_MAX_HISTORY,
+        }
+    }
+    /// Keeps only each device's latest `max_history` addresses, and at least one.
+    pub fn with_max_history(mut self, max_history: usize) -> Self {
+        self.max_history = max_history.max(1);
+        self
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Records that `device` was seen at `address` now.
+    pub fn record(&self, device: &Device, address: &Address) -> Result<AddressRecord, Error> {
+        self.record_at(device, address, OffsetDateTime::now_utc())
+    }
+    pub fn record_at(
+        &self,
+        device: &Device,
+        address: &Address,
+        seen: OffsetDateTime,
+    ) -> Result<AddressRecord, Error> {
+        let _lock = self.lock()?;
+        let mut file = self.load()?;
+        let current = file
+            .records
+            .iter_mut()
+            .rev()
+            .find(|record| record.device == *device);
+        let record = match current {
+            Some(current) if current.address == *address => {
+                current.last_seen = current.last_seen.max(seen);
+                current.clone()
+            }
+            _ => {
+                let record = AddressRecord {
+                    device: device.clone(),
+                    address: address.clone(),
+                    first_seen: seen,
+                    last_seen: seen,
+                };
+                file.records.push(record.clone());
+                record
+            }
+        };
+        let history = file
+            .records
+            .iter()
+            .filter(|record| record.device == *device)
+            .count();
+        let mut excess = history.saturating_sub(self.max_history);
+        file.records.retain(|record| {
+            let drop = excess > 0 && record.device == *device;
+            excess -= usize::from(drop);
+            !drop
+        });
+        self.save(&file)?;
+        Ok(record)
+    }
+    /// Where `device` was seen most recently.
+    pub fn lookup(&self, device: &Device) -> Result<Option<AddressRecord>, Error> {
+        Ok(self.address_history(device)?.into_iter().next())
+    }
+    /// Every address `device` was seen at, newest first.
+    pub fn address_history(&self, device: &Device) -> Result<Vec<AddressRecord>, Error> {
+        Ok(self
+            .load()?
+            .records
+            .into_iter()
+            .rev()
+            .filter(|record| record.device == *device)
+            .collect())
+    }
+    /// Every device's most recent record.
+    pub fn entries(&self) -> Result<Vec<AddressRecord>, Error> {
+        let mut latest: Vec<AddressRecord> = Vec::new();
+        for record in self.load()?.records.into_iter().rev() {
+            if !latest.iter().any(|seen| seen.device == record.device) {
+                latest.push(record);
+            }
+        }
+        Ok(latest)
+    }
+    fn load(&self) -> Result<AddressBookFile, Error> {
+        if !self.path.exists() {
+            return Ok(AddressBookFile::default());
+        }
+        let content =
+            fs::read_to_string(&self.path).map_err(|e| Error::file_system(&self.path, e))?;
+        serde_json::from_str(&content).map_err(|e| Error::json_read(e).with_path(&self.path))
+    }
+    /// Locks the book for a read-modify-write until the returned file is dropped.
+    fn lock(&self) -> Result<File, Error> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::file_system(parent, e))?;
+        }
+        let path = self.sibling("lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| Error::file_system(&path, e))?;
+        file.lock().map_err(|e| Error::file_system(&path, e))?;
+        Ok(file)
+    }
+    /// Writes the book next to itself and renames it into place. Callers hold the lock.
+    fn save(&self, file: &AddressBookFile) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(file).map_err(Error::JsonGeneration)?;
+        let temporary = self.sibling("tmp");
+        fs::write(&temporary, content).map_err(|e| Error::file_system(&temporary, e))?;
+        fs::rename(&temporary, &self.path).map_err(|e| Error::file_system(&self.path, e))
+    }
+    /// The book's path with `extension` appended, e.g. `addresses.json.lock`.
+    fn sibling(&self, extension: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".");
+        path.push(extension);
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "json")]
+    use crate::device::Model;
+    use std::net::Ipv4Addr;
+    #[cfg(feature = "json")]
+    use time::Duration;
+
+    #[test]
+    fn test_parse_and_display() {
+        let cases = [
+            (
+                "10.0.0.7",
+                Host::Ip(Ipv4Addr::new(10, 0, 0, 7).into()),
+                None,
+            ),
+            (
+                "10.0.0.7:8080",
+                Host::Ip(Ipv4Addr::new(10, 0, 0, 7).into()),
+                Some(8080),
+            ),
+            ("[::1]:80", Host::Ip("::1".parse().unwrap()), Some(80)),
+            ("fe80::1", Host::Ip("fe80::1".parse().unwrap()), None),
+            (
+                "scale-1.kitchen.example",
+                Host::Hostname("scale-1.kitchen.example".into()),
+                None,
+            ),
+            (
+                "libra-L1.local:5353",
+                Host::Mdns("libra-L1.local".into()),
+                Some(5353),
+            ),
+        ];
+        for (input, host, port) in cases {
+            let address: Address = input.parse().unwrap();
+            assert_eq!(address, Address::new(host, port));
+            assert_eq!(address.to_string(), input);
+        }
+        assert!("libra-L1.local".parse::<Address>().unwrap().is_mdns());
+    }
+
+    #[test]
+    fn test_invalid_addresses() {
+        assert_eq!("  ".parse::<Address>(), Err(AddressError::Empty));
+        assert_eq!(
+            "scale:http".parse::<Address>(),
+            Err(AddressError::InvalidPort("http".into()))
+        );
+        for address in ["scale:0", "10.0.0.7:0", "[::1]:0"] {
+            assert_eq!(
+                address.parse::<Address>(),
+                Err(AddressError::InvalidPort("0".into()))
+            );
+        }
+        for hostname in ["-scale", "scale..local", "scale_1", &"a".repeat(64)] {
+            assert!(matches!(
+                hostname.parse::<Address>(),
+                Err(AddressError::InvalidHostname { .. })
+            ));
+        }
+        let error = serde_json::from_str::<Address>("\"bad host\"").unwrap_err();
+        assert!(error.to_string().contains("invalid hostname"));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_address_book_history() {
+        let path = std::env::temp_dir().join("menu-test-address-book.json");
+        let _ = fs::remove_file(&path);
+        let book = AddressBook::new(&path);
+        let libra = Device::new(Model::LibraV0, "L1");
+        let ichibu = Device::new(Model::IchibuV2, "I1");
+        let first: Address = "10.0.0.7:80".parse().unwrap();
+        let second: Address = "libra-L1.local".parse().unwrap();
+        let start = OffsetDateTime::UNIX_EPOCH;
+
+        assert_eq!(book.lookup(&libra).unwrap(), None);
+        book.record_at(&libra, &first, start).unwrap();
+        book.record_at(&ichibu, &first, start).unwrap();
+        book.record_at(&libra, &first, start + Duration::minutes(1))
+            .unwrap();
+        book.record_at(&libra, &second, start + Duration::minutes(2))
+            .unwrap();
+
+        let history = book.address_history(&libra).unwrap();
+        let entries = book.entries().unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(book.sibling("lock")).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].address, second);
+        assert_eq!(history[1].address, first);
+        assert_eq!(history[1].first_seen, start);
+        assert_eq!(history[1].last_seen, start + Duration::minutes(1));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].address, second);
+        assert_eq!(entries[1].device, ichibu);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_address_book_caps_history() {
+        let path = std::env::temp_dir().join("menu-test-address-book-cap.json");
+        let _ = fs::remove_file(&path);
+        let book = AddressBook::new(&path).with_max_history(2);
+        let libra = Device::new(Model::LibraV0, "L1");
+        let ichibu = Device::new(Model::IchibuV2, "I1");
+        let start = OffsetDateTime::UNIX_EPOCH;
+        book.record_at(&ichibu, &"10.0.0.1".parse().unwrap(), start)
+            .unwrap();
+        for host in 2..6 {
+            let address = format!("10.0.0.{host}").parse().unwrap();
+            book.record_at(&libra, &address, start).unwrap();
+        }
+
+        let history = book.address_history(&libra).unwrap();
+        let ichibu_history = book.address_history(&ichibu).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(book.sibling("lock")).unwrap();
+        let addresses: Vec<String> = history.iter().map(|r| r.address.to_string()).collect();
+        assert_eq!(addresses, ["10.0.0.5", "10.0.0.4"]);
+        assert_eq!(ichibu_history.len(), 1);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_address_book_concurrent_records() {
+        let path = std::env::temp_dir().join("menu-test-address-book-concurrent.json");
+        let _ = fs::remove_file(&path);
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                // A book of its own per thread, as separate processes would have.
+                let book = AddressBook::new(&path);
+                std::thread::spawn(move || {
+                    let device = Device::new(Model::LibraV0, &format!("L{thread}"));
+                    for host in 1..6 {
+                        let address = format!("10.0.{thread}.{host}").parse().unwrap();
+                        book.record(&device, &address).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let book = AddressBook::new(&path);
+        let entries = book.entries().unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(book.sibling("lock")).unwrap();
+        assert_eq!(entries.len(), 8);
+        assert!(
+            entries
+                .iter()
+                .all(|r| r.address.to_string().ends_with(".5"))
+        );
+    }
+}
@@ -0,0 +1,628 @@
+use crate::device::Device;
+use crate::diff::FieldChange;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use time::OffsetDateTime;
+
+/// `previous_hash` of the first entry in a log.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Bytes read at a time when looking for the last entry of a log.
+const TAIL_CHUNK: u64 = 4096;
+
+static DEFAULT_AUDITOR: RwLock<Option<Auditor>> = RwLock::new(None);
+
+/// Sets the auditor that records mutations made without one of their own, such as
+/// [`Libra::edit_config_file`] or a [`ConfigBackend`] built without `with_auditor`.
+/// `None` turns it off again.
+///
+/// [`Libra::edit_config_file`]: crate::libra::Libra::edit_config_file
+/// [`ConfigBackend`]: crate::backend::ConfigBackend
+pub fn set_default_auditor(auditor: Option<Auditor>) {
+    *DEFAULT_AUDITOR.write().unwrap_or_else(|e| e.into_inner()) = auditor;
+}
+
+/// The auditor set with [`set_default_auditor`], if any.
+pub fn default_auditor() -> Option<Auditor> {
+    DEFAULT_AUDITOR
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Add,
+    Edit,
+    Remove,
+    /// A device moved to another location.
+    Transfer,
+    /// The mutation recorded by the entry named in `aborts` failed and changed nothing.
+    Abort,
+}
+
+/// Where a mutation was written to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum Source {
+    File { path: PathBuf },
+    Backend { url: String },
+}
+
+/// One mutation, as recorded in the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Position in the log, starting at 0.
+    pub sequence: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+    pub actor: String,
+    /// The device changed, or `None` for files that don't name one, such as a scale file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<Device>,
+    pub operation: Operation,
+    pub source: Source,
+    /// Changed fields. PIN values are redacted.
+    pub changes: Vec<FieldChange>,
+    /// For an [`Operation::Abort`], the sequence number of the entry that didn't happen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aborts: Option<u64>,
+    /// `hash` of the previous entry, or [`GENESIS_HASH`] for the first.
+    pub previous_hash: String,
+    /// Hex SHA-256 over every other field, chaining this entry to the previous one.
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> Result<String, Error> {
+        let mut value = serde_json::to_value(self).map_err(Error::JsonGeneration)?;
+        if let Some(map) = value.as_object_mut() {
+            map.remove("hash");
+        }
+        let digest = Sha256::digest(value.to_string().as_bytes());
+        Ok(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+}
+
+/// An append-only, hash-chained audit log stored as one JSON entry per line.
+///
+/// Editing or removing any entry breaks the chain from that entry on, which
+/// [`AuditLog::verify`] reports. Appends hold an exclusive lock on the file, so several
+/// processes can share a log without forking the chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Every entry, oldest first. A log that doesn't exist yet is empty.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, Error> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let content =
+            fs::read_to_string(&self.path).map_err(|e| Error::file_system(&self.path, e))?;
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .map_err(|e| Error::json_read(e).at_line(index + 1).with_path(&self.path))
+            })
+            .collect()
+    }
+    /// Appends an entry chained to the last one.
+    pub fn append(
+        &self,
+        actor: &str,
+        device: &Device,
+        operation: Operation,
+        source: Source,
+        changes: Vec<FieldChange>,
+    ) -> Result<AuditEntry, Error> {
+        self.append_entry(actor, Some(device), operation, source, changes, None)
+    }
+    fn append_entry(
+        &self,
+        actor: &str,
+        device: Option<&Device>,
+        operation: Operation,
+        source: Source,
+        changes: Vec<FieldChange>,
+        aborts: Option<u64>,
+    ) -> Result<AuditEntry, Error> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::file_system(parent, e))?;
+        }
+        let io_error = |e| Error::file_system(&self.path, e);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(io_error)?;
+        // Held until `file` is dropped, so no other writer can chain to the same entry.
+        file.lock().map_err(io_error)?;
+        let last = self.last_entry(&mut file)?;
+        let mut entry = AuditEntry {
+            sequence: last.as_ref().map_or(0, |last| last.sequence + 1),
+            timestamp: OffsetDateTime::now_utc(),
+            actor: actor.to_string(),
+            device: device.cloned(),
+            operation,
+            source,
+            changes: changes.into_iter().map(redact).collect(),
+            aborts,
+            previous_hash: last.map_or_else(|| GENESIS_HASH.to_string(), |last| last.hash),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash()?;
+
+        let mut line = serde_json::to_string(&entry).map_err(Error::JsonGeneration)?;
+        line.push('\n');
+        file.write_all(line.as_bytes()).map_err(io_error)?;
+        Ok(entry)
+    }
+    /// Reads the last entry from the end of the file, without reading the rest of it.
+    fn last_entry(&self, file: &mut File) -> Result<Option<AuditEntry>, Error> {
+        let io_error = |e| Error::file_system(&self.path, e);
+        let mut end = file.seek(SeekFrom::End(0)).map_err(io_error)?;
+        let mut tail = Vec::new();
+        loop {
+            let trimmed = tail.trim_ascii_end();
+            if let Some(newline) = trimmed.iter().rposition(|&byte| byte == b'\n') {
+                tail.drain(..=newline);
+                break;
+            }
+            if end == 0 {
+                break;
+            }
+            let start = end.saturating_sub(TAIL_CHUNK);
+            let mut chunk = vec![0; (end - start) as usize];
+            file.seek(SeekFrom::Start(start)).map_err(io_error)?;
+            file.read_exact(&mut chunk).map_err(io_error)?;
+            chunk.append(&mut tail);
+            tail = chunk;
+            end = start;
+        }
+        let line = tail.trim_ascii();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        serde_json::from_slice(line)
+            .map(Some)
+            .map_err(|e| Error::json_read(e).with_path(&self.path))
+    }
+    /// Checks the hash chain, returning the number of entries.
+    pub fn verify(&self) -> Result<usize, Error> {
+        let entries = self.entries()?;
+        let mut previous_hash = GENESIS_HASH.to_string();
+        for (index, entry) in entries.iter().enumerate() {
+            if entry.sequence != index as u64
+                || entry.previous_hash != previous_hash
+                || entry.hash != entry.compute_hash()?
+            {
+                return Err(Error::AuditChainBroken {
+                    path: self.path.clone(),
+                    sequence: index as u64,
+                });
+            }
+            previous_hash = entry.hash.clone();
+        }
+        Ok(entries.len())
+    }
+    /// The entries matching `query`, oldest first.
+    pub fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, Error> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter(|entry| query.matches(entry))
+            .collect())
+    }
+}
+
+fn redact(mut change: FieldChange) -> FieldChange {
+    if change.path == "pins" || change.path.starts_with("pins.") {
+        let redacted = || serde_json::Value::String("<redacted>".into());
+        change.old = change.old.map(|_| redacted());
+        change.new = change.new.map(|_| redacted());
+    }
+    change
+}
+
+/// Filters for [`AuditLog::query`]. Unset filters match everything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    device: Option<Device>,
+    actor: Option<String>,
+    field: Option<String>,
+    operation: Option<Operation>,
+    since: Option<OffsetDateTime>,
+    until: Option<OffsetDateTime>,
+}
+
+impl AuditQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn device(mut self, device: Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+    pub fn actor(mut self, actor: &str) -> Self {
+        self.actor = Some(actor.to_string());
+        self
+    }
+    /// Entries changing `field`, or anything below it if it names a table.
+    pub fn field(mut self, field: &str) -> Self {
+        self.field = Some(field.to_string());
+        self
+    }
+    pub fn operation(mut self, operation: Operation) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+    /// Entries recorded at or after `since`.
+    pub fn since(mut self, since: OffsetDateTime) -> Self {
+        self.since = Some(since);
+        self
+    }
+    /// Entries recorded before `until`.
+    pub fn until(mut self, until: OffsetDateTime) -> Self {
+        self.until = Some(until);
+        self
+    }
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.device
+            .as_ref()
+            .is_none_or(|device| entry.device.as_ref() == Some(device))
+            && self
+                .actor
+                .as_ref()
+                .is_none_or(|actor| *actor == entry.actor)
+            && self
+                .operation
+                .is_none_or(|operation| operation == entry.operation)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+            && self.field.as_ref().is_none_or(|field| {
+                entry.changes.iter().any(|change| {
+                    change
+                        .path
+                        .strip_prefix(field.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+                })
+            })
+    }
+}
+
+/// Records mutations made by one actor to an audit log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Auditor {
+    pub log: AuditLog,
+    pub actor: String,
+}
+
+impl Auditor {
+    pub fn new(log: AuditLog, actor: &str) -> Self {
+        Self {
+            log,
+            actor: actor.to_string(),
+        }
+    }
+}
+
+/// A mutation recorded before it runs; see [`begin`].
+#[cfg(feature = "write")]
+pub(crate) struct Pending<'a>(Option<(&'a Auditor, AuditEntry)>);
+
+/// Records a mutation with `auditor` before it is made, so nothing changes without a
+/// record of it. Edits that change nothing aren't recorded.
+#[cfg(feature = "write")]
+pub(crate) fn begin<'a>(
+    auditor: Option<&'a Auditor>,
+    device: Option<&Device>,
+    operation: Operation,
+    source: Source,
+    changes: Vec<FieldChange>,
+) -> Result<Pending<'a>, Error> {
+    let Some(auditor) = auditor else {
+        return Ok(Pending(None));
+    };
+    if operation == Operation::Edit && changes.is_empty() {
+        return Ok(Pending(None));
+    }
+    let entry =
+        auditor
+            .log
+            .append_entry(&auditor.actor, device, operation, source, changes, None)?;
+    Ok(Pending(Some((auditor, entry))))
+}
+
+#[cfg(feature = "write")]
+impl Pending<'_> {
+    /// Passes on the result of the mutation, recording an [`Operation::Abort`] if it failed.
+    /// The mutation's own error wins over a failure to record the abort.
+    pub(crate) fn finish<T>(self, result: Result<T, Error>) -> Result<T, Error> {
+        if let (Err(_), Some((auditor, entry))) = (&result, self.0) {
+            let _ = auditor.log.append_entry(
+                &auditor.actor,
+                entry.device.as_ref(),
+                Operation::Abort,
+                entry.source,
+                Vec::new(),
+                Some(entry.sequence),
+            );
+        }
+        result
+    }
+}
+
+/// Runs `mutate` between [`begin`] and [`Pending::finish`].
+#[cfg(feature = "write")]
+pub(crate) fn audited<T>(
+    auditor: Option<&Auditor>,
+    device: Option<&Device>,
+    operation: Operation,
+    source: Source,
+    changes: Vec<FieldChange>,
+    mutate: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    begin(auditor, device, operation, source, changes)?.finish(mutate())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Model;
+    use serde_json::json;
+
+    struct TestLog(AuditLog);
+    impl TestLog {
+        fn new(name: &str) -> Self {
+            let mut path = std::env::temp_dir();
+            path.push(format!("menu-test-{name}.audit.jsonl"));
+            let _ = fs::remove_file(&path);
+            Self(AuditLog::new(path))
+        }
+    }
+    impl Drop for TestLog {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(self.0.path());
+        }
+    }
+
+    fn change(path: &str, old: f64, new: f64) -> FieldChange {
+        FieldChange {
+            path: path.into(),
+            old: Some(json!(old)),
+            new: Some(json!(new)),
+        }
+    }
+
+    fn source() -> Source {
+        Source::File {
+            path: "scales.toml".into(),
+        }
+    }
+
+    #[test]
+    fn test_append_and_verify_chain() {
+        let log = TestLog::new("audit_chain");
+        let device = Device::new(Model::LibraV0, "L1");
+        let first = log
+            .0
+            .append(
+                "ana",
+                &device,
+                Operation::Edit,
+                source(),
+                vec![change("config.gain", 1., 2.)],
+            )
+            .unwrap();
+        let second = log
+            .0
+            .append("ben", &device, Operation::Remove, source(), vec![])
+            .unwrap();
+        assert_eq!(first.previous_hash, GENESIS_HASH);
+        assert_eq!(second.previous_hash, first.hash);
+        assert_eq!(second.sequence, 1);
+        assert_eq!(log.0.verify().unwrap(), 2);
+        assert_eq!(log.0.entries().unwrap(), [first, second]);
+    }
+
+    #[test]
+    fn test_floats_verify_exactly() {
+        let log = TestLog::new("audit_floats");
+        let device = Device::new(Model::LibraV0, "L1");
+        // Parses one ULP off without serde_json's `float_roundtrip`, changing the hash.
+        let changes = vec![change("config.gain", 1., 180.17933438838418)];
+        log.0
+            .append("ana", &device, Operation::Edit, source(), changes.clone())
+            .unwrap();
+        assert_eq!(log.0.verify().unwrap(), 1);
+        assert_eq!(log.0.entries().unwrap()[0].changes, changes);
+    }
+
+    #[test]
+    fn test_concurrent_appends_keep_one_chain() {
+        let log = TestLog::new("audit_concurrent");
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                // A log of its own per thread, as separate processes would have.
+                let log = AuditLog::new(log.0.path());
+                std::thread::spawn(move || {
+                    let device = Device::new(Model::LibraV0, &format!("L{thread}"));
+                    for _ in 0..5 {
+                        log.append("ana", &device, Operation::Edit, source(), vec![])
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(log.0.verify().unwrap(), 40);
+    }
+
+    #[test]
+    fn test_append_reads_only_the_tail() {
+        let log = TestLog::new("audit_tail");
+        let device = Device::new(Model::LibraV0, "L1");
+        // Entries longer than a chunk make the last one span several reads.
+        let long = vec![change(&"x".repeat(3 * TAIL_CHUNK as usize), 1., 2.)];
+        for _ in 0..3 {
+            log.0
+                .append("ana", &device, Operation::Edit, source(), long.clone())
+                .unwrap();
+        }
+        let last = log
+            .0
+            .append("ana", &device, Operation::Edit, source(), vec![])
+            .unwrap();
+        assert_eq!(last.sequence, 3);
+        assert_eq!(log.0.verify().unwrap(), 4);
+    }
+
+    #[cfg(feature = "write")]
+    #[test]
+    fn test_failed_mutation_is_aborted() {
+        let log = TestLog::new("audit_abort");
+        let auditor = Auditor::new(AuditLog::new(log.0.path()), "ana");
+        let device = Device::new(Model::LibraV0, "L1");
+        let changes = vec![change("config.gain", 1., 2.)];
+        let result: Result<(), Error> = audited(
+            Some(&auditor),
+            Some(&device),
+            Operation::Edit,
+            source(),
+            changes.clone(),
+            || {
+                assert_eq!(auditor.log.entries().unwrap().len(), 1, "recorded first");
+                Err(Error::NoSerialNumber)
+            },
+        );
+        assert!(matches!(result, Err(Error::NoSerialNumber)));
+        audited(
+            Some(&auditor),
+            None,
+            Operation::Edit,
+            source(),
+            vec![],
+            || Ok(()),
+        )
+        .unwrap();
+
+        let entries = auditor.log.entries().unwrap();
+        assert_eq!(
+            entries.len(),
+            2,
+            "edits that change nothing aren't recorded"
+        );
+        assert_eq!(entries[1].operation, Operation::Abort);
+        assert_eq!(entries[1].aborts, Some(0));
+        assert_eq!(auditor.log.verify().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let log = TestLog::new("audit_tamper");
+        let device = Device::new(Model::LibraV0, "L1");
+        for actor in ["ana", "ben", "cy"] {
+            log.0
+                .append(
+                    actor,
+                    &device,
+                    Operation::Edit,
+                    source(),
+                    vec![change("config.gain", 1., 2.)],
+                )
+                .unwrap();
+        }
+        let content = fs::read_to_string(log.0.path()).unwrap();
+        fs::write(log.0.path(), content.replacen("\"ben\"", "\"eve\"", 1)).unwrap();
+        let error = log.0.verify().unwrap_err();
+        assert_eq!(error.code(), "audit_chain_broken");
+        assert!(matches!(error, Error::AuditChainBroken { sequence: 1, .. }));
+
+        let lines: Vec<_> = content.lines().collect();
+        fs::write(log.0.path(), format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(matches!(
+            log.0.verify(),
+            Err(Error::AuditChainBroken { sequence: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_query() {
+        let log = TestLog::new("audit_query");
+        let scale = Device::new(Model::LibraV0, "L1");
+        let dispenser = Device::new(Model::IchibuV2, "I1");
+        let start = OffsetDateTime::now_utc();
+        log.0
+            .append(
+                "ana",
+                &scale,
+                Operation::Edit,
+                source(),
+                vec![change("config.gain", 1., 2.)],
+            )
+            .unwrap();
+        log.0
+            .append(
+                "ben",
+                &dispenser,
+                Operation::Edit,
+                source(),
+                vec![change("setpoint.empty", 100., 90.)],
+            )
+            .unwrap();
+
+        let actors = |query: AuditQuery| -> Vec<String> {
+            log.0
+                .query(&query)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.actor)
+                .collect()
+        };
+        assert_eq!(actors(AuditQuery::new()), ["ana", "ben"]);
+        assert_eq!(actors(AuditQuery::new().device(dispenser)), ["ben"]);
+        assert_eq!(actors(AuditQuery::new().field("config")), ["ana"]);
+        assert_eq!(actors(AuditQuery::new().field("setpoint.empty")), ["ben"]);
+        assert!(actors(AuditQuery::new().field("set")).is_empty());
+        assert_eq!(actors(AuditQuery::new().actor("ana").since(start)), ["ana"]);
+        assert!(actors(AuditQuery::new().until(start)).is_empty());
+    }
+
+    #[test]
+    fn test_pins_are_redacted() {
+        let log = TestLog::new("audit_pins");
+        let device = Device::new(Model::IchibuV2, "I1");
+        let changes = vec![FieldChange {
+            path: "pins.manager".into(),
+            old: Some(json!("2744")),
+            new: Some(json!("$argon2id$...")),
+        }];
+        let entry = log
+            .0
+            .append("ana", &device, Operation::Edit, source(), changes)
+            .unwrap();
+        assert_eq!(entry.changes[0].old, Some(json!("<redacted>")));
+        assert!(!fs::read_to_string(log.0.path()).unwrap().contains("2744"));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::diff::diff;
+use crate::error::Error;
+use crate::pin::AccessLevel;
+use serde::Serialize;
+
+/// Which config fields each access level may change.
+///
+/// Fields are matched by dotted path, as reported by [`crate::diff::diff`]. A pattern ending
+/// in `.*` matches everything below that table. Higher access levels may change everything
+/// lower ones can, and fields no rule matches can't be changed by anyone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    rules: Vec<(AccessLevel, String)>,
+}
+
+/// Operators may relabel a scale, managers tune setpoints, timeouts and sampling, and sudo
+/// may calibrate scales and remap motors and I/O. Scale fields are allowed both under
+/// `config.`, as in a [`crate::libra::Libra`], and bare, as in a [`crate::libra::Config`].
+impl Default for Policy {
+    fn default() -> Self {
+        const SCALE: [(AccessLevel, &str); 10] = [
+            (AccessLevel::Operator, "ingredient"),
+            (AccessLevel::Operator, "location"),
+            (AccessLevel::Manager, "heartbeat_period.*"),
+            (AccessLevel::Manager, "buffer_length"),
+            (AccessLevel::Manager, "max_noise"),
+            (AccessLevel::Manager, "phidget_sample_period.*"),
+            (AccessLevel::Sudo, "phidget_id"),
+            (AccessLevel::Sudo, "load_cell_id"),
+            (AccessLevel::Sudo, "gain"),
+            (AccessLevel::Sudo, "offset"),
+        ];
+        let policy = Self::empty()
+            .allow(AccessLevel::Manager, "setpoint.*")
+            .allow(AccessLevel::Manager, "dispense.timeout")
+            .allow(AccessLevel::Sudo, "conveyor_motor.*")
+            .allow(AccessLevel::Sudo, "hatch.*")
+            .allow(AccessLevel::Sudo, "photo_eye.*");
+        SCALE.into_iter().fold(policy, |policy, (level, field)| {
+            policy
+                .allow(level, &format!("config.{field}"))
+                .allow(level, field)
+        })
+    }
+}
+
+impl Policy {
+    /// A policy that allows nothing.
+    pub fn empty() -> Self {
+        Self { rules: Vec::new() }
+    }
+    /// Lets `access_level`, and every level above it, change the fields matching `pattern`.
+    pub fn allow(mut self, access_level: AccessLevel, pattern: &str) -> Self {
+        self.rules.push((access_level, pattern.to_string()));
+        self
+    }
+    pub fn allows(&self, access_level: AccessLevel, path: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|(minimum, pattern)| access_level >= *minimum && matches(pattern, path))
+    }
+    /// The fields changed between `old` and `new` that `access_level` may not change.
+    pub fn denied_fields<T: Serialize + ?Sized>(
+        &self,
+        access_level: AccessLevel,
+        old: &T,
+        new: &T,
+    ) -> Result<Vec<String>, Error> {
+        Ok(diff(old, new)?
+            .into_iter()
+            .map(|change| change.path)
+            .filter(|path| !self.allows(access_level, path))
+            .collect())
+    }
+    /// Fails with `Error::Unauthorized`, listing the denied fields, if `access_level` may not
+    /// make every change between `old` and `new`.
+    pub fn authorize_edit<T: Serialize + ?Sized>(
+        &self,
+        access_level: AccessLevel,
+        old: &T,
+        new: &T,
+    ) -> Result<(), Error> {
+        let denied = self.denied_fields(access_level, old, new)?;
+        if denied.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized {
+                access_level,
+                denied,
+            })
+        }
+    }
+}
+
+/// [`Policy::authorize_edit`] with the default policy.
+pub fn authorize_edit<T: Serialize + ?Sized>(
+    access_level: AccessLevel,
+    old: &T,
+    new: &T,
+) -> Result<(), Error> {
+    Policy::default().authorize_edit(access_level, old, new)
+}
+
+fn matches(pattern: &str, path: &str) -> bool {
+    match pattern.strip_suffix(".*") {
+        Some(table) => path
+            .strip_prefix(table)
+            .is_some_and(|rest| rest.starts_with('.')),
+        None => pattern == path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ichibu::Ichibu;
+    use crate::libra::{Config, Libra};
+    use std::time::Duration;
+
+    #[test]
+    fn test_operator_may_relabel_libra() {
+        let old = Libra::default();
+        let mut new = old.clone();
+        new.config.ingredient = "Tofu".into();
+        authorize_edit(AccessLevel::Operator, &old, &new).unwrap();
+
+        new.config.gain = 2.0;
+        new.config.phidget_id = 7;
+        let error = authorize_edit(AccessLevel::Operator, &old, &new).unwrap_err();
+        assert_eq!(error.code(), "unauthorized");
+        assert!(matches!(
+            &error,
+            Error::Unauthorized { denied, .. } if denied == &["config.gain", "config.phidget_id"]
+        ));
+        assert_eq!(
+            Policy::default()
+                .denied_fields(AccessLevel::Manager, &old, &new)
+                .unwrap(),
+            ["config.gain", "config.phidget_id"]
+        );
+        authorize_edit(AccessLevel::Sudo, &old, &new).unwrap();
+    }
+
+    #[test]
+    fn test_bare_config_roles() {
+        let old = Config::default();
+        let mut new = old.clone();
+        new.ingredient = "Tofu".into();
+        new.heartbeat_period = Duration::from_secs(30);
+        assert!(authorize_edit(AccessLevel::Operator, &old, &new).is_err());
+        authorize_edit(AccessLevel::Manager, &old, &new).unwrap();
+
+        new.gain = 2.0;
+        let error = authorize_edit(AccessLevel::Manager, &old, &new).unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::Unauthorized { denied, .. } if denied == &["gain"]
+        ));
+        authorize_edit(AccessLevel::Sudo, &old, &new).unwrap();
+    }
+
+    #[test]
+    fn test_every_libra_field_is_editable() {
+        let old = Libra::default();
+        let mut new = old.clone();
+        // No `..`, so a new field fails to compile until it is listed here.
+        new.config = Config {
+            phidget_id: 1,
+            load_cell_id: 1,
+            gain: 2.0,
+            offset: 1.0,
+            location: "Line 3".into(),
+            ingredient: "Tofu".into(),
+            heartbeat_period: Duration::from_secs(30),
+            buffer_length: 10,
+            max_noise: 1.0,
+            phidget_sample_period: Duration::from_millis(100),
+        };
+        let changes = diff(&old.config, &new.config).unwrap();
+        let fields = serde_json::to_value(&old.config).unwrap();
+        let changed = |field: &String| {
+            changes
+                .iter()
+                .any(|change| change.path.split('.').next() == Some(field.as_str()))
+        };
+        assert!(fields.as_object().unwrap().keys().all(changed));
+        assert_eq!(
+            Policy::default()
+                .denied_fields(AccessLevel::Sudo, &old, &new)
+                .unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_ichibu_roles() {
+        let old = Ichibu::default();
+        let mut new = old.clone();
+        new.core_mut().setpoint_mut().set_empty(50.).unwrap();
+        new.core_mut().dispense_mut().set_timeout(10);
+        assert!(authorize_edit(AccessLevel::Operator, &old, &new).is_err());
+        authorize_edit(AccessLevel::Manager, &old, &new).unwrap();
+
+        new.hatch_mut().unwrap().motor_mut().set_id(3);
+        assert!(authorize_edit(AccessLevel::Manager, &old, &new).is_err());
+        authorize_edit(AccessLevel::Sudo, &old, &new).unwrap();
+    }
+
+    #[cfg(feature = "pins")]
+    #[test]
+    fn test_pins_need_explicit_rule() {
+        let old = Ichibu::default();
+        let mut new = old.clone();
+        new.core_mut().pins_mut().set_operator("99").unwrap();
+        assert_eq!(
+            Policy::default()
+                .denied_fields(AccessLevel::Sudo, &old, &new)
+                .unwrap(),
+            ["pins.operator"]
+        );
+        let policy = Policy::default().allow(AccessLevel::Sudo, "pins.*");
+        policy
+            .authorize_edit(AccessLevel::Sudo, &old, &new)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_pattern_matching() {
+        assert!(matches("setpoint.*", "setpoint.empty"));
+        assert!(matches("hatch.*", "hatch.motor.id"));
+        assert!(!matches("hatch.*", "hatch"));
+        assert!(!matches("hatch.*", "hatchback.id"));
+        assert!(!matches("config.gain", "config.gain_offset"));
+    }
+}
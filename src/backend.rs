@@ -1,178 +1,883 @@
+#[cfg(feature = "audit")]
+use crate::audit::Auditor;
+#[cfg(feature = "write")]
+use crate::audit::default_auditor;
+#[cfg(feature = "bundle")]
+use crate::bundle::BundleVerifier;
+use crate::token::{StaticToken, TokenProvider};
+use std::sync::Arc;
+
+#[cfg(any(feature = "write", feature = "address"))]
+pub(crate) use request::{Clients, TIMEOUT};
+
 pub const BACKEND_URL: &str = "https://us-west1-back-of-house-backend.cloudfunctions.net/mise/";
 
 #[derive(Clone)]
 pub struct ConfigBackend {
     pub path: String,
-    pub auth_token: String,
+    /// Supplies the bearer token, refreshing it as it expires.
+    pub token_provider: Arc<dyn TokenProvider>,
+    /// Records config edits made through this backend, when set.
+    #[cfg(feature = "audit")]
+    pub auditor: Option<Auditor>,
+    /// When set, configs are only accepted as bundles signed for the device by the pinned key.
+    #[cfg(feature = "bundle")]
+    pub bundle_verifier: Option<BundleVerifier>,
+    #[cfg(any(feature = "write", feature = "address"))]
+    pub(crate) clients: Arc<Clients>,
 }
 impl ConfigBackend {
     pub fn new(path: String, auth_token: String) -> Self {
-        Self { path, auth_token }
+        Self {
+            path,
+            token_provider: Arc::new(StaticToken::new(auth_token)),
+            #[cfg(feature = "audit")]
+            auditor: None,
+            #[cfg(feature = "bundle")]
+            bundle_verifier: None,
+            #[cfg(any(feature = "write", feature = "address"))]
+            clients: Arc::default(),
+        }
+    }
+    pub fn with_token_provider(mut self, mut token_provider: impl TokenProvider + 'static) -> Self {
+        token_provider.attach(&self);
+        self.token_provider = Arc::new(token_provider);
+        self
+    }
+    #[cfg(feature = "audit")]
+    pub fn with_auditor(mut self, auditor: Auditor) -> Self {
+        self.auditor = Some(auditor);
+        self
+    }
+    /// The auditor recording mutations made through this backend: its own, else the
+    /// [default auditor](crate::audit::set_default_auditor).
+    #[cfg(feature = "write")]
+    pub(crate) fn active_auditor(&self) -> Option<Auditor> {
+        self.auditor.clone().or_else(default_auditor)
+    }
+    #[cfg(feature = "bundle")]
+    pub fn with_bundle_verifier(mut self, bundle_verifier: BundleVerifier) -> Self {
+        self.bundle_verifier = Some(bundle_verifier);
+        self
+    }
+}
+
+#[cfg(any(feature = "write", feature = "address"))]
+mod request {
+    use crate::backend::ConfigBackend;
+    use crate::error::Error;
+    use reqwest::{Method, StatusCode};
+    use std::sync::OnceLock;
+    use std::time::Duration;
+
+    pub(crate) const TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// HTTP clients shared by every request a backend (and its clones) makes, so connections
+    /// are reused. Each is created on first use, which keeps the blocking client out of async
+    /// code that never needs it.
+    #[derive(Default)]
+    pub(crate) struct Clients {
+        blocking: OnceLock<reqwest::blocking::Client>,
+        #[cfg(feature = "write")]
+        non_blocking: OnceLock<reqwest::Client>,
+    }
+
+    impl Clients {
+        pub(crate) fn blocking(&self) -> &reqwest::blocking::Client {
+            self.blocking.get_or_init(reqwest::blocking::Client::new)
+        }
+        #[cfg(feature = "write")]
+        pub(crate) fn non_blocking(&self) -> &reqwest::Client {
+            self.non_blocking.get_or_init(reqwest::Client::new)
+        }
+    }
+
+    impl ConfigBackend {
+        /// Joins `segments` onto the backend's path, each percent-encoded as a single path
+        /// segment, so a serial number like `a/b` or `x?y` can't address another resource.
+        /// `.` and `..` are rejected: URL parsing resolves them even when encoded.
+        pub(crate) fn url(&self, segments: &[&str]) -> Result<String, Error> {
+            let mut url = self.path.clone();
+            for segment in segments {
+                if matches!(*segment, "" | "." | "..") {
+                    return Err(Error::validation(
+                        "url",
+                        format!("{segment:?} can't be a path segment"),
+                    ));
+                }
+                url.push('/');
+                for byte in segment.bytes() {
+                    if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                        url.push(byte as char);
+                    } else {
+                        url.push_str(&format!("%{byte:02X}"));
+                    }
+                }
+            }
+            Ok(url)
+        }
+        /// Sends an authenticated request, built by `build`, which may override the default
+        /// timeout. If the backend answers 401 and the token provider can offer a new token,
+        /// the request is retried once with it.
+        pub(crate) fn send(
+            &self,
+            method: Method,
+            url: &str,
+            build: impl Fn(reqwest::blocking::RequestBuilder) -> reqwest::blocking::RequestBuilder,
+        ) -> Result<reqwest::blocking::Response, Error> {
+            let client = self.clients.blocking();
+            let attempt = || -> Result<reqwest::blocking::Response, Error> {
+                let token = self.token_provider.token()?;
+                build(client.request(method.clone(), url).timeout(TIMEOUT))
+                    .bearer_auth(token.expose())
+                    .send()
+                    .map_err(|e| Error::reqwest(method.clone(), url, e))
+            };
+            let response = attempt()?;
+            if response.status() == StatusCode::UNAUTHORIZED && self.token_provider.invalidate() {
+                return attempt();
+            }
+            Ok(response)
+        }
+        #[cfg(feature = "write")]
+        pub(crate) async fn send_async(
+            &self,
+            method: Method,
+            url: &str,
+            build: impl Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder,
+        ) -> Result<reqwest::Response, Error> {
+            let client = self.clients.non_blocking();
+            let attempt = async || -> Result<reqwest::Response, Error> {
+                let token = self.token_provider.token_async().await?;
+                build(client.request(method.clone(), url).timeout(TIMEOUT))
+                    .bearer_auth(token.expose())
+                    .send()
+                    .await
+                    .map_err(|e| Error::reqwest(method.clone(), url, e))
+            };
+            let response = attempt().await?;
+            if response.status() == StatusCode::UNAUTHORIZED && self.token_provider.invalidate() {
+                return attempt().await;
+            }
+            Ok(response)
+        }
     }
 }
 
 #[cfg(feature = "write")]
 pub mod write {
+    use crate::audit::{Auditor, Operation, Pending, Source, begin};
     pub(crate) use crate::backend::ConfigBackend;
+    use crate::backend::TIMEOUT;
+    #[cfg(feature = "bundle")]
+    use crate::bundle::SignedBundle;
     use crate::device::{Device, Model};
+    use crate::diff::{FieldChange, diff};
     use crate::error::Error;
-    use crate::libra::Config;
-    use reqwest::StatusCode;
+    use crate::libra::{Config, ConfigPatch};
+    use futures_util::{StreamExt, stream};
+    use reqwest::header::{CONTENT_TYPE, ETAG, HeaderMap, IF_MATCH, IF_NONE_MATCH};
+    use reqwest::{Method, StatusCode};
+    use std::time::Duration;
+
+    /// A value together with the version the backend reported for it.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Versioned<T> {
+        pub value: T,
+        /// The `ETag` to send back with conditional requests, if the backend sent one.
+        pub etag: Option<String>,
+    }
+
+    const MERGE_PATCH: &str = "application/merge-patch+json";
+
+    /// RFC 7240 preference asking the backend to hold a conditional GET open.
+    const PREFER: &str = "prefer";
+
+    fn etag(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string)
+    }
+
+    /// Unwraps an unconditional fetch. Only conditional requests may come back unchanged,
+    /// so a backend that answers one with 304 anyway gets a backend error.
+    fn unconditional(
+        config: Option<Versioned<Config>>,
+        backend: &ConfigBackend,
+        device: &Device,
+    ) -> Result<Versioned<Config>, Error> {
+        match config {
+            Some(config) => Ok(config),
+            None => Err(Error::Backend {
+                method: Method::GET,
+                url: backend.url(&[device.model.route(), device.serial_number.as_str()])?,
+                status: StatusCode::NOT_MODIFIED,
+                body: String::new(),
+            }),
+        }
+    }
 
     impl ConfigBackend {
         pub fn make_new_device(&self, model: Model, config: Config) -> Result<Device, Error> {
-            let client = reqwest::blocking::Client::new();
-            let url = format!("{}/{:?}", self.path, model);
-            let response = client
-                .post(url)
-                .bearer_auth(&self.auth_token)
-                .json(&config)
-                .timeout(std::time::Duration::from_secs(60))
-                .send()
-                .map_err(Error::Reqwest)?;
+            let url = self.url(&[model.route()])?;
+            let response = self.send(Method::POST, &url, |request| request.json(&config))?;
             if response.status() == StatusCode::CREATED {
-                let device: Device = response.json().map_err(Error::Reqwest)?;
+                let device: Device = response
+                    .json()
+                    .map_err(|e| Error::reqwest(Method::POST, &url, e))?;
                 Ok(device)
             } else {
-                Err(Error::Backend(response.status()))
+                Err(Error::backend(Method::POST, &url, response))
             }
         }
         pub fn get_config(&self, device: Device) -> Result<Config, Error> {
-            let client = reqwest::blocking::Client::new();
-            let url = format!("{}/{:?}/{}", self.path, device.model, device.serial_number);
-            let response = client
-                .get(url)
-                .bearer_auth(&self.auth_token)
-                .timeout(std::time::Duration::from_secs(60))
-                .send()
-                .map_err(Error::Reqwest)?;
-            if response.status() == StatusCode::OK {
-                let config: Config = response.json().map_err(Error::Reqwest)?;
-                Ok(config)
-            } else {
-                Err(Error::Backend(response.status()))
+            Ok(self.get_versioned_config(device)?.value)
+        }
+        /// Fetches a device's config together with its current version.
+        pub fn get_versioned_config(&self, device: Device) -> Result<Versioned<Config>, Error> {
+            let config = self.fetch_config(&device, None, None)?;
+            unconditional(config, self, &device)
+        }
+        /// Fetches a device's config unless it still has version `etag`, in which case
+        /// `None` is returned without transferring the config.
+        pub fn get_config_if_changed(
+            &self,
+            device: Device,
+            etag: &str,
+        ) -> Result<Option<Versioned<Config>>, Error> {
+            self.fetch_config(&device, Some(etag), None)
+        }
+        /// GETs a device's config. With `wait`, asks the backend to hold a conditional
+        /// request open until the config changes or `wait` passes.
+        pub(crate) fn fetch_config(
+            &self,
+            device: &Device,
+            if_none_match: Option<&str>,
+            wait: Option<Duration>,
+        ) -> Result<Option<Versioned<Config>>, Error> {
+            let url = self.url(&[device.model.route(), device.serial_number.as_str()])?;
+            let response = self.send(Method::GET, &url, |mut request| {
+                if let Some(etag) = if_none_match {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(wait) = wait {
+                    // The backend holds the request open for up to `wait` before answering.
+                    request = request
+                        .header(PREFER, format!("wait={}", wait.as_secs()))
+                        .timeout(TIMEOUT + wait);
+                }
+                request
+            })?;
+            if if_none_match.is_some() && response.status() == StatusCode::NOT_MODIFIED {
+                return Ok(None);
+            }
+            if response.status() != StatusCode::OK {
+                return Err(Error::backend(Method::GET, &url, response));
             }
+            let etag = etag(response.headers());
+            #[cfg(feature = "bundle")]
+            if let Some(verifier) = &self.bundle_verifier {
+                let bundle: SignedBundle = response
+                    .json()
+                    .map_err(|e| Error::reqwest(Method::GET, &url, e))?;
+                return Ok(Some(Versioned {
+                    value: verifier.open(&bundle, device)?,
+                    etag,
+                }));
+            }
+            let config: Config = response
+                .json()
+                .map_err(|e| Error::reqwest(Method::GET, &url, e))?;
+            Ok(Some(Versioned {
+                value: config,
+                etag,
+            }))
+        }
+        /// Fetches the configs of several devices in order, one result per device.
+        pub fn get_configs(&self, devices: &[Device]) -> Vec<(Device, Result<Config, Error>)> {
+            devices
+                .iter()
+                .map(|device| (device.clone(), self.get_config(device.clone())))
+                .collect()
         }
+        /// Replaces a device's config. With an auditor set, the current config is fetched
+        /// first so the changed fields can be recorded before the new one is sent.
         pub fn edit_config(&self, device: Device, config: Config) -> Result<(), Error> {
-            let client = reqwest::blocking::Client::new();
-            let url = format!("{}/{:?}/{}", self.path, device.model, device.serial_number);
-            let response = client
-                .put(url)
-                .bearer_auth(&self.auth_token)
-                .json(&config)
-                .timeout(std::time::Duration::from_secs(60))
-                .send()
-                .map_err(Error::Reqwest)?;
-            if response.status() == StatusCode::OK {
+            self.put_config(device, config, None).map(|_| ())
+        }
+        /// Replaces a device's config only if it is still at version `etag`, returning the
+        /// new version. If someone else changed it first, fails with [`Error::Conflict`]
+        /// holding the config as it is now.
+        pub fn edit_config_if_match(
+            &self,
+            device: Device,
+            config: Config,
+            etag: &str,
+        ) -> Result<Option<String>, Error> {
+            self.put_config(device, config, Some(etag))
+        }
+        fn put_config(
+            &self,
+            device: Device,
+            config: Config,
+            if_match: Option<&str>,
+        ) -> Result<Option<String>, Error> {
+            let auditor = self.active_auditor();
+            let old = match &auditor {
+                Some(_) => Some(self.get_config(device.clone())?),
+                None => None,
+            };
+            let url = self.url(&[device.model.route(), device.serial_number.as_str()])?;
+            let pending = begin_edit(auditor.as_ref(), &device, &url, old, &config)?;
+            pending.finish((|| {
+                let response = self.send(Method::PUT, &url, |request| match if_match {
+                    Some(etag) => request.header(IF_MATCH, etag).json(&config),
+                    None => request.json(&config),
+                })?;
+                if response.status() == StatusCode::PRECONDITION_FAILED {
+                    let current = self.get_versioned_config(device.clone())?;
+                    return Err(Error::conflict(device.clone(), current));
+                }
+                if response.status() != StatusCode::OK {
+                    return Err(Error::backend(Method::PUT, &url, response));
+                }
+                Ok(etag(response.headers()))
+            })())
+        }
+        /// Changes only the fields set in `patch`, sent as a JSON Merge Patch.
+        pub fn patch_config(&self, device: Device, patch: &ConfigPatch) -> Result<(), Error> {
+            let auditor = self.active_auditor();
+            let old = match &auditor {
+                Some(_) => Some(self.get_config(device.clone())?),
+                None => None,
+            };
+            let url = self.url(&[device.model.route(), device.serial_number.as_str()])?;
+            let body = serde_json::to_string(patch).map_err(Error::JsonGeneration)?;
+            let pending = begin_patch(auditor.as_ref(), &device, &url, old, patch)?;
+            pending.finish((|| {
+                let response = self.send(Method::PATCH, &url, |request| {
+                    request.header(CONTENT_TYPE, MERGE_PATCH).body(body.clone())
+                })?;
+                if !response.status().is_success() {
+                    return Err(Error::backend(Method::PATCH, &url, response));
+                }
                 Ok(())
-            } else {
-                Err(Error::Backend(response.status()))
-            }
+            })())
         }
         pub async fn make_new_device_async(
             &self,
             model: Model,
             config: Config,
         ) -> Result<Device, Error> {
-            let client = reqwest::Client::new();
-            let url = format!("{}/{:?}", self.path, model);
-            let response = client
-                .post(url)
-                .bearer_auth(&self.auth_token)
-                .json(&config)
-                .timeout(std::time::Duration::from_secs(60))
-                .send()
-                .await
-                .map_err(Error::Reqwest)?;
+            let url = self.url(&[model.route()])?;
+            let response = self
+                .send_async(Method::POST, &url, |request| request.json(&config))
+                .await?;
             if response.status() == StatusCode::CREATED {
-                let device: Device = response.json().await.map_err(Error::Reqwest)?;
+                let device: Device = response
+                    .json()
+                    .await
+                    .map_err(|e| Error::reqwest(Method::POST, &url, e))?;
                 Ok(device)
             } else {
-                Err(Error::Backend(response.status()))
+                Err(Error::backend_async(Method::POST, &url, response).await)
             }
         }
         pub async fn get_config_async(&self, device: Device) -> Result<Config, Error> {
-            let client = reqwest::Client::new();
-            let url = format!("{}/{:?}/{}", self.path, device.model, device.serial_number);
-            let response = client
-                .get(url)
-                .bearer_auth(&self.auth_token)
-                .timeout(std::time::Duration::from_secs(60))
-                .send()
+            Ok(self.get_versioned_config_async(device).await?.value)
+        }
+        pub async fn get_versioned_config_async(
+            &self,
+            device: Device,
+        ) -> Result<Versioned<Config>, Error> {
+            let config = self.fetch_config_async(&device, None, None).await?;
+            unconditional(config, self, &device)
+        }
+        pub async fn get_config_if_changed_async(
+            &self,
+            device: Device,
+            etag: &str,
+        ) -> Result<Option<Versioned<Config>>, Error> {
+            self.fetch_config_async(&device, Some(etag), None).await
+        }
+        pub(crate) async fn fetch_config_async(
+            &self,
+            device: &Device,
+            if_none_match: Option<&str>,
+            wait: Option<Duration>,
+        ) -> Result<Option<Versioned<Config>>, Error> {
+            let url = self.url(&[device.model.route(), device.serial_number.as_str()])?;
+            let response = self
+                .send_async(Method::GET, &url, |mut request| {
+                    if let Some(etag) = if_none_match {
+                        request = request.header(IF_NONE_MATCH, etag);
+                    }
+                    if let Some(wait) = wait {
+                        // The backend holds the request open for up to `wait` before answering.
+                        request = request
+                            .header(PREFER, format!("wait={}", wait.as_secs()))
+                            .timeout(TIMEOUT + wait);
+                    }
+                    request
+                })
+                .await?;
+            if if_none_match.is_some() && response.status() == StatusCode::NOT_MODIFIED {
+                return Ok(None);
+            }
+            if response.status() != StatusCode::OK {
+                return Err(Error::backend_async(Method::GET, &url, response).await);
+            }
+            let etag = etag(response.headers());
+            #[cfg(feature = "bundle")]
+            if let Some(verifier) = &self.bundle_verifier {
+                let bundle: SignedBundle = response
+                    .json()
+                    .await
+                    .map_err(|e| Error::reqwest(Method::GET, &url, e))?;
+                return Ok(Some(Versioned {
+                    value: verifier.open(&bundle, device)?,
+                    etag,
+                }));
+            }
+            let config: Config = response
+                .json()
+                .await
+                .map_err(|e| Error::reqwest(Method::GET, &url, e))?;
+            Ok(Some(Versioned {
+                value: config,
+                etag,
+            }))
+        }
+        /// Like [`ConfigBackend::get_configs`], with up to `max_concurrent` requests in flight.
+        pub async fn get_configs_async(
+            &self,
+            devices: &[Device],
+            max_concurrent: usize,
+        ) -> Vec<(Device, Result<Config, Error>)> {
+            stream::iter(devices.iter().cloned())
+                .map(|device| async move {
+                    let result = self.get_config_async(device.clone()).await;
+                    (device, result)
+                })
+                .buffered(max_concurrent.max(1))
+                .collect()
+                .await
+        }
+        pub async fn edit_config_async(&self, device: Device, config: Config) -> Result<(), Error> {
+            self.put_config_async(device, config, None)
                 .await
-                .map_err(Error::Reqwest)?;
+                .map(|_| ())
+        }
+        pub async fn edit_config_if_match_async(
+            &self,
+            device: Device,
+            config: Config,
+            etag: &str,
+        ) -> Result<Option<String>, Error> {
+            self.put_config_async(device, config, Some(etag)).await
+        }
+        async fn put_config_async(
+            &self,
+            device: Device,
+            config: Config,
+            if_match: Option<&str>,
+        ) -> Result<Option<String>, Error> {
+            let auditor = self.active_auditor();
+            let old = match &auditor {
+                Some(_) => Some(self.get_config_async(device.clone()).await?),
+                None => None,
+            };
+            let url = self.url(&[device.model.route(), device.serial_number.as_str()])?;
+            let pending = begin_edit(auditor.as_ref(), &device, &url, old, &config)?;
+            let result = async {
+                let response = self
+                    .send_async(Method::PUT, &url, |request| match if_match {
+                        Some(etag) => request.header(IF_MATCH, etag).json(&config),
+                        None => request.json(&config),
+                    })
+                    .await?;
+                if response.status() == StatusCode::PRECONDITION_FAILED {
+                    let current = self.get_versioned_config_async(device.clone()).await?;
+                    return Err(Error::conflict(device.clone(), current));
+                }
+                if response.status() != StatusCode::OK {
+                    return Err(Error::backend_async(Method::PUT, &url, response).await);
+                }
+                Ok(etag(response.headers()))
+            };
+            pending.finish(result.await)
+        }
+        pub async fn patch_config_async(
+            &self,
+            device: Device,
+            patch: &ConfigPatch,
+        ) -> Result<(), Error> {
+            let auditor = self.active_auditor();
+            let old = match &auditor {
+                Some(_) => Some(self.get_config_async(device.clone()).await?),
+                None => None,
+            };
+            let url = self.url(&[device.model.route(), device.serial_number.as_str()])?;
+            let body = serde_json::to_string(patch).map_err(Error::JsonGeneration)?;
+            let pending = begin_patch(auditor.as_ref(), &device, &url, old, patch)?;
+            let result = async {
+                let response = self
+                    .send_async(Method::PATCH, &url, |request| {
+                        request.header(CONTENT_TYPE, MERGE_PATCH).body(body.clone())
+                    })
+                    .await?;
+                if !response.status().is_success() {
+                    return Err(Error::backend_async(Method::PATCH, &url, response).await);
+                }
+                Ok(())
+            };
+            pending.finish(result.await)
+        }
+    }
+
+    /// Records a patch with `auditor` before it is sent; see [`begin_edit`].
+    fn begin_patch<'a>(
+        auditor: Option<&'a Auditor>,
+        device: &Device,
+        url: &str,
+        old: Option<Config>,
+        patch: &ConfigPatch,
+    ) -> Result<Pending<'a>, Error> {
+        let new = old.as_ref().map(|old| {
+            let mut new = old.clone();
+            patch.apply(&mut new);
+            new
+        });
+        begin_edit(auditor, device, url, old, &new.unwrap_or_default())
+    }
+
+    /// Records an edit with `auditor` before it is sent, naming fields as they appear in a
+    /// [`Libra`]. Nothing is recorded without the old config to compare with.
+    ///
+    /// [`Libra`]: crate::libra::Libra
+    fn begin_edit<'a>(
+        auditor: Option<&'a Auditor>,
+        device: &Device,
+        url: &str,
+        old: Option<Config>,
+        new: &Config,
+    ) -> Result<Pending<'a>, Error> {
+        let Some(old) = old else {
+            return begin(None, None, Operation::Edit, backend_source(url), Vec::new());
+        };
+        let changes = diff(&old, new)?
+            .into_iter()
+            .map(|change| FieldChange {
+                path: format!("config.{}", change.path),
+                ..change
+            })
+            .collect();
+        begin(
+            auditor,
+            Some(device),
+            Operation::Edit,
+            backend_source(url),
+            changes,
+        )
+    }
+
+    pub(crate) fn backend_source(url: &str) -> Source {
+        Source::Backend {
+            url: url.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "write")]
+pub mod lifecycle {
+    use crate::audit::{Operation, begin};
+    use crate::backend::ConfigBackend;
+    use crate::backend::write::backend_source;
+    use crate::device::{Device, Model};
+    use crate::diff::FieldChange;
+    use crate::error::Error;
+    use reqwest::{Method, StatusCode};
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashSet;
+
+    /// One page of a device listing.
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    pub struct DevicePage {
+        pub devices: Vec<Device>,
+        /// Pass to the next call to continue the listing; `None` on the last page.
+        #[serde(default)]
+        pub next_page_token: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct Transfer<'a> {
+        location: &'a str,
+    }
+
+    /// Returns the token of the page after this one, or an error if the listing at `url`
+    /// already followed it, so a backend that loops ends the listing instead of hanging it.
+    fn next_page_token(
+        seen: &mut HashSet<String>,
+        url: &str,
+        page_token: Option<String>,
+    ) -> Result<Option<String>, Error> {
+        match page_token {
+            Some(page_token) if !seen.insert(page_token.clone()) => Err(Error::RepeatedPageToken {
+                url: url.to_string(),
+                page_token,
+            }),
+            page_token => Ok(page_token),
+        }
+    }
+
+    fn transfer_changes(new_location: &str) -> Vec<FieldChange> {
+        vec![FieldChange {
+            path: "config.location".into(),
+            old: None,
+            new: Some(new_location.into()),
+        }]
+    }
+
+    impl ConfigBackend {
+        /// Lists every registered device of `model`, following all pages.
+        pub fn list_devices(&self, model: Model) -> Result<Vec<Device>, Error> {
+            let url = self.url(&[model.route()])?;
+            let mut devices = Vec::new();
+            let mut seen = HashSet::new();
+            let mut page_token = None;
+            loop {
+                let page = self.list_devices_page(model.clone(), page_token.as_deref())?;
+                devices.extend(page.devices);
+                page_token = next_page_token(&mut seen, &url, page.next_page_token)?;
+                if page_token.is_none() {
+                    return Ok(devices);
+                }
+            }
+        }
+        pub fn list_devices_page(
+            &self,
+            model: Model,
+            page_token: Option<&str>,
+        ) -> Result<DevicePage, Error> {
+            let url = self.url(&[model.route()])?;
+            let response = self.send(Method::GET, &url, |request| match page_token {
+                Some(token) => request.query(&[("page_token", token)]),
+                None => request,
+            })?;
             if response.status() == StatusCode::OK {
-                let config: Config = response.json().await.map_err(Error::Reqwest)?;
-                Ok(config)
+                response
+                    .json()
+                    .map_err(|e| Error::reqwest(Method::GET, &url, e))
             } else {
-                Err(Error::Backend(response.status()))
+                Err(Error::backend(Method::GET, &url, response))
             }
         }
-        pub async fn edit_config_async(&self, device: Device, config: Config) -> Result<(), Error> {
-            let client = reqwest::Client::new();
-            let url = format!("{}/{:?}/{}", self.path, device.model, device.serial_number);
-            let response = client
-                .put(url)
-                .bearer_auth(&self.auth_token)
-                .json(&config)
-                .timeout(std::time::Duration::from_secs(60))
-                .send()
-                .await
-                .map_err(Error::Reqwest)?;
+        /// Deregisters a device and deletes its config.
+        pub fn delete_device(&self, device: Device) -> Result<(), Error> {
+            let url = self.url(&[device.model.route(), device.serial_number.as_str()])?;
+            let auditor = self.active_auditor();
+            let source = backend_source(&url);
+            let pending = begin(
+                auditor.as_ref(),
+                Some(&device),
+                Operation::Remove,
+                source,
+                vec![],
+            )?;
+            pending.finish((|| {
+                let response = self.send(Method::DELETE, &url, |request| request)?;
+                if response.status().is_success() {
+                    Ok(())
+                } else {
+                    Err(Error::backend(Method::DELETE, &url, response))
+                }
+            })())
+        }
+        /// Moves a device to another kitchen location.
+        pub fn transfer_device(&self, device: Device, new_location: &str) -> Result<(), Error> {
+            let url = self.url(&[
+                device.model.route(),
+                device.serial_number.as_str(),
+                "transfer",
+            ])?;
+            let body = Transfer {
+                location: new_location,
+            };
+            let auditor = self.active_auditor();
+            let pending = begin(
+                auditor.as_ref(),
+                Some(&device),
+                Operation::Transfer,
+                backend_source(&url),
+                transfer_changes(new_location),
+            )?;
+            pending.finish((|| {
+                let response = self.send(Method::POST, &url, |request| request.json(&body))?;
+                if response.status() == StatusCode::OK {
+                    Ok(())
+                } else {
+                    Err(Error::backend(Method::POST, &url, response))
+                }
+            })())
+        }
+        pub async fn list_devices_async(&self, model: Model) -> Result<Vec<Device>, Error> {
+            let url = self.url(&[model.route()])?;
+            let mut devices = Vec::new();
+            let mut seen = HashSet::new();
+            let mut page_token = None;
+            loop {
+                let page = self
+                    .list_devices_page_async(model.clone(), page_token.as_deref())
+                    .await?;
+                devices.extend(page.devices);
+                page_token = next_page_token(&mut seen, &url, page.next_page_token)?;
+                if page_token.is_none() {
+                    return Ok(devices);
+                }
+            }
+        }
+        pub async fn list_devices_page_async(
+            &self,
+            model: Model,
+            page_token: Option<&str>,
+        ) -> Result<DevicePage, Error> {
+            let url = self.url(&[model.route()])?;
+            let response = self
+                .send_async(Method::GET, &url, |request| match page_token {
+                    Some(token) => request.query(&[("page_token", token)]),
+                    None => request,
+                })
+                .await?;
             if response.status() == StatusCode::OK {
-                Ok(())
+                response
+                    .json()
+                    .await
+                    .map_err(|e| Error::reqwest(Method::GET, &url, e))
             } else {
-                Err(Error::Backend(response.status()))
+                Err(Error::backend_async(Method::GET, &url, response).await)
             }
         }
+        pub async fn delete_device_async(&self, device: Device) -> Result<(), Error> {
+            let url = self.url(&[device.model.route(), device.serial_number.as_str()])?;
+            let auditor = self.active_auditor();
+            let source = backend_source(&url);
+            let pending = begin(
+                auditor.as_ref(),
+                Some(&device),
+                Operation::Remove,
+                source,
+                vec![],
+            )?;
+            let result = async {
+                let response = self
+                    .send_async(Method::DELETE, &url, |request| request)
+                    .await?;
+                if response.status().is_success() {
+                    Ok(())
+                } else {
+                    Err(Error::backend_async(Method::DELETE, &url, response).await)
+                }
+            };
+            pending.finish(result.await)
+        }
+        pub async fn transfer_device_async(
+            &self,
+            device: Device,
+            new_location: &str,
+        ) -> Result<(), Error> {
+            let url = self.url(&[
+                device.model.route(),
+                device.serial_number.as_str(),
+                "transfer",
+            ])?;
+            let body = Transfer {
+                location: new_location,
+            };
+            let auditor = self.active_auditor();
+            let pending = begin(
+                auditor.as_ref(),
+                Some(&device),
+                Operation::Transfer,
+                backend_source(&url),
+                transfer_changes(new_location),
+            )?;
+            let result = async {
+                let response = self
+                    .send_async(Method::POST, &url, |request| request.json(&body))
+                    .await?;
+                if response.status() == StatusCode::OK {
+                    Ok(())
+                } else {
+                    Err(Error::backend_async(Method::POST, &url, response).await)
+                }
+            };
+            pending.finish(result.await)
+        }
     }
 }
 
 #[cfg(feature = "address")]
 pub mod address {
+    use crate::address::{Address, AddressBook};
     use crate::backend::ConfigBackend;
     use crate::device::Device;
     use crate::error::Error;
-    use reqwest::StatusCode;
+    use reqwest::{Method, StatusCode};
     use serde::{Deserialize, Serialize};
 
     impl ConfigBackend {
-        pub fn get_address(&self, device: Device) -> Result<String, Error> {
-            let client = reqwest::blocking::Client::new();
-            let url = format!(
-                "{}/{}/{:?}/{}",
-                self.path, "address", device.model, device.serial_number
-            );
-            let response = client
-                .get(url)
-                .bearer_auth(&self.auth_token)
-                .timeout(std::time::Duration::from_secs(60))
-                .send()
-                .map_err(Error::Reqwest)?;
+        pub fn get_address(&self, device: Device) -> Result<Address, Error> {
+            let url = self.url(&[
+                "address",
+                device.model.route(),
+                device.serial_number.as_str(),
+            ])?;
+            let response = self.send(Method::GET, &url, |request| request)?;
             if response.status() == StatusCode::OK {
-                let response: AddressResponse = response.json().map_err(Error::Reqwest)?;
-                Ok(response.address)
+                let response: AddressResponse = response
+                    .json()
+                    .map_err(|e| Error::reqwest(Method::GET, &url, e))?;
+                Ok(response.address.parse()?)
             } else {
-                Err(Error::Backend(response.status()))
+                Err(Error::backend(Method::GET, &url, response))
             }
         }
-        pub fn put_address(&self, device: Device, address: String) -> Result<(), Error> {
-            let client = reqwest::blocking::Client::new();
-            let url = format!(
-                "{}/{}/{:?}/{}",
-                self.path, "address", device.model, device.serial_number
-            );
-            let response = client
-                .put(url)
-                .json(&AddressResponse { address })
-                .bearer_auth(&self.auth_token)
-                .timeout(std::time::Duration::from_secs(60))
-                .send()
-                .map_err(Error::Reqwest)?;
+        pub fn put_address(&self, device: Device, address: &Address) -> Result<(), Error> {
+            let url = self.url(&[
+                "address",
+                device.model.route(),
+                device.serial_number.as_str(),
+            ])?;
+            let body = AddressResponse {
+                address: address.to_string(),
+            };
+            let response = self.send(Method::PUT, &url, |request| request.json(&body))?;
             if response.status() == StatusCode::OK {
                 Ok(())
             } else {
-                Err(Error::Backend(response.status()))
+                Err(Error::backend(Method::PUT, &url, response))
+            }
+        }
+        /// Asks the backend where `device` is and records the answer in `book`. If the
+        /// backend can't be reached or fails, falls back to where `book` last saw it.
+        pub fn resolve_address(
+            &self,
+            device: Device,
+            book: &AddressBook,
+        ) -> Result<Address, Error> {
+            match self.get_address(device.clone()) {
+                Ok(address) => {
+                    book.record(&device, &address)?;
+                    Ok(address)
+                }
+                Err(error @ (Error::Reqwest { .. } | Error::Backend { .. })) => {
+                    match book.lookup(&device)? {
+                        Some(record) => Ok(record.address),
+                        None => Err(error),
+                    }
+                }
+                Err(error) => Err(error),
             }
         }
     }
@@ -180,15 +885,78 @@ pub mod address {
     struct AddressResponse {
         address: String,
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::device::Model;
+
+        #[test]
+        fn test_resolve_address_falls_back_to_book() {
+            let mut server = mockito::Server::new();
+            let device = Device::new(Model::LibraV0, "L1");
+            let path = std::env::temp_dir().join("menu-test-resolve-address.json");
+            let _ = std::fs::remove_file(&path);
+            let book = AddressBook::new(&path);
+            let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+            server
+                .mock("GET", "/address/LibraV0/L1")
+                .with_body(r#"{"address":"libra-L1.local:8080"}"#)
+                .expect(1)
+                .create();
+            let outage = server
+                .mock("GET", "/address/LibraV0/L1")
+                .with_status(503)
+                .expect(1)
+                .create();
+
+            let address = backend.resolve_address(device.clone(), &book).unwrap();
+            assert!(address.is_mdns());
+            let fallback = backend.resolve_address(device.clone(), &book);
+            let history = book.address_history(&device).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            outage.assert();
+            assert_eq!(fallback.unwrap(), address);
+            assert_eq!(history.len(), 1);
+        }
+
+        #[test]
+        fn test_get_address_rejects_invalid() {
+            let mut server = mockito::Server::new();
+            server
+                .mock("GET", "/address/LibraV0/L1")
+                .with_body(r#"{"address":"not an address"}"#)
+                .create();
+            let put = server
+                .mock("PUT", "/address/LibraV0/L1")
+                .match_body(mockito::Matcher::Json(
+                    serde_json::json!({"address": "[::1]:80"}),
+                ))
+                .create();
+            let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+            let device = Device::new(Model::LibraV0, "L1");
+            let error = backend.get_address(device.clone()).unwrap_err();
+            assert_eq!(error.code(), "address");
+            backend
+                .put_address(device, &"[::1]:80".parse().unwrap())
+                .unwrap();
+            put.assert();
+        }
+    }
 }
 
 #[cfg(feature = "write")]
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::audit::{AuditLog, Operation, Source};
+    #[cfg(feature = "bundle")]
+    use crate::bundle::{BundleError, BundleSigner};
     use crate::device::{Device, Model};
     use crate::error::Error;
-    use crate::libra::Config;
+    use crate::libra::{Config, ConfigPatch, Libra};
+    use crate::read::Read;
+    use crate::token::ClientCredentials;
     use mockito;
     use reqwest::StatusCode;
     use serde_json;
@@ -244,7 +1012,7 @@ mod tests {
         mock.assert();
         assert!(result.is_err());
         match result.unwrap_err() {
-            Error::Backend(status) => assert_eq!(status, StatusCode::BAD_REQUEST),
+            Error::Backend { status, .. } => assert_eq!(status, StatusCode::BAD_REQUEST),
             other_err => panic!("Expected Error::Backend, got {:?}", other_err),
         }
     }
@@ -298,7 +1066,44 @@ mod tests {
         mock.assert();
         assert!(result.is_err());
         match result.unwrap_err() {
-            Error::Backend(status) => assert_eq!(status, StatusCode::NOT_FOUND),
+            Error::Backend { status, .. } => assert_eq!(status, StatusCode::NOT_FOUND),
+            other_err => panic!("Expected Error::Backend, got {:?}", other_err),
+        }
+    }
+
+    #[test]
+    fn test_config_backend_error_context() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+
+        let mock = server
+            .mock(
+                "GET",
+                &format!("/{:?}/{}", device.model, device.serial_number)[..],
+            )
+            .with_status(403)
+            .with_body("token expired")
+            .create();
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        let result = backend.get_config(device);
+
+        mock.assert();
+        let error = result.unwrap_err();
+        assert_eq!(error.code(), "backend");
+        match error {
+            Error::Backend {
+                method,
+                url: request_url,
+                status,
+                body,
+            } => {
+                assert_eq!(method, reqwest::Method::GET);
+                assert_eq!(request_url, format!("{url}/LibraV0/L1"));
+                assert_eq!(status, StatusCode::FORBIDDEN);
+                assert_eq!(body, "token expired");
+            }
             other_err => panic!("Expected Error::Backend, got {:?}", other_err),
         }
     }
@@ -330,6 +1135,484 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_config_backend_edit_config_audited() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        let old_config = Config::default();
+        let mut config = old_config.clone();
+        config.ingredient = "Tofu".into();
+        let path = format!("/{}/{}", device.model.route(), device.serial_number);
+
+        let get_mock = server
+            .mock("GET", &path[..])
+            .with_status(200)
+            .with_body(serde_json::to_string(&old_config).unwrap())
+            .create();
+        let put_mock = server.mock("PUT", &path[..]).with_status(200).create();
+
+        let mut log_path = std::env::temp_dir();
+        log_path.push("menu-test-backend_audit.audit.jsonl");
+        let _ = std::fs::remove_file(&log_path);
+        let auditor = Auditor::new(AuditLog::new(&log_path), "ana");
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string())
+            .with_auditor(auditor.clone());
+        backend.edit_config(device.clone(), config).unwrap();
+
+        get_mock.assert();
+        put_mock.assert();
+        let entries = auditor.log.entries().unwrap();
+        let _ = std::fs::remove_file(&log_path);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].device.as_ref(), Some(&device));
+        assert_eq!(entries[0].changes[0].path, "config.ingredient");
+        assert_eq!(
+            entries[0].source,
+            Source::Backend {
+                url: format!("{url}{path}")
+            }
+        );
+    }
+
+    #[test]
+    fn test_config_backend_audits_before_sending() {
+        let mut server = mockito::Server::new();
+        let device = Device::new(Model::LibraV0, "L1");
+        server
+            .mock("GET", "/LibraV0/L1")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .create();
+        let put_mock = server
+            .mock("PUT", "/LibraV0/L1")
+            .with_status(500)
+            .expect(1)
+            .create();
+        let config = Config {
+            gain: 2.0,
+            ..Config::default()
+        };
+
+        // A log that can't be written stops the edit before it is sent.
+        let unwritable = Auditor::new(AuditLog::new(std::env::temp_dir()), "ana");
+        let backend =
+            ConfigBackend::new(server.url(), "test-token".to_string()).with_auditor(unwritable);
+        let error = backend
+            .edit_config(device.clone(), config.clone())
+            .unwrap_err();
+        assert_eq!(error.code(), "file_system");
+
+        let log_path = std::env::temp_dir().join("menu-test-backend_abort.audit.jsonl");
+        let _ = std::fs::remove_file(&log_path);
+        let auditor = Auditor::new(AuditLog::new(&log_path), "ana");
+        let backend = backend.with_auditor(auditor.clone());
+        backend.edit_config(device.clone(), config).unwrap_err();
+        backend.transfer_device(device, "Kitchen 2").unwrap_err();
+
+        put_mock.assert();
+        let entries = auditor.log.entries().unwrap();
+        let _ = std::fs::remove_file(&log_path);
+        let operations: Vec<_> = entries.iter().map(|entry| entry.operation).collect();
+        assert_eq!(
+            operations,
+            [
+                Operation::Edit,
+                Operation::Abort,
+                Operation::Transfer,
+                Operation::Abort
+            ]
+        );
+        assert_eq!(entries[2].changes[0].path, "config.location");
+        assert_eq!(entries[3].aborts, Some(2));
+    }
+
+    #[cfg(feature = "bundle")]
+    #[test]
+    fn test_config_backend_get_signed_config() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        let config = Config {
+            gain: 4.0,
+            ..Config::default()
+        };
+        let signer = BundleSigner::generate();
+        let expires_at = time::OffsetDateTime::now_utc() + time::Duration::hours(1);
+        let path = format!("/{}/{}", device.model.route(), device.serial_number);
+
+        let bundle = signer.sign(&device, expires_at, &config).unwrap();
+        let mock = server
+            .mock("GET", &path[..])
+            .with_status(200)
+            .with_body(serde_json::to_string(&bundle).unwrap())
+            .create();
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string())
+            .with_bundle_verifier(signer.verifier());
+        assert_eq!(backend.get_config(device.clone()).unwrap(), config);
+        mock.assert();
+
+        let forged = BundleSigner::generate()
+            .sign(&device, expires_at, &config)
+            .unwrap();
+        server
+            .mock("GET", &path[..])
+            .with_status(200)
+            .with_body(serde_json::to_string(&forged).unwrap())
+            .create();
+        assert!(matches!(
+            backend.get_config(device.clone()),
+            Err(Error::Bundle(BundleError::BadSignature))
+        ));
+
+        server
+            .mock("GET", &path[..])
+            .with_status(200)
+            .with_body(serde_json::to_string(&config).unwrap())
+            .create();
+        assert_eq!(
+            backend.get_config(device).unwrap_err().code(),
+            "backend_unreachable",
+            "unsigned configs are rejected"
+        );
+    }
+
+    #[test]
+    fn test_config_backend_refreshes_rejected_token() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        let path = format!("/{}/{}", device.model.route(), device.serial_number);
+
+        let first_token = server
+            .mock("POST", "/oauth/token")
+            .with_body(r#"{"access_token":"revoked","expires_in":3600}"#)
+            .expect(1)
+            .create();
+        let second_token = server
+            .mock("POST", "/oauth/token")
+            .with_body(r#"{"access_token":"fresh","expires_in":3600}"#)
+            .expect(1)
+            .create();
+        let rejected = server
+            .mock("GET", &path[..])
+            .match_header("authorization", "Bearer revoked")
+            .with_status(401)
+            .create();
+        let accepted = server
+            .mock("GET", &path[..])
+            .match_header("authorization", "Bearer fresh")
+            .with_status(200)
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .expect(2)
+            .create();
+
+        let backend = ConfigBackend::new(url.to_string(), String::new()).with_token_provider(
+            ClientCredentials::new(format!("{url}/oauth/token"), "scale", "shh"),
+        );
+        assert_eq!(
+            backend.get_config(device.clone()).unwrap(),
+            Config::default()
+        );
+        assert_eq!(backend.get_config(device).unwrap(), Config::default());
+
+        first_token.assert();
+        second_token.assert();
+        rejected.assert();
+        accepted.assert();
+    }
+
+    #[test]
+    fn test_config_backend_static_token_is_not_retried() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        let path = format!("/{}/{}", device.model.route(), device.serial_number);
+
+        let mock = server
+            .mock("GET", &path[..])
+            .with_status(401)
+            .expect(1)
+            .create();
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        let error = backend.get_config(device).unwrap_err();
+
+        mock.assert();
+        assert!(
+            matches!(error, Error::Backend { status, .. } if status == StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[test]
+    fn test_config_backend_get_configs_to_file() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let devices: Vec<Device> = ["L1", "L2", "L3"]
+            .into_iter()
+            .map(|serial_number| Device::new(Model::LibraV0, serial_number))
+            .collect();
+        let mut config = Config::default();
+        for device in [&devices[0], &devices[2]] {
+            config.location = device.serial_number.to_string();
+            server
+                .mock(
+                    "GET",
+                    &*format!("/{}/{}", device.model.route(), device.serial_number),
+                )
+                .with_body(serde_json::to_string(&config).unwrap())
+                .create();
+        }
+        server.mock("GET", "/LibraV0/L2").with_status(404).create();
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        let results = backend.get_configs(&devices);
+        assert_eq!(
+            results.iter().map(|(device, _)| device).collect::<Vec<_>>(),
+            devices.iter().collect::<Vec<_>>()
+        );
+
+        let path = std::env::temp_dir().join("menu-test-get_configs.toml");
+        let _ = std::fs::remove_file(&path);
+        let failures = Libra::new_config_file_from_results(results, &path).unwrap();
+        let libras = Libra::read_as_vec(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, devices[1]);
+        assert_eq!(failures[0].1.code(), "backend");
+        assert_eq!(
+            libras
+                .iter()
+                .map(|libra| libra.config.location.as_str())
+                .collect::<Vec<_>>(),
+            ["L1", "L3"]
+        );
+    }
+
+    #[test]
+    fn test_config_backend_list_devices_stops_on_repeated_page_token() {
+        let mut server = mockito::Server::new();
+        let looping = server
+            .mock("GET", "/LibraV0")
+            .match_query(mockito::Matcher::Any)
+            .with_body(
+                r#"{"devices":[{"model":"LibraV0","serial_number":"L1"}],"next_page_token":"p2"}"#,
+            )
+            .expect(2)
+            .create();
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let error = backend.list_devices(Model::LibraV0).unwrap_err();
+
+        looping.assert();
+        assert!(matches!(
+            error,
+            Error::RepeatedPageToken { ref page_token, .. } if page_token == "p2"
+        ));
+    }
+
+    #[test]
+    fn test_config_backend_list_devices_follows_pages() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let first = server
+            .mock("GET", "/LibraV0")
+            .match_query(mockito::Matcher::Missing)
+            .with_body(
+                r#"{"devices":[{"model":"LibraV0","serial_number":"L1"}],"next_page_token":"p2"}"#,
+            )
+            .create();
+        let second = server
+            .mock("GET", "/LibraV0")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "page_token".into(),
+                "p2".into(),
+            ))
+            .with_body(r#"{"devices":[{"model":"LibraV0","serial_number":"L2"}]}"#)
+            .create();
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        let devices = backend.list_devices(Model::LibraV0).unwrap();
+
+        first.assert();
+        second.assert();
+        assert_eq!(
+            devices,
+            [
+                Device::new(Model::LibraV0, "L1"),
+                Device::new(Model::LibraV0, "L2")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_backend_delete_and_transfer_device() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        let delete = server
+            .mock("DELETE", "/LibraV0/L1")
+            .match_header("authorization", "Bearer test-token")
+            .with_status(204)
+            .create();
+        let transfer = server
+            .mock("POST", "/LibraV0/L1/transfer")
+            .match_body(mockito::Matcher::Json(
+                serde_json::json!({ "location": "Kitchen 2" }),
+            ))
+            .with_status(200)
+            .create();
+        server
+            .mock("POST", "/LibraV0/L2/transfer")
+            .with_status(404)
+            .create();
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        backend.delete_device(device.clone()).unwrap();
+        backend.transfer_device(device, "Kitchen 2").unwrap();
+        let error = backend
+            .transfer_device(Device::new(Model::LibraV0, "L2"), "Kitchen 2")
+            .unwrap_err();
+
+        delete.assert();
+        transfer.assert();
+        assert!(matches!(error, Error::Backend { status, .. } if status == StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn test_config_backend_conditional_get() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        server
+            .mock("GET", "/LibraV0/L1")
+            .with_header("etag", "\"v1\"")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .create();
+        let unchanged = server
+            .mock("GET", "/LibraV0/L1")
+            .match_header("if-none-match", "\"v1\"")
+            .with_status(304)
+            .create();
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        let versioned = backend.get_versioned_config(device.clone()).unwrap();
+        assert_eq!(versioned.value, Config::default());
+        assert_eq!(versioned.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(
+            backend.get_config_if_changed(device, "\"v1\"").unwrap(),
+            None
+        );
+        unchanged.assert();
+    }
+
+    #[test]
+    fn test_config_backend_encodes_serial_numbers() {
+        let mut server = mockito::Server::new();
+        let device = Device::new(Model::LibraV0, "a/b c?%");
+        let mock = server
+            .mock("GET", "/LibraV0/a%2Fb%20c%3F%25")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .create();
+        let dots = server.mock("DELETE", "/").expect(0).create();
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        assert_eq!(backend.get_config(device).unwrap(), Config::default());
+        let error = backend
+            .delete_device(Device::new(Model::LibraV0, ".."))
+            .unwrap_err();
+        assert!(matches!(error, Error::Validation { field: "url", .. }));
+        mock.assert();
+        dots.assert();
+    }
+
+    #[test]
+    fn test_config_backend_unrequested_not_modified() {
+        let mut server = mockito::Server::new();
+        server.mock("GET", "/LibraV0/L1").with_status(304).create();
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let error = backend
+            .get_versioned_config(Device::new(Model::LibraV0, "L1"))
+            .unwrap_err();
+        assert!(
+            matches!(error, Error::Backend { status, .. } if status == StatusCode::NOT_MODIFIED)
+        );
+    }
+
+    #[test]
+    fn test_config_backend_edit_config_if_match() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        let theirs = Config {
+            ingredient: "Tofu".into(),
+            ..Config::default()
+        };
+        let accepted = server
+            .mock("PUT", "/LibraV0/L1")
+            .match_header("if-match", "\"v1\"")
+            .with_header("etag", "\"v2\"")
+            .create();
+        let stale = server
+            .mock("PUT", "/LibraV0/L1")
+            .match_header("if-match", "\"v0\"")
+            .with_status(412)
+            .create();
+        let current = server
+            .mock("GET", "/LibraV0/L1")
+            .with_header("etag", "\"v2\"")
+            .with_body(serde_json::to_string(&theirs).unwrap())
+            .create();
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        let etag = backend
+            .edit_config_if_match(device.clone(), Config::default(), "\"v1\"")
+            .unwrap();
+        assert_eq!(etag.as_deref(), Some("\"v2\""));
+
+        let error = backend
+            .edit_config_if_match(device.clone(), Config::default(), "\"v0\"")
+            .unwrap_err();
+        assert_eq!(error.code(), "conflict");
+        match error {
+            Error::Conflict {
+                device: conflicted,
+                current,
+            } => {
+                assert_eq!(conflicted, device);
+                assert_eq!(current.value, theirs);
+                assert_eq!(current.etag.as_deref(), Some("\"v2\""));
+            }
+            other => panic!("Expected Error::Conflict, got {other:?}"),
+        }
+        accepted.assert();
+        stale.assert();
+        current.assert();
+    }
+
+    #[test]
+    fn test_config_backend_patch_config() {
+        let mut server = mockito::Server::new();
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        let mock = server
+            .mock("PATCH", "/LibraV0/L1")
+            .match_header("content-type", "application/merge-patch+json")
+            .match_body(mockito::Matcher::JsonString(
+                r#"{"ingredient":"Tofu"}"#.to_string(),
+            ))
+            .with_status(204)
+            .create();
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        let patch = ConfigPatch {
+            ingredient: Some("Tofu".into()),
+            ..ConfigPatch::default()
+        };
+        backend.patch_config(device, &patch).unwrap();
+        mock.assert();
+    }
+
     #[test]
     fn test_config_backend_edit_config_error() {
         let mut server = mockito::Server::new();
@@ -356,7 +1639,7 @@ mod tests {
         mock.assert();
         assert!(result.is_err());
         match result.unwrap_err() {
-            Error::Backend(status) => assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR),
+            Error::Backend { status, .. } => assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR),
             other_err => panic!("Expected Error::Backend, got {:?}", other_err),
         }
     }
@@ -414,7 +1697,7 @@ mod tests {
         mock.assert_async().await;
         assert!(result.is_err());
         match result.unwrap_err() {
-            Error::Backend(status) => assert_eq!(status, StatusCode::BAD_REQUEST),
+            Error::Backend { status, .. } => assert_eq!(status, StatusCode::BAD_REQUEST),
             other_err => panic!("Expected Error::Backend, got {:?}", other_err),
         }
     }
@@ -470,7 +1753,7 @@ mod tests {
         mock.assert_async().await;
         assert!(result.is_err());
         match result.unwrap_err() {
-            Error::Backend(status) => assert_eq!(status, StatusCode::NOT_FOUND),
+            Error::Backend { status, .. } => assert_eq!(status, StatusCode::NOT_FOUND),
             other_err => panic!("Expected Error::Backend, got {:?}", other_err),
         }
     }
@@ -503,6 +1786,166 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_config_backend_refreshes_expiring_token_async() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        let path = format!("/{}/{}", device.model.route(), device.serial_number);
+
+        // Expiring within the refresh margin, so every request fetches a new token.
+        let token = server
+            .mock("POST", "/oauth/token")
+            .with_body(r#"{"access_token":"short","expires_in":5}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let mock = server
+            .mock("GET", &path[..])
+            .match_header("authorization", "Bearer short")
+            .with_status(200)
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .expect(2)
+            .create_async()
+            .await;
+
+        let backend = ConfigBackend::new(url.to_string(), String::new()).with_token_provider(
+            ClientCredentials::new(format!("{url}/oauth/token"), "scale", "shh"),
+        );
+        backend.get_config_async(device.clone()).await.unwrap();
+        backend.get_config_async(device).await.unwrap();
+
+        token.assert_async().await;
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_config_backend_get_configs_async() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+        let devices: Vec<Device> = (0..12)
+            .map(|i| Device::new(Model::LibraV0, &format!("L{i}")))
+            .collect();
+        let mock = server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/LibraV0/L\d+$".to_string()),
+            )
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .expect(12)
+            .create_async()
+            .await;
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        let results = backend.get_configs_async(&devices, 4).await;
+
+        mock.assert_async().await;
+        assert_eq!(results.len(), 12);
+        for ((device, result), expected) in results.iter().zip(&devices) {
+            assert_eq!(device, expected);
+            assert_eq!(result.as_ref().unwrap(), &Config::default());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_config_backend_device_lifecycle_async() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+        let device = Device::new(Model::IchibuV2, "I1");
+        let list = server
+            .mock("GET", "/IchibuV2")
+            .with_body(
+                r#"{"devices":[{"model":"IchibuV2","serial_number":"I1"}],"next_page_token":null}"#,
+            )
+            .create_async()
+            .await;
+        let transfer = server
+            .mock("POST", "/IchibuV2/I1/transfer")
+            .match_body(mockito::Matcher::Json(
+                serde_json::json!({ "location": "Line 3" }),
+            ))
+            .create_async()
+            .await;
+        let delete = server.mock("DELETE", "/IchibuV2/I1").create_async().await;
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        assert_eq!(
+            backend.list_devices_async(Model::IchibuV2).await.unwrap(),
+            std::slice::from_ref(&device)
+        );
+        backend
+            .transfer_device_async(device.clone(), "Line 3")
+            .await
+            .unwrap();
+        backend.delete_device_async(device).await.unwrap();
+
+        list.assert_async().await;
+        transfer.assert_async().await;
+        delete.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_config_backend_edit_config_if_match_async_conflict() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        server
+            .mock("PUT", "/LibraV0/L1")
+            .with_status(412)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/LibraV0/L1")
+            .with_header("etag", "\"v3\"")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .create_async()
+            .await;
+
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string());
+        let error = backend
+            .edit_config_if_match_async(device, Config::default(), "\"v1\"")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, Error::Conflict { current, .. } if current.etag.as_deref() == Some("\"v3\""))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_backend_patch_config_async_audited() {
+        let mut server = mockito::Server::new_async().await;
+        let url = server.url();
+        let device = Device::new(Model::LibraV0, "L1");
+        server
+            .mock("GET", "/LibraV0/L1")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .create_async()
+            .await;
+        let mock = server
+            .mock("PATCH", "/LibraV0/L1")
+            .match_body(mockito::Matcher::JsonString(r#"{"gain":2.0}"#.to_string()))
+            .create_async()
+            .await;
+
+        let log_path = std::env::temp_dir().join("menu-test-backend_patch.audit.jsonl");
+        let _ = std::fs::remove_file(&log_path);
+        let auditor = Auditor::new(AuditLog::new(&log_path), "ana");
+        let backend = ConfigBackend::new(url.to_string(), "test-token".to_string())
+            .with_auditor(auditor.clone());
+        let patch = ConfigPatch {
+            gain: Some(2.0),
+            ..ConfigPatch::default()
+        };
+        backend.patch_config_async(device, &patch).await.unwrap();
+
+        mock.assert_async().await;
+        let entries = auditor.log.entries().unwrap();
+        let _ = std::fs::remove_file(&log_path);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].changes.len(), 1);
+        assert_eq!(entries[0].changes[0].path, "config.gain");
+    }
+
     #[tokio::test]
     async fn test_config_backend_edit_config_async_error() {
         let mut server = mockito::Server::new_async().await;
@@ -530,7 +1973,7 @@ mod tests {
         mock.assert_async().await;
         assert!(result.is_err());
         match result.unwrap_err() {
-            Error::Backend(status) => assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR),
+            Error::Backend { status, .. } => assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR),
             other_err => panic!("Expected Error::Backend, got {:?}", other_err),
         }
     }
@@ -0,0 +1,52 @@
+//! Manages the key for encrypted config values.
+//!
+//! ```text
+//! menu-secret keygen <key file>
+//! echo -n 2744 | menu-secret encrypt <key file>
+//! ```
+use menu::error::Error;
+use menu::secret::SecretKey;
+use std::io::Read;
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage:
+  menu-secret keygen <key file>
+  menu-secret encrypt <key file>   (reads the value from stdin)";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["keygen", key_file] => {
+            let key_file = Path::new(key_file);
+            if key_file.exists() {
+                return Err(Error::FileExists(key_file.to_path_buf()).to_string());
+            }
+            SecretKey::generate()
+                .save(key_file)
+                .map_err(|e| e.to_string())
+        }
+        ["encrypt", key_file] => {
+            let key = SecretKey::from_file(Path::new(key_file)).map_err(|e| e.to_string())?;
+            // Read from stdin so the value stays out of shell history and process listings.
+            let mut value = String::new();
+            std::io::stdin()
+                .read_to_string(&mut value)
+                .map_err(|e| format!("Couldn't read stdin: {e}"))?;
+            println!("{}", key.encrypt(value.trim_end_matches(['\r', '\n'])));
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
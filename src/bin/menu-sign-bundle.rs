@@ -0,0 +1,109 @@
+//! Produces signed config bundles for provisioning.
+//!
+//! ```text
+//! menu-sign-bundle keygen <key file>
+//! menu-sign-bundle public-key <key file>
+//! menu-sign-bundle sign <key file> <device> <config file> [--expires-in 24h] [--out <bundle file>]
+//! ```
+use menu::bundle::BundleSigner;
+use menu::device::Device;
+use menu::error::Error;
+use menu::format::Format;
+use std::path::Path;
+use std::process::ExitCode;
+use time::{Duration, OffsetDateTime};
+
+const USAGE: &str = "Usage:
+  menu-sign-bundle keygen <key file>
+  menu-sign-bundle public-key <key file>
+  menu-sign-bundle sign <key file> <device> <config file> [--expires-in 24h] [--out <bundle file>]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["keygen", key_file] => {
+            let key_file = Path::new(key_file);
+            if key_file.exists() {
+                return Err(Error::FileExists(key_file.to_path_buf()).to_string());
+            }
+            let signer = BundleSigner::generate();
+            signer.save(key_file).map_err(|e| e.to_string())?;
+            println!("{}", signer.verifier().to_base64());
+            Ok(())
+        }
+        ["public-key", key_file] => {
+            let signer = BundleSigner::from_file(Path::new(key_file)).map_err(|e| e.to_string())?;
+            println!("{}", signer.verifier().to_base64());
+            Ok(())
+        }
+        ["sign", key_file, device, config_file, options @ ..] => {
+            let signer = BundleSigner::from_file(Path::new(key_file)).map_err(|e| e.to_string())?;
+            let device: Device = device.parse().map_err(|e| format!("Invalid device: {e}"))?;
+            let config_file = Path::new(config_file);
+            let content = std::fs::read_to_string(config_file)
+                .map_err(|e| format!("Couldn't read {}: {e}", config_file.display()))?;
+            let config: serde_json::Value = Format::from_path(config_file)
+                .deserialize(&content)
+                .map_err(|e| e.to_string())?;
+
+            let mut expires_in = Duration::hours(24);
+            let mut out = None;
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                let value = options
+                    .next()
+                    .ok_or_else(|| format!("{option} needs a value\n{USAGE}"))?;
+                match *option {
+                    "--expires-in" => expires_in = parse_duration(value)?,
+                    "--out" => out = Some(Path::new(value)),
+                    _ => return Err(format!("Unknown option {option}\n{USAGE}")),
+                }
+            }
+
+            let expires_at = OffsetDateTime::now_utc()
+                .checked_add(expires_in)
+                .ok_or_else(|| format!("--expires-in is too far in the future\n{USAGE}"))?;
+            let bundle = signer
+                .sign(&device, expires_at, &config)
+                .map_err(|e| e.to_string())?;
+            let bundle = serde_json::to_string_pretty(&bundle).map_err(|e| e.to_string())?;
+            match out {
+                Some(out) => std::fs::write(out, bundle + "\n")
+                    .map_err(|e| format!("Couldn't write {}: {e}", out.display())),
+                None => {
+                    println!("{bundle}");
+                    Ok(())
+                }
+            }
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// Parses durations like `90s`, `30m`, `24h` or `7d`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration {value}, expected e.g. 30m, 24h or 7d\n{USAGE}");
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let amount: i64 = value[..value.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let seconds = match unit {
+        's' => Some(amount),
+        'm' => amount.checked_mul(60),
+        'h' => amount.checked_mul(60 * 60),
+        'd' => amount.checked_mul(24 * 60 * 60),
+        _ => None,
+    };
+    seconds.map(Duration::seconds).ok_or_else(invalid)
+}
@@ -0,0 +1,256 @@
+use crate::device::Device;
+use crate::error::Error;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::OsRng;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+/// Prefixed to the payload before signing, so a bundle signature can't be mistaken for a
+/// signature over anything else.
+const CONTEXT: &[u8] = b"menu-bundle-v1\n";
+
+/// A config signed for one device.
+///
+/// `payload` holds the canonical JSON of a [`BundlePayload`] exactly as it was signed, so
+/// verification never depends on re-serializing the config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedBundle {
+    pub payload: String,
+    /// Base64 Ed25519 signature over the payload.
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundlePayload<T> {
+    pub device: Device,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+    pub config: T,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum BundleError {
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Bundle signature doesn't match the pinned key")]
+    BadSignature,
+    #[error("Bundle is for {found}, not {expected}")]
+    WrongDevice { expected: Device, found: Device },
+    #[error("Bundle expired at {0}")]
+    Expired(OffsetDateTime),
+    #[error("Bundles can only be opened for the device they were signed for")]
+    NoDevice,
+}
+
+/// Produces signed bundles. Only the provisioning side should hold one.
+pub struct BundleSigner {
+    key: SigningKey,
+}
+
+impl BundleSigner {
+    pub fn generate() -> Self {
+        Self {
+            key: SigningKey::generate(&mut OsRng),
+        }
+    }
+    /// Reads a base64 secret key, as written by [`BundleSigner::save`].
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(|e| Error::file_system(path, e))?;
+        let bytes = decode_key(content.trim())?;
+        Ok(Self {
+            key: SigningKey::from_bytes(&bytes),
+        })
+    }
+    /// Writes the secret key as base64, readable only by its owner on Unix.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(BASE64.encode(self.key.to_bytes()).as_bytes()))
+            .map_err(|e| Error::file_system(path, e))
+    }
+    pub fn verifier(&self) -> BundleVerifier {
+        BundleVerifier {
+            key: self.key.verifying_key(),
+        }
+    }
+    pub fn sign<T: Serialize>(
+        &self,
+        device: &Device,
+        expires_at: OffsetDateTime,
+        config: &T,
+    ) -> Result<SignedBundle, Error> {
+        let payload = BundlePayload {
+            device: device.clone(),
+            expires_at,
+            config,
+        };
+        // `serde_json::Value` keeps object keys sorted, which makes the output canonical.
+        let payload = serde_json::to_value(&payload)
+            .map_err(Error::JsonGeneration)?
+            .to_string();
+        let signature = self.key.sign(&[CONTEXT, payload.as_bytes()].concat());
+        Ok(SignedBundle {
+            payload,
+            signature: BASE64.encode(signature.to_bytes()),
+        })
+    }
+}
+
+/// Checks bundles against a pinned public key before their config is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleVerifier {
+    key: VerifyingKey,
+}
+
+impl BundleVerifier {
+    /// Pins a base64 public key, as printed by [`BundleVerifier::to_base64`].
+    pub fn from_base64(key: &str) -> Result<Self, Error> {
+        let bytes = decode_key(key.trim())?;
+        let key =
+            VerifyingKey::from_bytes(&bytes).map_err(|e| BundleError::InvalidKey(e.to_string()))?;
+        Ok(Self { key })
+    }
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.key.to_bytes())
+    }
+    /// Verifies `bundle` is signed, meant for `device` and unexpired, then returns its config.
+    pub fn open<T: DeserializeOwned>(
+        &self,
+        bundle: &SignedBundle,
+        device: &Device,
+    ) -> Result<T, Error> {
+        self.open_at(bundle, device, OffsetDateTime::now_utc())
+    }
+    /// Like [`BundleVerifier::open`], as if called at `now`.
+    pub fn open_at<T: DeserializeOwned>(
+        &self,
+        bundle: &SignedBundle,
+        device: &Device,
+        now: OffsetDateTime,
+    ) -> Result<T, Error> {
+        let signature = BASE64
+            .decode(&bundle.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or(BundleError::BadSignature)?;
+        self.key
+            .verify(&[CONTEXT, bundle.payload.as_bytes()].concat(), &signature)
+            .map_err(|_| BundleError::BadSignature)?;
+        let payload: BundlePayload<T> =
+            serde_json::from_str(&bundle.payload).map_err(Error::json_read)?;
+        if payload.device != *device {
+            return Err(BundleError::WrongDevice {
+                expected: device.clone(),
+                found: payload.device,
+            }
+            .into());
+        }
+        if now >= payload.expires_at {
+            return Err(BundleError::Expired(payload.expires_at).into());
+        }
+        Ok(payload.config)
+    }
+}
+
+fn decode_key(key: &str) -> Result<[u8; 32], Error> {
+    let bytes = BASE64
+        .decode(key)
+        .map_err(|e| BundleError::InvalidKey(e.to_string()))?;
+    let length = bytes.len();
+    Ok(bytes
+        .try_into()
+        .map_err(|_| BundleError::InvalidKey(format!("expected 32 bytes, got {length}")))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Model;
+    use crate::libra::Config;
+    use time::Duration;
+
+    fn device() -> Device {
+        Device::new(Model::LibraV0, "L1")
+    }
+
+    fn signed(signer: &BundleSigner, config: &Config) -> SignedBundle {
+        let expires_at = OffsetDateTime::now_utc() + Duration::hours(1);
+        signer.sign(&device(), expires_at, config).unwrap()
+    }
+
+    #[test]
+    fn test_sign_and_open() {
+        let signer = BundleSigner::generate();
+        let config = Config {
+            gain: 0.1 + 0.2,
+            ..Config::default()
+        };
+        let bundle = signed(&signer, &config);
+
+        let verifier = BundleVerifier::from_base64(&signer.verifier().to_base64()).unwrap();
+        assert_eq!(verifier.open::<Config>(&bundle, &device()).unwrap(), config);
+
+        let round_tripped: SignedBundle =
+            serde_json::from_str(&serde_json::to_string(&bundle).unwrap()).unwrap();
+        assert_eq!(
+            verifier.open::<Config>(&round_tripped, &device()).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn test_rejects_tampering_and_other_keys() {
+        let signer = BundleSigner::generate();
+        let mut bundle = signed(&signer, &Config::default());
+        let other = BundleSigner::generate().verifier();
+        let error = other.open::<Config>(&bundle, &device()).unwrap_err();
+        assert_eq!(error.code(), "bundle");
+        assert!(matches!(error, Error::Bundle(BundleError::BadSignature)));
+
+        bundle.payload = bundle.payload.replace("\"gain\":1.0", "\"gain\":9.0");
+        assert!(matches!(
+            signer.verifier().open::<Config>(&bundle, &device()),
+            Err(Error::Bundle(BundleError::BadSignature))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_wrong_device_and_expired() {
+        let signer = BundleSigner::generate();
+        let bundle = signed(&signer, &Config::default());
+        let verifier = signer.verifier();
+        let other_device = Device::new(Model::LibraV0, "L2");
+        assert!(matches!(
+            verifier.open::<Config>(&bundle, &other_device),
+            Err(Error::Bundle(BundleError::WrongDevice { .. }))
+        ));
+        let later = OffsetDateTime::now_utc() + Duration::hours(2);
+        assert!(matches!(
+            verifier.open_at::<Config>(&bundle, &device(), later),
+            Err(Error::Bundle(BundleError::Expired(_)))
+        ));
+    }
+
+    #[test]
+    fn test_key_files() {
+        let mut path = std::env::temp_dir();
+        path.push("menu-test-bundle.key");
+        let signer = BundleSigner::generate();
+        signer.save(&path).unwrap();
+        let loaded = BundleSigner::from_file(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.verifier(), signer.verifier());
+        assert!(BundleVerifier::from_base64("c2hvcnQ=").is_err());
+    }
+}
@@ -1,57 +1,412 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
+use thiserror::Error;
 
+/// A physical device, identified by its model and serial number.
+///
+/// Its textual form is `{model}-{serial number}`, e.g. `LibraV0-L001`. Everything after the
+/// first `-` belongs to the serial number, and characters outside `[A-Za-z0-9._-]` are
+/// percent-encoded, so every device round-trips through `Display` and `FromStr`.
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Device {
     pub model: Model,
-    pub serial_number: String,
+    pub serial_number: SerialNumber,
 }
 
 impl Device {
+    /// Creates a device without checking the serial number against the model's rules.
     pub fn new(model: Model, serial_number: &str) -> Self {
         Self {
             model,
-            serial_number: serial_number.to_string(),
+            serial_number: SerialNumber::new(serial_number),
         }
     }
+    /// Creates a device, rejecting serial numbers that break the model's rules.
+    pub fn try_new(model: Model, serial_number: &str) -> Result<Self, DeviceParseError> {
+        let serial_number = SerialNumber::parse(&model, serial_number)?;
+        Ok(Self {
+            model,
+            serial_number,
+        })
+    }
 }
 impl fmt::Display for Device {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}-{}", self.model, self.serial_number,)
+        write!(
+            f,
+            "{:?}-{}",
+            self.model,
+            escape(self.serial_number.as_str())
+        )
     }
 }
 
 impl FromStr for Device {
-    type Err = String;
+    type Err = DeviceParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = s.split('-').collect();
-        if parts.len() != 2 {
-            return Err(format!("Invalid format: {}", s));
+        let (model, serial_number) = s
+            .split_once('-')
+            .ok_or_else(|| DeviceParseError::MissingSeparator(s.to_string()))?;
+        let model = Model::from_str(model)
+            .map_err(|_| DeviceParseError::UnknownModel(model.to_string()))?;
+        Device::try_new(model, &unescape(serial_number)?)
+    }
+}
+
+impl TryFrom<&str> for Device {
+    type Error = DeviceParseError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+pub use crate::model::Model;
+
+/// A device serial number. Serializes as a plain string.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(transparent)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SerialNumber(String);
+
+impl SerialNumber {
+    /// Wraps a serial number without validating it.
+    pub fn new(serial_number: &str) -> Self {
+        Self(serial_number.to_string())
+    }
+    /// Validates a serial number against the rules of `model`.
+    pub fn parse(model: &Model, serial_number: &str) -> Result<Self, DeviceParseError> {
+        model
+            .serial_rules()
+            .validate(serial_number)
+            .map_err(|reason| DeviceParseError::InvalidSerial {
+                model: model.clone(),
+                serial_number: serial_number.to_string(),
+                reason,
+            })?;
+        Ok(Self::new(serial_number))
+    }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+impl fmt::Display for SerialNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl AsRef<str> for SerialNumber {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+impl PartialEq<str> for SerialNumber {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+impl PartialEq<&str> for SerialNumber {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+/// Characters allowed in a serial number after its prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    Digits,
+    /// ASCII letters, digits and `-`.
+    Alphanumeric,
+}
+impl Charset {
+    pub fn contains(&self, c: char) -> bool {
+        match self {
+            Charset::Digits => c.is_ascii_digit(),
+            Charset::Alphanumeric => c.is_ascii_alphanumeric() || c == '-',
         }
-        let model = Model::from_str(parts[0])?;
-        let serial_number = parts[1].to_string();
-        Ok(Device::new(model, &serial_number))
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
-pub enum Model {
-    IchibuV1,
-    IchibuV2,
-    LibraV0,
+/// Format rules a model's serial numbers must follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialRules {
+    pub prefix: &'static str,
+    /// Minimum length, including the prefix and any check character.
+    pub min_length: usize,
+    /// Maximum length, including the prefix and any check character.
+    pub max_length: usize,
+    pub charset: Charset,
+    /// Whether the last character is a Luhn mod 36 check character over the rest.
+    pub checksum: bool,
+}
+impl SerialRules {
+    pub fn validate(&self, serial_number: &str) -> Result<(), SerialError> {
+        let Some(rest) = serial_number.strip_prefix(self.prefix) else {
+            return Err(SerialError::MissingPrefix(self.prefix));
+        };
+        let length = serial_number.chars().count();
+        if length < self.min_length {
+            return Err(SerialError::TooShort {
+                min_length: self.min_length,
+                length,
+            });
+        }
+        if length > self.max_length {
+            return Err(SerialError::TooLong {
+                max_length: self.max_length,
+                length,
+            });
+        }
+        if let Some(c) = rest.chars().find(|c| !self.charset.contains(*c)) {
+            return Err(SerialError::InvalidCharacter(c));
+        }
+        if self.checksum {
+            let Some((index, found)) = serial_number.char_indices().next_back() else {
+                return Err(SerialError::TooShort {
+                    min_length: self.min_length.max(1),
+                    length,
+                });
+            };
+            let body = &serial_number[..index];
+            let expected = check_character(body).ok_or(SerialError::InvalidCharacter(found))?;
+            if !found.eq_ignore_ascii_case(&expected) {
+                return Err(SerialError::ChecksumMismatch { expected, found });
+            }
+        }
+        Ok(())
+    }
+    /// Appends the check character to `body` when the rules require one.
+    pub fn with_checksum(&self, body: &str) -> Option<String> {
+        if !self.checksum {
+            return Some(body.to_string());
+        }
+        check_character(body).map(|check| format!("{body}{check}"))
+    }
 }
 
-impl FromStr for Model {
-    type Err = String;
+/// Luhn mod 36 check character over the alphanumeric characters of `body`; `-` is skipped.
+/// Returns `None` if `body` contains anything else.
+pub fn check_character(body: &str) -> Option<char> {
+    const BASE: u32 = 36;
+    let mut sum = 0;
+    let mut factor = 2;
+    for c in body.chars().rev().filter(|c| *c != '-') {
+        let addend = factor * c.to_digit(BASE)?;
+        sum += addend / BASE + addend % BASE;
+        factor = 3 - factor;
+    }
+    std::char::from_digit((BASE - sum % BASE) % BASE, BASE).map(|c| c.to_ascii_uppercase())
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "IchibuV1" => Ok(Model::IchibuV1),
-            "IchibuV2" => Ok(Model::IchibuV2),
-            "LibraV0" => Ok(Model::LibraV0),
-            _ => Err(format!("Invalid model: {}", s)),
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum DeviceParseError {
+    #[error("Expected {{model}}-{{serial number}}, got {0:?}")]
+    MissingSeparator(String),
+    #[error("Invalid model: {0}")]
+    UnknownModel(String),
+    #[error("Invalid percent-encoding in serial number {0:?}")]
+    InvalidEscape(String),
+    #[error("Invalid serial number {serial_number:?} for {model:?}: {reason}")]
+    InvalidSerial {
+        model: Model,
+        serial_number: String,
+        reason: SerialError,
+    },
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SerialError {
+    #[error("must start with {0:?}")]
+    MissingPrefix(&'static str),
+    #[error("must be at least {min_length} characters long, got {length}")]
+    TooShort { min_length: usize, length: usize },
+    #[error("must be at most {max_length} characters long, got {length}")]
+    TooLong { max_length: usize, length: usize },
+    #[error("contains invalid character {0:?}")]
+    InvalidCharacter(char),
+    #[error("check character should be {expected:?}, got {found:?}")]
+    ChecksumMismatch { expected: char, found: char },
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.')
+}
+
+fn escape(serial_number: &str) -> String {
+    let mut escaped = String::with_capacity(serial_number.len());
+    for byte in serial_number.bytes() {
+        if is_unreserved(byte) {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{byte:02X}"));
         }
     }
+    escaped
+}
+
+fn unescape(escaped: &str) -> Result<String, DeviceParseError> {
+    let invalid = || DeviceParseError::InvalidEscape(escaped.to_string());
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut iter = escaped.bytes();
+    while let Some(byte) = iter.next() {
+        match byte {
+            b'%' => {
+                let hex = [
+                    iter.next().ok_or_else(invalid)?,
+                    iter.next().ok_or_else(invalid)?,
+                ];
+                let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            }
+            byte if is_unreserved(byte) => bytes.push(byte),
+            _ => return Err(invalid()),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn model() -> impl Strategy<Value = Model> {
+        proptest::sample::select(Model::ALL)
+    }
+
+    fn valid_device() -> impl Strategy<Value = Device> {
+        model().prop_flat_map(|model| {
+            let rules = model.serial_rules();
+            let check_length = usize::from(rules.checksum);
+            let rest_length = rules.min_length - rules.prefix.len() - check_length
+                ..=rules.max_length - rules.prefix.len() - check_length;
+            proptest::string::string_regex(&format!(
+                "[A-Za-z0-9-]{{{},{}}}",
+                rest_length.start(),
+                rest_length.end()
+            ))
+            .unwrap()
+            .prop_map(move |rest| {
+                let serial_number = rules
+                    .with_checksum(&format!("{}{rest}", rules.prefix))
+                    .unwrap();
+                Device::new(model.clone(), &serial_number)
+            })
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_display_from_str_round_trip(device in valid_device()) {
+            let parsed = Device::from_str(&device.to_string()).unwrap();
+            prop_assert_eq!(parsed, device);
+        }
+
+        #[test]
+        fn test_escape_round_trip(serial_number in any::<String>()) {
+            let escaped = escape(&serial_number);
+            prop_assert!(!escaped.contains(|c: char| !c.is_ascii_graphic()));
+            prop_assert_eq!(unescape(&escaped).unwrap(), serial_number);
+        }
+
+        #[test]
+        fn test_checksum_detects_single_substitution(
+            body in "L[A-Z0-9]{4,10}",
+            index in 1usize..5,
+            replacement in "[A-Z0-9]",
+        ) {
+            let rules = SerialRules { checksum: true, ..Model::LibraV0.serial_rules() };
+            let serial_number = rules.with_checksum(&body).unwrap();
+            prop_assert!(rules.validate(&serial_number).is_ok());
+            let original = &serial_number[index..index + 1];
+            prop_assume!(original != replacement);
+            let mut tampered = serial_number.clone();
+            tampered.replace_range(index..index + 1, &replacement);
+            prop_assert!(rules.validate(&tampered).is_err());
+        }
+    }
+
+    #[test]
+    fn test_dashed_serial_round_trips() {
+        let device = Device::try_new(Model::LibraV0, "L-001").unwrap();
+        assert_eq!(device.to_string(), "LibraV0-L-001");
+        assert_eq!(Device::try_from("LibraV0-L-001").unwrap(), device);
+    }
+
+    #[test]
+    fn test_escaped_serial_round_trips() {
+        let device = Device::new(Model::LibraV0, "L 1/2%");
+        assert_eq!(device.to_string(), "LibraV0-L%201%2F2%25");
+        assert_eq!(unescape("L%201%2F2%25").unwrap(), "L 1/2%");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Device::from_str("LibraV0"),
+            Err(DeviceParseError::MissingSeparator("LibraV0".to_string()))
+        );
+        assert_eq!(
+            Device::from_str("LibraV9-L1"),
+            Err(DeviceParseError::UnknownModel("LibraV9".to_string()))
+        );
+        assert_eq!(
+            Device::from_str("LibraV0-L%2"),
+            Err(DeviceParseError::InvalidEscape("L%2".to_string()))
+        );
+        assert!(matches!(
+            Device::from_str("LibraV0-I1"),
+            Err(DeviceParseError::InvalidSerial {
+                reason: SerialError::MissingPrefix("L"),
+                ..
+            })
+        ));
+        assert!(matches!(
+            Device::from_str("LibraV0-L"),
+            Err(DeviceParseError::InvalidSerial {
+                reason: SerialError::TooShort { .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            Device::from_str("LibraV0-L%201"),
+            Err(DeviceParseError::InvalidSerial {
+                reason: SerialError::InvalidCharacter(' '),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let rules = SerialRules {
+            checksum: true,
+            ..Model::LibraV0.serial_rules()
+        };
+        let serial_number = rules.with_checksum("L0001").unwrap();
+        assert_eq!(rules.validate(&serial_number), Ok(()));
+        assert_eq!(
+            rules.validate("L00010"),
+            Err(SerialError::ChecksumMismatch {
+                expected: serial_number.chars().last().unwrap(),
+                found: '0',
+            })
+        );
+
+        let unprefixed = SerialRules {
+            prefix: "",
+            min_length: 0,
+            ..rules
+        };
+        assert_eq!(
+            unprefixed.validate(""),
+            Err(SerialError::TooShort {
+                min_length: 1,
+                length: 0
+            })
+        );
+    }
 }
@@ -0,0 +1,133 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A single changed field, addressed by its dotted path, e.g. `setpoint.empty`.
+/// `old` is `None` for added fields and `new` is `None` for removed ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub path: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// The fields that differ between two values of a config type, down to their leaves.
+/// Arrays are compared as a whole.
+pub fn diff<T: Serialize + ?Sized>(old: &T, new: &T) -> Result<Vec<FieldChange>, Error> {
+    let old = serde_json::to_value(old).map_err(Error::JsonGeneration)?;
+    let new = serde_json::to_value(new).map_err(Error::JsonGeneration)?;
+    Ok(diff_values(&old, &new))
+}
+
+/// Like [`diff`], treating a missing side as an empty table, so adding or removing a whole
+/// entry lists every one of its fields.
+pub fn diff_optional<T: Serialize + ?Sized>(
+    old: Option<&T>,
+    new: Option<&T>,
+) -> Result<Vec<FieldChange>, Error> {
+    let to_value = |value: Option<&T>| match value {
+        Some(value) => serde_json::to_value(value).map_err(Error::JsonGeneration),
+        None => Ok(Value::Object(Default::default())),
+    };
+    Ok(diff_values(&to_value(old)?, &to_value(new)?))
+}
+
+pub fn diff_values(old: &Value, new: &Value) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    diff_at("", Some(old), Some(new), &mut changes);
+    changes
+}
+
+fn diff_at(path: &str, old: Option<&Value>, new: Option<&Value>, changes: &mut Vec<FieldChange>) {
+    let old_map = old.and_then(Value::as_object);
+    let new_map = new.and_then(Value::as_object);
+    if old_map.is_none() && new_map.is_none() {
+        if old != new {
+            changes.push(FieldChange {
+                path: path.to_string(),
+                old: old.cloned(),
+                new: new.cloned(),
+            });
+        }
+        return;
+    }
+    // A table replacing a leaf, or the other way round, is reported as the leaf changing
+    // plus every field of the table, so all paths end at a leaf.
+    let old_leaf = old.filter(|value| !value.is_object());
+    let new_leaf = new.filter(|value| !value.is_object());
+    if old_leaf.is_some() || new_leaf.is_some() {
+        changes.push(FieldChange {
+            path: path.to_string(),
+            old: old_leaf.cloned(),
+            new: new_leaf.cloned(),
+        });
+    }
+    let mut keys: Vec<&String> = old_map.into_iter().flat_map(|map| map.keys()).collect();
+    keys.extend(new_map.into_iter().flat_map(|map| map.keys()));
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let child = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        diff_at(
+            &child,
+            old_map.and_then(|map| map.get(key)),
+            new_map.and_then(|map| map.get(key)),
+            changes,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libra::Libra;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_reports_leaf_paths() {
+        let old = Libra::default();
+        let mut new = old.clone();
+        new.config.gain = 2.0;
+        new.config.location = "Kitchen".into();
+        let changes = diff(&old, &new).unwrap();
+        assert_eq!(
+            changes,
+            [
+                FieldChange {
+                    path: "config.gain".into(),
+                    old: Some(json!(1.0)),
+                    new: Some(json!(2.0)),
+                },
+                FieldChange {
+                    path: "config.location".into(),
+                    old: Some(json!("Caldo HQ")),
+                    new: Some(json!("Kitchen")),
+                },
+            ]
+        );
+        assert!(diff(&old, &old).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff_added_and_removed_tables() {
+        let old = json!({ "a": 1, "hatch": { "open_input": 1, "motor": { "id": 0 } } });
+        let new = json!({ "a": [1, 2], "b": null });
+        let paths: Vec<_> = diff_values(&old, &new)
+            .into_iter()
+            .map(|change| (change.path, change.old.is_some(), change.new.is_some()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("a".to_string(), true, true),
+                ("b".to_string(), false, true),
+                ("hatch.motor.id".to_string(), true, false),
+                ("hatch.open_input".to_string(), true, false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use crate::address::{Address, Host};
+use crate::device::Device;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// UDP port announcers listen on.
+pub const DISCOVERY_PORT: u16 = 41_234;
+
+/// How often an announcer checks whether it was stopped, and how long it pauses after a
+/// failed receive.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a device tells browsers about itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Announcement {
+    pub device: Device,
+    /// Version of the config the device runs, e.g. the backend ETag it last applied.
+    pub config_version: Option<String>,
+    /// Port the device serves on, if any.
+    pub port: Option<u16>,
+}
+
+/// A device that answered a [`Browser`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discovered {
+    pub announcement: Announcement,
+    /// Where the answer came from.
+    pub from: SocketAddr,
+}
+
+impl Discovered {
+    /// Where to reach the device: the IP it answered from and the port it announced.
+    pub fn address(&self) -> Address {
+        Address::new(Host::Ip(self.from.ip()), self.announcement.port)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "menu")]
+enum Message {
+    Probe,
+    Announce(Announcement),
+}
+
+impl Message {
+    fn parse(packet: &[u8]) -> Option<Self> {
+        serde_json::from_slice(packet).ok()
+    }
+    fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(self).map_err(Error::JsonGeneration)
+    }
+}
+
+/// Answers discovery probes on the LAN with the device's [`Announcement`] until dropped.
+///
+/// It never announces unprompted: a device is only found by a [`Browser`] probing while it
+/// runs. Probes are only answered when they come from a private, link-local or loopback
+/// address, so the announcer can't be used to reflect larger replies at hosts elsewhere.
+pub struct Announcer {
+    local_addr: SocketAddr,
+    announcement: Arc<Mutex<Announcement>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Announcer {
+    /// Listens on [`DISCOVERY_PORT`] on every interface.
+    pub fn start(announcement: Announcement) -> Result<Self, Error> {
+        Self::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT), announcement)
+    }
+    pub fn bind(address: impl ToSocketAddrs, announcement: Announcement) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(address).map_err(Error::Discovery)?;
+        socket
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(Error::Discovery)?;
+        let local_addr = socket.local_addr().map_err(Error::Discovery)?;
+        let announcement = Arc::new(Mutex::new(announcement));
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let announcement = announcement.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || answer_probes(&socket, &announcement, &stop))
+        };
+        Ok(Self {
+            local_addr,
+            announcement,
+            stop,
+            handle: Some(handle),
+        })
+    }
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+    /// Announces `config_version` from now on, e.g. after applying a new config.
+    pub fn set_config_version(&self, config_version: Option<String>) {
+        self.announcement.lock().unwrap().config_version = config_version;
+    }
+}
+
+impl Drop for Announcer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn answer_probes(socket: &UdpSocket, announcement: &Mutex<Announcement>, stop: &AtomicBool) {
+    let mut buffer = [0; 2048];
+    while !stop.load(Ordering::Relaxed) {
+        let (length, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue;
+            }
+            // Errors that don't wait out the read timeout would otherwise spin.
+            Err(_) => {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+        };
+        if !is_local(from.ip()) {
+            continue;
+        }
+        // Anything else on the port, including other announcements, is ignored.
+        if let Some(Message::Probe) = Message::parse(&buffer[..length]) {
+            let reply = Message::Announce(announcement.lock().unwrap().clone());
+            if let Ok(reply) = reply.to_bytes() {
+                let _ = socket.send_to(&reply, from);
+            }
+        }
+    }
+}
+
+/// Whether `ip` is on the local network: private, link-local or loopback.
+fn is_local(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local() || ip.is_loopback(),
+        IpAddr::V6(ip) => ip.is_unique_local() || ip.is_unicast_link_local() || ip.is_loopback(),
+    }
+}
+
+/// Finds devices on the LAN by broadcasting a probe and collecting the announcements that
+/// come back within a timeout.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Browser {
+    targets: Vec<SocketAddr>,
+    timeout: Duration,
+}
+
+impl Default for Browser {
+    /// Broadcasts to [`DISCOVERY_PORT`] and waits 2s.
+    fn default() -> Self {
+        Self {
+            targets: vec![(Ipv4Addr::BROADCAST, DISCOVERY_PORT).into()],
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+impl Browser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Probes `targets` instead of broadcasting, e.g. a subnet's broadcast address or a
+    /// single announcer.
+    pub fn with_targets(mut self, targets: impl IntoIterator<Item = SocketAddr>) -> Self {
+        self.targets = targets.into_iter().collect();
+        self
+    }
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Every device that answered before the timeout, once each, ordered by device.
+    pub fn browse(&self) -> Result<Vec<Discovered>, Error> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).map_err(Error::Discovery)?;
+        socket.set_broadcast(true).map_err(Error::Discovery)?;
+        let probe = Message::Probe.to_bytes()?;
+        for target in &self.targets {
+            socket.send_to(&probe, target).map_err(Error::Discovery)?;
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let mut discovered: Vec<Discovered> = Vec::new();
+        let mut buffer = [0; 2048];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            socket
+                .set_read_timeout(Some(remaining))
+                .map_err(Error::Discovery)?;
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    break;
+                }
+                // A target with nothing listening may bounce the probe back as an error.
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => return Err(Error::Discovery(e)),
+            };
+            let Some(Message::Announce(announcement)) = Message::parse(&buffer[..length]) else {
+                continue;
+            };
+            discovered.retain(|seen| seen.announcement.device != announcement.device);
+            discovered.push(Discovered { announcement, from });
+        }
+        discovered.sort_by_key(|found| found.announcement.device.to_string());
+        Ok(discovered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Model;
+
+    fn announce(device: Device, port: Option<u16>) -> Announcer {
+        let announcement = Announcement {
+            device,
+            config_version: Some("v1".into()),
+            port,
+        };
+        Announcer::bind((Ipv4Addr::LOCALHOST, 0), announcement).unwrap()
+    }
+
+    #[test]
+    fn test_browse_over_loopback() {
+        let libra = announce(Device::new(Model::LibraV0, "L1"), Some(8080));
+        let ichibu = announce(Device::new(Model::IchibuV2, "I1"), None);
+        let browser = Browser::new()
+            .with_targets([libra.local_addr(), ichibu.local_addr()])
+            .with_timeout(Duration::from_millis(300));
+
+        let found = browser.browse().unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            found[0].announcement.device,
+            Device::new(Model::IchibuV2, "I1")
+        );
+        assert_eq!(found[1].address().to_string(), "127.0.0.1:8080");
+        assert_eq!(found[1].announcement.config_version.as_deref(), Some("v1"));
+
+        libra.set_config_version(Some("v2".into()));
+        drop(ichibu);
+        let found = browser.browse().unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].announcement.config_version.as_deref(), Some("v2"));
+    }
+
+    #[test]
+    fn test_only_local_sources_are_answered() {
+        for ip in [
+            "10.0.0.7",
+            "172.16.4.2",
+            "192.168.1.20",
+            "169.254.0.3",
+            "127.0.0.1",
+            "fe80::1",
+            "fd00::7",
+            "::1",
+            "::ffff:192.168.1.20",
+        ] {
+            assert!(is_local(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["8.8.8.8", "100.64.0.1", "2001:db8::1", "::ffff:8.8.8.8"] {
+            assert!(!is_local(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn test_announcer_ignores_stray_packets() {
+        let libra = announce(Device::new(Model::LibraV0, "L1"), None);
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket.send_to(b"not json", libra.local_addr()).unwrap();
+        socket
+            .send_to(br#"{"menu":"announce"}"#, libra.local_addr())
+            .unwrap();
+
+        let found = Browser::new()
+            .with_targets([libra.local_addr()])
+            .with_timeout(Duration::from_millis(300))
+            .browse()
+            .unwrap();
+        assert_eq!(found.len(), 1);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::error::Error;
+use crate::secret::{self, SecretKey};
+use crate::snapshot;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+/// A TOML config file opened for editing.
+///
+/// Only keys whose values actually change are touched, so hand-written comments,
+/// table ordering and whitespace survive an edit. Encrypted values stay encrypted.
+pub(crate) struct TomlDocument {
+    path: PathBuf,
+    document: DocumentMut,
+    key: Option<SecretKey>,
+}
+
+impl TomlDocument {
+    pub(crate) fn open(path: &Path) -> Result<Self, Error> {
+        let file_as_string = fs::read_to_string(path).map_err(|e| Error::file_system(path, e))?;
+        let document = file_as_string
+            .parse::<DocumentMut>()
+            .map_err(|e| Error::toml_edit(&file_as_string, e).with_path(path))?;
+        let key = secret::contains_encrypted(document.as_item())
+            .then(SecretKey::from_env)
+            .transpose()?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            document,
+            key,
+        })
+    }
+    /// Merges `value` into the table at `key`, or into the document root when `key` is `None`.
+    pub(crate) fn merge<T: Serialize>(
+        &mut self,
+        key: Option<&str>,
+        value: &T,
+    ) -> Result<(), Error> {
+        let Some(key) = key else {
+            merge_table(
+                self.document.as_table_mut(),
+                to_table(value)?,
+                self.key.as_ref(),
+            );
+            return Ok(());
+        };
+        match self.document.get_mut(key) {
+            Some(item) => merge_item(item, Item::Table(to_table(value)?), self.key.as_ref()),
+            None => self.insert(key, value)?,
+        }
+        Ok(())
+    }
+    /// Appends `value` as a new table at the end of the document.
+    pub(crate) fn insert<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), Error> {
+        let mut table = to_table(value)?;
+        table.set_implicit(true);
+        self.document.insert(key, Item::Table(table));
+        Ok(())
+    }
+    /// Keeps only the root-level keys for which `keep` returns `true`.
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.document.retain(|key, _| keep(key));
+    }
+    /// Writes the document back, snapshotting the file first.
+    pub(crate) fn save(&self) -> Result<(), Error> {
+        snapshot::before_write(&self.path)?;
+        self.save_without_snapshot()
+    }
+    /// Writes the document back without keeping a copy of what it replaces, for edits that
+    /// remove secrets.
+    pub(crate) fn save_without_snapshot(&self) -> Result<(), Error> {
+        fs::write(&self.path, self.document.to_string())
+            .map_err(|e| Error::file_system(&self.path, e))
+    }
+}
+
+fn to_table<T: Serialize>(value: &T) -> Result<Table, Error> {
+    let toml_string = toml::to_string(value).map_err(Error::TomlGeneration)?;
+    let document = toml_string
+        .parse::<DocumentMut>()
+        .map_err(|e| Error::toml_edit(&toml_string, e))?;
+    Ok(detached(document.as_table()))
+}
+
+/// Freshly serialized tables carry positions relative to their own document, which would
+/// interleave them with the tables already in the file, so they are rebuilt without one.
+fn detached(table: &Table) -> Table {
+    let mut detached_table = Table::new();
+    detached_table.set_implicit(table.is_implicit());
+    for (key, item) in table.iter() {
+        let item = match item {
+            Item::Table(table) => Item::Table(detached(table)),
+            item => item.clone(),
+        };
+        detached_table.insert(key, item);
+    }
+    detached_table
+}
+
+fn merge_table(table: &mut Table, new_table: Table, secret_key: Option<&SecretKey>) {
+    let removed_keys: Vec<String> = table
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !new_table.contains_key(key))
+        .collect();
+    for key in removed_keys {
+        table.remove(&key);
+    }
+    for (key, new_item) in new_table {
+        match table.get_mut(&key) {
+            Some(item) => merge_item(item, new_item, secret_key),
+            None => {
+                table.insert(&key, new_item);
+            }
+        }
+    }
+}
+
+fn merge_item(item: &mut Item, new_item: Item, secret_key: Option<&SecretKey>) {
+    match (item, new_item) {
+        (Item::Table(table), Item::Table(new_table)) => merge_table(table, new_table, secret_key),
+        (Item::Value(Value::InlineTable(inline)), Item::Table(new_table)) => {
+            let mut table = inline.clone().into_table();
+            merge_table(&mut table, new_table, secret_key);
+            let mut new_inline = table.into_inline_table();
+            *new_inline.decor_mut() = inline.decor().clone();
+            *inline = new_inline;
+        }
+        (Item::Value(value), Item::Value(new_value)) => {
+            let new_value = match (value.as_str(), new_value.as_str(), secret_key) {
+                (Some(encrypted), Some(plaintext), Some(key))
+                    if secret::is_encrypted(encrypted) =>
+                {
+                    if key.decrypt(encrypted).is_ok_and(|old| old == plaintext) {
+                        return;
+                    }
+                    Value::from(key.encrypt(plaintext))
+                }
+                _ => new_value,
+            };
+            if !value_eq(value, &new_value) {
+                let decor = value.decor().clone();
+                *value = new_value;
+                *value.decor_mut() = decor;
+            }
+        }
+        (item, new_item) => *item = new_item,
+    }
+}
+
+fn value_eq(value: &Value, other: &Value) -> bool {
+    match (value, other) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Float(a), Value::Float(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Float(b)) | (Value::Float(b), Value::Integer(a)) => {
+            *a.value() as f64 == *b.value()
+        }
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Datetime(a), Value::Datetime(b)) => a.value() == b.value(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| value_eq(a, b))
+        }
+        (Value::InlineTable(a), Value::InlineTable(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| value_eq(a, b)))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ichibu::ScaleConfig;
+    use crate::read::Read;
+
+    fn edited<T: Serialize>(original: &str, key: Option<&str>, value: &T) -> String {
+        let mut document = TomlDocument {
+            path: PathBuf::new(),
+            document: original.parse().unwrap(),
+            key: None,
+        };
+        document.merge(key, value).unwrap();
+        document.document.to_string()
+    }
+
+    #[test]
+    fn test_merge_unchanged_value_keeps_formatting() {
+        let original = "# Scale\nphidget_id   =  716620 # serial\n#coefficients = [0, 1, 2, 3]\n";
+        let scale_config: ScaleConfig = toml::from_str(original).unwrap();
+        assert_eq!(edited(original, None, &scale_config), original);
+    }
+
+    #[test]
+    fn test_merge_adds_changed_keys_only() {
+        let original = include_str!("../scale.toml");
+        let mut scale_config: ScaleConfig = toml::from_str(original).unwrap();
+        scale_config.update_coefficients([0.0, 1.0, 2.0, 3.0]);
+        let result = edited(original, None, &scale_config);
+        assert!(result.starts_with("phidget_id = 716620\n"));
+        assert!(result.contains("#coefficients = [0, 1, 2, 3]"));
+        assert!(result.contains("coefficients = [0.0, 1.0, 2.0, 3.0]\n"));
+        assert_eq!(
+            toml::from_str::<toml::Value>(&result).unwrap(),
+            toml::Value::try_from(&scale_config).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_merge_replaces_value_keeping_comment() {
+        let original = "[a]\nx = 1.0 # keep me\ny = \"old\"\n\n# trailing table\n[b]\nz = 2\n";
+        let new: toml::Value = toml::from_str("[a]\nx = 1\ny = \"new\"\n[b]\nz = 2\n").unwrap();
+        assert_eq!(
+            edited(original, None, &new),
+            "[a]\nx = 1.0 # keep me\ny = \"new\"\n\n# trailing table\n[b]\nz = 2\n"
+        );
+    }
+
+    #[test]
+    fn test_scale_config_edit_config_file() {
+        let mut path = std::env::temp_dir();
+        path.push("menu-test-scale_config_edit.toml");
+        fs::write(&path, include_str!("../scale.toml")).unwrap();
+
+        let mut scale_config = ScaleConfig::read(&path).unwrap();
+        scale_config.update_coefficients([0.0, 1.0, 2.0, 3.0]);
+        scale_config.edit_config_file(&path).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_dir_all(snapshot::Snapshots::new(&path).dir());
+        assert_eq!(
+            content,
+            "phidget_id = 716620\ncoefficients = [0.0, 1.0, 2.0, 3.0]\n#coefficients = [0, 1, 2, 3]"
+        );
+    }
+
+    #[test]
+    fn test_open_loads_key_only_for_encrypted_values() {
+        let mut path = std::env::temp_dir();
+        path.push("menu-test-edit_open_key.toml");
+        fs::write(
+            &path,
+            "# rotate !enc: values yearly
+note = \"not !enc:AAAA\"\n",
+        )
+        .unwrap();
+        let opened = TomlDocument::open(&path).map(|document| document.key.is_none());
+        fs::write(&path, "pin = \"!enc:AAAA\"\n").unwrap();
+        let encrypted = TomlDocument::open(&path).map(|_| ());
+        let _ = fs::remove_file(&path);
+        assert!(opened.unwrap());
+        assert!(encrypted.is_err(), "MENU_KEY_FILE isn't set");
+    }
+
+    #[test]
+    fn test_merge_removes_missing_keys() {
+        let original = "phidget_id = 1\n# fitted 2024-01-01\ncoefficients = [0.0, 1.0, 2.0, 3.0]\n";
+        let scale_config: ScaleConfig = toml::from_str("phidget_id = 1").unwrap();
+        assert_eq!(edited(original, None, &scale_config), "phidget_id = 1\n");
+    }
+
+    #[cfg(feature = "secret")]
+    #[test]
+    fn test_merge_keeps_values_encrypted() {
+        let key = SecretKey::generate();
+        let unchanged = key.encrypt("2744");
+        let original = format!(
+            "[pins]\nmanager = \"{unchanged}\" # encrypted\noperator = \"{}\"\n",
+            key.encrypt("200")
+        );
+        let new: toml::Value =
+            toml::from_str("[pins]\nmanager = \"2744\"\noperator = \"300\"\n").unwrap();
+        let mut document = TomlDocument {
+            path: PathBuf::new(),
+            document: original.parse().unwrap(),
+            key: Some(key.clone()),
+        };
+        document.merge(None, &new).unwrap();
+
+        let result = document.document.to_string();
+        assert!(result.contains(&format!("manager = \"{unchanged}\" # encrypted\n")));
+        assert!(!result.contains("300"));
+        let decrypted: toml::Value = toml::from_str(&key.decrypt_toml(&result).unwrap()).unwrap();
+        assert_eq!(decrypted, new);
+    }
+}
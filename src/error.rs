@@ -1,40 +1,411 @@
+use crate::address::AddressError;
+#[cfg(feature = "bundle")]
+use crate::bundle::BundleError;
+use crate::device::{Device, DeviceParseError};
+use crate::format::Format;
 #[cfg(any(feature = "write", feature = "address"))]
 use reqwest;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use toml;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Error generating config.toml: {0}")]
+    #[error("Error generating TOML: {0}")]
     TomlGeneration(toml::ser::Error),
-    #[error("Error reading config.toml: {0}")]
-    TomlRead(toml::de::Error),
-    #[error("Error with file system operation: {0}")]
-    FileSystem(#[from] std::io::Error),
-    #[error("Feature not yet implemented!")]
-    NotImplemented,
-    #[error("{0}")]
-    Custom(String),
+    #[error("Error reading TOML{}: {}", Location(path, span), source.message())]
+    TomlRead {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        source: Box<toml::de::Error>,
+    },
+    #[error("Error editing TOML{}: {}", Location(path, span), source.message())]
+    TomlEdit {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        source: Box<toml_edit::TomlError>,
+    },
+    #[cfg(feature = "json")]
+    #[error("Error generating JSON: {0}")]
+    JsonGeneration(serde_json::Error),
+    #[cfg(feature = "json")]
+    #[error("Error reading JSON{}: {source}", Location(path, span))]
+    JsonRead {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        source: serde_json::Error,
+    },
+    #[error("Error generating YAML: {0}")]
+    YamlGeneration(serde_yaml::Error),
+    #[error("Error reading YAML{}: {source}", Location(path, span))]
+    YamlRead {
+        path: Option<PathBuf>,
+        span: Option<Span>,
+        source: serde_yaml::Error,
+    },
+    #[error("Expected {format} root{} to be a table of tables", Location(path, &None))]
+    InvalidRoot {
+        path: Option<PathBuf>,
+        format: Format,
+    },
+    #[error("Error with file system operation on {}: {source}", path.display())]
+    FileSystem {
+        path: PathBuf,
+        source: std::io::Error,
+    },
     #[cfg(any(feature = "write", feature = "address"))]
-    #[error("Error reaching backend: {0}")]
-    Reqwest(reqwest::Error),
+    #[error("Error reaching backend with {method} {url}: {source}")]
+    Reqwest {
+        method: reqwest::Method,
+        url: String,
+        source: reqwest::Error,
+    },
     #[cfg(feature = "write")]
     #[error("Device must be assigned a serial number before compiling config file!")]
     NoSerialNumber,
     #[cfg(feature = "write")]
-    #[error("Error serializing from backend: {0}")]
-    SerdeJson(serde_json::Error),
-    #[error("File Already Exists")]
-    FileExists,
-    #[error("File Does Not Exist")]
-    FileNotFound,
-    #[error("Libra not found in config file!")]
-    LibraNotFound,
-    #[error("Libra already exists in config file!")]
-    LibraAlreadyExists,
-    #[error("Couldn't access environment variable: {0}")]
-    Env(#[from] std::env::VarError),
+    #[error("Backend handed out page token {page_token} twice while listing {url}")]
+    RepeatedPageToken { url: String, page_token: String },
+    #[error("File already exists: {}", .0.display())]
+    FileExists(PathBuf),
+    #[error("File does not exist: {}", .0.display())]
+    FileNotFound(PathBuf),
+    #[error("Libra {device} not found in {}!", path.display())]
+    LibraNotFound { device: Device, path: PathBuf },
+    #[error("Libra {device} already exists in {}!", path.display())]
+    LibraAlreadyExists { device: Device, path: PathBuf },
+    #[error("Invalid device: {0}")]
+    DeviceParse(#[from] DeviceParseError),
+    #[error("Invalid address: {0}")]
+    Address(#[from] AddressError),
+    #[error("Couldn't determine local network address: {0}")]
+    LocalAddress(std::io::Error),
+    #[error("LAN discovery failed: {0}")]
+    Discovery(std::io::Error),
+    #[error("Invalid config bundle: {0}")]
+    #[cfg(feature = "bundle")]
+    Bundle(#[from] BundleError),
+    #[error("Couldn't access environment variable {name}: {source}")]
+    Env {
+        name: String,
+        source: std::env::VarError,
+    },
+    #[cfg(feature = "pins")]
+    #[error("Couldn't hash PIN: {0}")]
+    PinHash(argon2::password_hash::Error),
+    #[error("Wrong PIN, {attempts_remaining} attempts remaining")]
+    WrongPin { attempts_remaining: u32 },
+    #[error("Too many wrong PINs, try again in {}s", retry_after.as_secs())]
+    LockedOut { retry_after: std::time::Duration },
+    #[error("{access_level} may not change {}", denied.join(", "))]
+    Unauthorized {
+        access_level: crate::pin::AccessLevel,
+        denied: Vec<String>,
+    },
+    #[error("Audit log {} was tampered with at entry {sequence}", path.display())]
+    AuditChainBroken { path: PathBuf, sequence: u64 },
+    #[error("Invalid {field}: {reason}")]
+    Validation { field: &'static str, reason: String },
+    #[cfg(feature = "write")]
+    #[error("{device} was changed by someone else; its config is now at version {}", current.etag.as_deref().unwrap_or("unknown"))]
+    Conflict {
+        device: Device,
+        current: Box<crate::backend::write::Versioned<crate::libra::Config>>,
+    },
     #[cfg(any(feature = "write", feature = "address"))]
-    #[error("Backend Error: {0:?}")]
-    Backend(reqwest::StatusCode),
+    #[error("Backend responded to {method} {url} with {status}: {body}")]
+    Backend {
+        method: reqwest::Method,
+        url: String,
+        status: reqwest::StatusCode,
+        body: String,
+    },
+}
+
+impl Error {
+    /// A stable, machine-readable identifier for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::TomlGeneration(_) => "toml_generation",
+            Error::TomlRead { .. } => "toml_read",
+            Error::TomlEdit { .. } => "toml_edit",
+            #[cfg(feature = "json")]
+            Error::JsonGeneration(_) => "json_generation",
+            #[cfg(feature = "json")]
+            Error::JsonRead { .. } => "json_read",
+            Error::YamlGeneration(_) => "yaml_generation",
+            Error::YamlRead { .. } => "yaml_read",
+            Error::InvalidRoot { .. } => "invalid_root",
+            Error::FileSystem { .. } => "file_system",
+            #[cfg(any(feature = "write", feature = "address"))]
+            Error::Reqwest { .. } => "backend_unreachable",
+            #[cfg(feature = "write")]
+            Error::NoSerialNumber => "no_serial_number",
+            #[cfg(feature = "write")]
+            Error::RepeatedPageToken { .. } => "repeated_page_token",
+            Error::FileExists(_) => "file_exists",
+            Error::FileNotFound(_) => "file_not_found",
+            Error::LibraNotFound { .. } => "libra_not_found",
+            Error::LibraAlreadyExists { .. } => "libra_already_exists",
+            Error::DeviceParse(_) => "device_parse",
+            Error::Address(_) => "address",
+            Error::LocalAddress(_) => "local_address",
+            Error::Discovery(_) => "discovery",
+            #[cfg(feature = "bundle")]
+            Error::Bundle(_) => "bundle",
+            Error::Env { .. } => "env",
+            Error::Validation { .. } => "validation",
+            #[cfg(feature = "pins")]
+            Error::PinHash(_) => "pin_hash",
+            Error::WrongPin { .. } => "wrong_pin",
+            Error::LockedOut { .. } => "locked_out",
+            Error::Unauthorized { .. } => "unauthorized",
+            Error::AuditChainBroken { .. } => "audit_chain_broken",
+            #[cfg(feature = "write")]
+            Error::Conflict { .. } => "conflict",
+            #[cfg(any(feature = "write", feature = "address"))]
+            Error::Backend { .. } => "backend",
+        }
+    }
+    pub(crate) fn file_system(path: &Path, source: std::io::Error) -> Self {
+        Error::FileSystem {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+    pub(crate) fn toml_read(input: &str, source: toml::de::Error) -> Self {
+        Error::TomlRead {
+            path: None,
+            span: source
+                .span()
+                .map(|span| Span::from_offset(input, span.start)),
+            source: Box::new(source),
+        }
+    }
+    #[cfg(any(feature = "write", feature = "secret"))]
+    pub(crate) fn toml_edit(input: &str, source: toml_edit::TomlError) -> Self {
+        Error::TomlEdit {
+            path: None,
+            span: source
+                .span()
+                .map(|span| Span::from_offset(input, span.start)),
+            source: Box::new(source),
+        }
+    }
+    #[cfg(feature = "json")]
+    pub(crate) fn json_read(source: serde_json::Error) -> Self {
+        let span = (source.line() > 0).then(|| Span {
+            line: source.line(),
+            column: source.column(),
+        });
+        Error::JsonRead {
+            path: None,
+            span,
+            source,
+        }
+    }
+    pub(crate) fn yaml_read(source: serde_yaml::Error) -> Self {
+        let span = source.location().map(|location| Span {
+            line: location.line(),
+            column: location.column(),
+        });
+        Error::YamlRead {
+            path: None,
+            span,
+            source,
+        }
+    }
+    #[cfg(feature = "write")]
+    pub(crate) fn conflict(
+        device: Device,
+        current: crate::backend::write::Versioned<crate::libra::Config>,
+    ) -> Self {
+        Error::Conflict {
+            device,
+            current: Box::new(current),
+        }
+    }
+    pub(crate) fn validation(field: &'static str, reason: impl Into<String>) -> Self {
+        Error::Validation {
+            field,
+            reason: reason.into(),
+        }
+    }
+    /// Attaches the file a parse error came from.
+    pub(crate) fn with_path(mut self, file_path: &Path) -> Self {
+        match &mut self {
+            Error::TomlRead { path, .. }
+            | Error::TomlEdit { path, .. }
+            | Error::YamlRead { path, .. }
+            | Error::InvalidRoot { path, .. } => *path = Some(file_path.to_path_buf()),
+            #[cfg(feature = "json")]
+            Error::JsonRead { path, .. } => *path = Some(file_path.to_path_buf()),
+            _ => {}
+        }
+        self
+    }
+    /// Moves a parse error's span to `line`, for input parsed one line at a time.
+    #[cfg(feature = "audit")]
+    pub(crate) fn at_line(mut self, line: usize) -> Self {
+        if let Error::JsonRead {
+            span: Some(span), ..
+        } = &mut self
+        {
+            span.line = line;
+        }
+        self
+    }
+    /// The line and column a parse error points at, if known.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::TomlRead { span, .. }
+            | Error::TomlEdit { span, .. }
+            | Error::YamlRead { span, .. } => *span,
+            #[cfg(feature = "json")]
+            Error::JsonRead { span, .. } => *span,
+            _ => None,
+        }
+    }
+    /// The file an error relates to, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::TomlRead { path, .. }
+            | Error::TomlEdit { path, .. }
+            | Error::YamlRead { path, .. }
+            | Error::InvalidRoot { path, .. } => path.as_deref(),
+            #[cfg(feature = "json")]
+            Error::JsonRead { path, .. } => path.as_deref(),
+            Error::FileSystem { path, .. }
+            | Error::LibraNotFound { path, .. }
+            | Error::LibraAlreadyExists { path, .. }
+            | Error::AuditChainBroken { path, .. } => Some(path),
+            Error::FileExists(path) | Error::FileNotFound(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(any(feature = "write", feature = "address"))]
+impl Error {
+    pub(crate) fn reqwest(method: reqwest::Method, url: &str, source: reqwest::Error) -> Self {
+        Error::Reqwest {
+            method,
+            url: url.to_string(),
+            source,
+        }
+    }
+    /// Builds an `Error::Backend` from an unexpected response, keeping its body for context.
+    pub(crate) fn backend(
+        method: reqwest::Method,
+        url: &str,
+        response: reqwest::blocking::Response,
+    ) -> Self {
+        let status = response.status();
+        Error::Backend {
+            method,
+            url: url.to_string(),
+            status,
+            body: response.text().unwrap_or_default(),
+        }
+    }
+    #[cfg(feature = "write")]
+    pub(crate) async fn backend_async(
+        method: reqwest::Method,
+        url: &str,
+        response: reqwest::Response,
+    ) -> Self {
+        let status = response.status();
+        Error::Backend {
+            method,
+            url: url.to_string(),
+            status,
+            body: response.text().await.unwrap_or_default(),
+        }
+    }
+}
+
+/// A 1-based line and column in a config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+impl Span {
+    fn from_offset(input: &str, offset: usize) -> Self {
+        let before = &input[..offset.min(input.len())];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Renders ` in {path}:{line}:{column}`, leaving out whatever is unknown.
+struct Location<'a>(&'a Option<PathBuf>, &'a Option<Span>);
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.0, self.1) {
+            (Some(path), Some(span)) => write!(f, " in {}:{span}", path.display()),
+            (Some(path), None) => write!(f, " in {}", path.display()),
+            (None, Some(span)) => write!(f, " at {span}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+    use crate::libra::Libra;
+
+    #[test]
+    fn test_toml_read_span() {
+        let input = "[a]\nb = 1\nc = [\n";
+        let error = Format::Toml.deserialize::<toml::Value>(input).unwrap_err();
+        assert_eq!(error.code(), "toml_read");
+        assert_eq!(error.span(), Some(Span { line: 4, column: 1 }));
+        let error = error.with_path(Path::new("scale.toml"));
+        assert_eq!(error.path(), Some(Path::new("scale.toml")));
+        assert!(
+            error
+                .to_string()
+                .starts_with("Error reading TOML in scale.toml:4:1: ")
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_read_span() {
+        let error = Format::Json
+            .deserialize::<Libra>("{\n  \"config\": 1\n}")
+            .unwrap_err();
+        assert_eq!(error.code(), "json_read");
+        assert_eq!(error.span().map(|span| span.line), Some(2));
+    }
+
+    #[test]
+    fn test_yaml_read_span() {
+        let error = Format::Yaml
+            .deserialize::<Libra>("config:\n  gain: [\n")
+            .unwrap_err();
+        assert_eq!(error.code(), "yaml_read");
+        assert!(error.span().is_some());
+    }
+
+    #[test]
+    fn test_span_from_offset_counts_characters() {
+        assert_eq!(Span::from_offset("", 0), Span { line: 1, column: 1 });
+        assert_eq!(
+            Span::from_offset("é = 1\nab", 9),
+            Span { line: 2, column: 3 }
+        );
+    }
 }
@@ -0,0 +1,250 @@
+use crate::error::Error;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Format {
+    #[default]
+    Toml,
+    #[cfg(feature = "json")]
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// Picks a format from the file extension, or `None` if the extension is missing or unknown.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "toml" => Some(Format::Toml),
+            #[cfg(feature = "json")]
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+    /// Like [`Format::from_extension`], but falls back to TOML, the crate's native format.
+    pub fn from_path(path: &Path) -> Self {
+        Self::from_extension(path).unwrap_or_default()
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            #[cfg(feature = "json")]
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+        }
+    }
+    pub fn deserialize<T: DeserializeOwned>(&self, input: &str) -> Result<T, Error> {
+        match self {
+            Format::Toml => toml::from_str(input).map_err(|e| Error::toml_read(input, e)),
+            #[cfg(feature = "json")]
+            Format::Json => serde_json::from_str(input).map_err(Error::json_read),
+            Format::Yaml => serde_yaml::from_str(input).map_err(Error::yaml_read),
+        }
+    }
+    pub fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<String, Error> {
+        match self {
+            Format::Toml => toml::to_string(value).map_err(Error::TomlGeneration),
+            #[cfg(feature = "json")]
+            Format::Json => serde_json::to_string_pretty(value).map_err(Error::JsonGeneration),
+            Format::Yaml => serde_yaml::to_string(value).map_err(Error::YamlGeneration),
+        }
+    }
+    /// Deserializes a document whose root is a table of entries, such as a multi-Libra config file.
+    pub fn deserialize_tables<T: DeserializeOwned>(&self, input: &str) -> Result<Vec<T>, Error> {
+        match self.deserialize::<BTreeMap<String, T>>(input) {
+            Ok(tables) => Ok(tables.into_values().collect()),
+            Err(_) if self.has_non_table_root(input) => Err(Error::InvalidRoot {
+                path: None,
+                format: *self,
+            }),
+            Err(error) => Err(error),
+        }
+    }
+    /// Whether `input` parses, but not into a table. A TOML root is always a table.
+    fn has_non_table_root(&self, input: &str) -> bool {
+        match self {
+            Format::Toml => false,
+            #[cfg(feature = "json")]
+            Format::Json => serde_json::from_str::<serde_json::Value>(input)
+                .is_ok_and(|value| !value.is_object()),
+            Format::Yaml => serde_yaml::from_str::<serde_yaml::Value>(input)
+                .is_ok_and(|value| !value.is_mapping()),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Toml => write!(f, "TOML"),
+            #[cfg(feature = "json")]
+            Format::Json => write!(f, "JSON"),
+            Format::Yaml => write!(f, "YAML"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "toml" => Ok(Format::Toml),
+            #[cfg(feature = "json")]
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            _ => Err(format!("Invalid format: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ichibu::{Ichibu, ScaleConfig};
+    use crate::libra::Libra;
+    use std::path::PathBuf;
+
+    #[cfg(feature = "json")]
+    const FORMATS: [Format; 3] = [Format::Toml, Format::Json, Format::Yaml];
+    #[cfg(not(feature = "json"))]
+    const FORMATS: [Format; 2] = [Format::Toml, Format::Yaml];
+
+    const ICHIBU: &str = r#"
+        [device]
+        model = "IchibuV2"
+        serial_number = "I0"
+
+        [conveyor_motor]
+        id = 0
+        scale = 800
+        acceleration = 1.25
+        velocity = 0.5
+
+        [hatch]
+        open_input = 1
+        close_input = 2
+        motor = { id = 0, scale = 800, acceleration = 1.25, velocity = 0.5 }
+
+        [photo_eye]
+        input = 0
+        sample_number = 25
+        sample_period = 50
+
+        [pins]
+        manager = "2744"
+        operator = "200"
+        sudo = "101010101"
+
+        [dispense]
+        timeout = 0
+
+        [setpoint]
+        empty = 100
+        filling_threshold = 500
+    "#;
+
+    fn round_trip<T: Serialize + DeserializeOwned>(value: &T) {
+        let expected = serde_json::to_value(value).unwrap();
+        for format in FORMATS {
+            let serialized = format.serialize(value).unwrap();
+            let deserialized: T = format.deserialize(&serialized).unwrap();
+            let actual = serde_json::to_value(&deserialized).unwrap();
+            assert_eq!(actual, expected, "{format} round trip changed the value");
+        }
+    }
+
+    #[test]
+    fn test_from_extension() {
+        assert_eq!(
+            Format::from_extension(&PathBuf::from("scale.toml")),
+            Some(Format::Toml)
+        );
+        #[cfg(feature = "json")]
+        assert_eq!(
+            Format::from_extension(&PathBuf::from("scale.JSON")),
+            Some(Format::Json)
+        );
+        assert_eq!(
+            Format::from_extension(&PathBuf::from("scale.yml")),
+            Some(Format::Yaml)
+        );
+        assert_eq!(Format::from_extension(&PathBuf::from("scale.txt")), None);
+        assert_eq!(Format::from_path(&PathBuf::from("scale")), Format::Toml);
+    }
+
+    #[test]
+    fn test_libra_round_trip() {
+        let mut libra = Libra::default();
+        // Parses one ULP off without serde_json's `float_roundtrip`.
+        libra.config.gain = 180.17933438838418;
+        libra.config.offset = -1.0e-7;
+        round_trip(&libra);
+    }
+
+    #[test]
+    fn test_ichibu_round_trip() {
+        let ichibu: Ichibu = Format::Toml.deserialize(ICHIBU).unwrap();
+        round_trip(&ichibu);
+    }
+
+    #[test]
+    fn test_scale_config_round_trip() {
+        let mut scale_config: ScaleConfig = Format::Toml
+            .deserialize(include_str!("../scale.toml"))
+            .unwrap();
+        round_trip(&scale_config);
+        scale_config.update_coefficients([0.5, -1.25, 3.0e-3, 1.0 / 3.0]);
+        round_trip(&scale_config);
+    }
+
+    #[test]
+    fn test_deserialize_tables_rejects_non_table_root() {
+        let result = Format::Yaml.deserialize_tables::<Libra>("- 1");
+        assert!(matches!(
+            result,
+            Err(Error::InvalidRoot {
+                format: Format::Yaml,
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_deserialize_json_tables() {
+        let result = Format::Json.deserialize_tables::<Libra>("[]");
+        assert!(matches!(
+            result,
+            Err(Error::InvalidRoot {
+                format: Format::Json,
+                ..
+            })
+        ));
+        let result = Format::Json.deserialize_tables::<Libra>("{");
+        assert!(matches!(result, Err(Error::JsonRead { .. })));
+    }
+
+    #[test]
+    fn test_format_specific_errors() {
+        assert!(matches!(
+            Format::Toml.deserialize::<Libra>("not = [valid"),
+            Err(Error::TomlRead { .. })
+        ));
+        #[cfg(feature = "json")]
+        assert!(matches!(
+            Format::Json.deserialize::<Libra>("{"),
+            Err(Error::JsonRead { .. })
+        ));
+        assert!(matches!(
+            Format::Yaml.deserialize::<Libra>("config: ["),
+            Err(Error::YamlRead { .. })
+        ));
+    }
+}
@@ -1,4 +1,7 @@
 use crate::error::Error;
+use crate::format::Format;
+use crate::snapshot;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::fs::File;
@@ -12,25 +15,53 @@ pub trait Generate<'de> {
         Self: Sized,
         Self: Deserialize<'de>,
     {
-        serde_json::from_str(json).map_err(Error::SerdeJson)
+        serde_json::from_str(json).map_err(Error::json_read)
+    }
+    fn from_str_as(input: &str, format: Format) -> Result<Self, Error>
+    where
+        Self: Sized,
+        Self: DeserializeOwned,
+    {
+        format.deserialize(input)
+    }
+    fn to_string_as(&self, format: Format) -> Result<String, Error>
+    where
+        Self: Serialize,
+    {
+        format.serialize(self)
     }
     fn to_toml_string(&self) -> Result<String, Error>
     where
         Self: Serialize,
     {
-        toml::to_string(self).map_err(Error::TomlGeneration)
+        self.to_string_as(Format::Toml)
     }
     fn generate_toml(self, file_path: &Path) -> Result<(), Error>
+    where
+        Self: Sized,
+        Self: Serialize,
+    {
+        self.generate_with_format(file_path, Format::Toml)
+    }
+    /// Creates a new config file, picking the format from the file extension.
+    fn generate(self, file_path: &Path) -> Result<(), Error>
+    where
+        Self: Sized,
+        Self: Serialize,
+    {
+        self.generate_with_format(file_path, Format::from_path(file_path))
+    }
+    fn generate_with_format(self, file_path: &Path, format: Format) -> Result<(), Error>
     where
         Self: Sized,
         Self: Serialize,
     {
         if let Some(parent) = file_path.parent() {
-            fs::create_dir_all(parent).map_err(Error::FileSystem)?;
+            fs::create_dir_all(parent).map_err(|e| Error::file_system(file_path, e))?;
         }
-        let mut file = File::create_new(file_path).map_err(Error::FileSystem)?;
-        file.write_all(self.to_toml_string()?.as_bytes())
-            .map_err(Error::FileSystem)?;
+        let mut file = File::create_new(file_path).map_err(|e| Error::file_system(file_path, e))?;
+        file.write_all(self.to_string_as(format)?.as_bytes())
+            .map_err(|e| Error::file_system(file_path, e))?;
         Ok(())
     }
     fn overwrite_toml(self, file_path: &Path) -> Result<(), Error>
@@ -38,9 +69,25 @@ pub trait Generate<'de> {
         Self: Sized,
         Self: Serialize,
     {
-        let mut file = File::create(file_path).map_err(Error::FileSystem)?;
-        file.write_all(self.to_toml_string()?.as_bytes())
-            .map_err(Error::FileSystem)?;
+        self.overwrite_with_format(file_path, Format::Toml)
+    }
+    /// Overwrites a config file, picking the format from the file extension.
+    fn overwrite(self, file_path: &Path) -> Result<(), Error>
+    where
+        Self: Sized,
+        Self: Serialize,
+    {
+        self.overwrite_with_format(file_path, Format::from_path(file_path))
+    }
+    fn overwrite_with_format(self, file_path: &Path, format: Format) -> Result<(), Error>
+    where
+        Self: Sized,
+        Self: Serialize,
+    {
+        snapshot::before_write(file_path)?;
+        let mut file = File::create(file_path).map_err(|e| Error::file_system(file_path, e))?;
+        file.write_all(self.to_string_as(format)?.as_bytes())
+            .map_err(|e| Error::file_system(file_path, e))?;
         Ok(())
     }
     fn add_as_table(self, file_path: &Path, table_name: &str) -> Result<(), Error>
@@ -49,21 +96,28 @@ pub trait Generate<'de> {
         Self: Serialize,
     {
         if let Some(parent) = file_path.parent() {
-            fs::create_dir_all(parent).map_err(Error::FileSystem)?;
+            fs::create_dir_all(parent).map_err(|e| Error::file_system(file_path, e))?;
         }
+        snapshot::before_write(file_path)?;
         let mut file = std::fs::OpenOptions::new()
             .create(true)
             .append(true)
             .open(file_path)
-            .map_err(Error::FileSystem)?;
+            .map_err(|e| Error::file_system(file_path, e))?;
         let mut table_map = std::collections::BTreeMap::new();
         table_map.insert(table_name, self);
-        let toml_string = toml::to_string(&table_map).map_err(Error::TomlGeneration)?;
-        if file.metadata().map_err(Error::FileSystem)?.len() > 0 {
-            file.write_all(b"\n").map_err(Error::FileSystem)?;
+        let toml_string = Format::Toml.serialize(&table_map)?;
+        if file
+            .metadata()
+            .map_err(|e| Error::file_system(file_path, e))?
+            .len()
+            > 0
+        {
+            file.write_all(b"\n")
+                .map_err(|e| Error::file_system(file_path, e))?;
         }
         file.write_all(toml_string.as_bytes())
-            .map_err(Error::FileSystem)?;
+            .map_err(|e| Error::file_system(file_path, e))?;
 
         Ok(())
     }
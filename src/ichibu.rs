@@ -1,27 +1,400 @@
-use crate::device::Device;
+use crate::device::{Device, Model};
+use crate::error::Error;
 use crate::ichibu_items::*;
 use crate::read::Read;
-use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[cfg(feature = "write")]
-use crate::error::Error;
+use crate::audit::{Operation, audited, default_auditor};
+#[cfg(feature = "write")]
+use crate::diff::diff;
+#[cfg(feature = "write")]
+use crate::edit::TomlDocument;
+#[cfg(feature = "write")]
+use crate::format::Format;
 #[cfg(feature = "write")]
 use crate::generate::Generate;
+#[cfg(feature = "write")]
+use crate::libra::file_source;
+#[cfg(feature = "write")]
+use crate::pull::FromBackend;
+#[cfg(feature = "write")]
+use crate::snapshot;
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct Ichibu {
-    device: Option<Device>,
+/// Configuration of an Ichibu dispenser, in the shape its generation expects.
+///
+/// The generation is selected by `device.model` when loading. A config without a device
+/// is taken to be a V2 config if it has a `hatch` table, and a V1 config otherwise.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Ichibu {
+    V1(IchibuV1),
+    V2(IchibuV2),
+}
+/// Settings shared by every Ichibu generation.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct IchibuCore {
+    /// Motor driving the conveyor.
     conveyor_motor: Motor,
-    hatch: Hatch,
     photo_eye: PhotoEye,
     pins: Pins,
     dispense: Dispense,
     setpoint: Setpoint,
 }
+/// Configuration of a first generation dispenser, which has no hatch.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct IchibuV1 {
+    /// Identity of the dispenser, required before the config can be generated.
+    device: Option<Device>,
+    #[serde(flatten)]
+    core: IchibuCore,
+}
+/// Configuration of a second generation dispenser.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct IchibuV2 {
+    /// Identity of the dispenser, required before the config can be generated.
+    device: Option<Device>,
+    #[serde(flatten)]
+    core: IchibuCore,
+    hatch: Hatch,
+}
+
+/// A V1 config converted to V2, along with the dotted paths of the fields that V1 has no
+/// equivalent for and were filled with defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upgrade {
+    pub config: IchibuV2,
+    pub defaulted: Vec<String>,
+}
+
+/// The shipped `config.toml`.
+impl Default for Ichibu {
+    fn default() -> Self {
+        Ichibu::V2(IchibuV2::default())
+    }
+}
+/// The shipped `config.toml`, moved to `Model::IchibuV1` and without its hatch.
+impl Default for IchibuV1 {
+    fn default() -> Self {
+        Self {
+            device: Some(Device::new(Model::IchibuV1, "I0")),
+            core: IchibuCore::default(),
+        }
+    }
+}
+/// The shipped `config.toml`.
+impl Default for IchibuV2 {
+    fn default() -> Self {
+        Self {
+            device: Some(Device::new(Model::IchibuV2, "I0")),
+            core: IchibuCore::default(),
+            hatch: Hatch::default(),
+        }
+    }
+}
+
+impl IchibuCore {
+    pub fn conveyor_motor(&self) -> &Motor {
+        &self.conveyor_motor
+    }
+    pub fn photo_eye(&self) -> &PhotoEye {
+        &self.photo_eye
+    }
+    pub fn pins(&self) -> &Pins {
+        &self.pins
+    }
+    pub fn dispense(&self) -> &Dispense {
+        &self.dispense
+    }
+    pub fn setpoint(&self) -> &Setpoint {
+        &self.setpoint
+    }
+    // The parts' own setters keep them valid, so they can be handed out mutably.
+    pub fn conveyor_motor_mut(&mut self) -> &mut Motor {
+        &mut self.conveyor_motor
+    }
+    pub fn photo_eye_mut(&mut self) -> &mut PhotoEye {
+        &mut self.photo_eye
+    }
+    pub fn pins_mut(&mut self) -> &mut Pins {
+        &mut self.pins
+    }
+    pub fn dispense_mut(&mut self) -> &mut Dispense {
+        &mut self.dispense
+    }
+    pub fn setpoint_mut(&mut self) -> &mut Setpoint {
+        &mut self.setpoint
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        self.conveyor_motor.validate()?;
+        self.photo_eye.validate()?;
+        self.pins.validate()?;
+        self.setpoint.validate()
+    }
+}
+
+fn check_model(device: &Option<Device>, model: Model) -> Result<(), Error> {
+    match device {
+        Some(device) if device.model != model => Err(Error::validation(
+            "device.model",
+            format!("expected {model}, got {}", device.model),
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl IchibuV1 {
+    pub fn builder() -> IchibuV1Builder {
+        IchibuV1Builder::default()
+    }
+    pub fn device(&self) -> Option<&Device> {
+        self.device.as_ref()
+    }
+    pub fn core(&self) -> &IchibuCore {
+        &self.core
+    }
+    pub fn core_mut(&mut self) -> &mut IchibuCore {
+        &mut self.core
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        check_model(&self.device, Model::IchibuV1)?;
+        self.core.validate()
+    }
+}
+impl IchibuV2 {
+    pub fn builder() -> IchibuV2Builder {
+        IchibuV2Builder::default()
+    }
+    pub fn device(&self) -> Option<&Device> {
+        self.device.as_ref()
+    }
+    pub fn core(&self) -> &IchibuCore {
+        &self.core
+    }
+    pub fn core_mut(&mut self) -> &mut IchibuCore {
+        &mut self.core
+    }
+    pub fn hatch(&self) -> &Hatch {
+        &self.hatch
+    }
+    pub fn hatch_mut(&mut self) -> &mut Hatch {
+        &mut self.hatch
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        check_model(&self.device, Model::IchibuV2)?;
+        self.core.validate()?;
+        self.hatch.validate()
+    }
+}
+
+/// Builds an [`IchibuV1`], starting from its defaults.
+#[derive(Debug, Clone, Default)]
+pub struct IchibuV1Builder {
+    ichibu: IchibuV1,
+}
+impl IchibuV1Builder {
+    /// Sets the device, or clears it with `None`.
+    pub fn device(mut self, device: Option<Device>) -> Self {
+        self.ichibu.device = device;
+        self
+    }
+    pub fn conveyor_motor(mut self, conveyor_motor: Motor) -> Self {
+        self.ichibu.core.conveyor_motor = conveyor_motor;
+        self
+    }
+    pub fn photo_eye(mut self, photo_eye: PhotoEye) -> Self {
+        self.ichibu.core.photo_eye = photo_eye;
+        self
+    }
+    pub fn pins(mut self, pins: Pins) -> Self {
+        self.ichibu.core.pins = pins;
+        self
+    }
+    pub fn dispense(mut self, dispense: Dispense) -> Self {
+        self.ichibu.core.dispense = dispense;
+        self
+    }
+    pub fn setpoint(mut self, setpoint: Setpoint) -> Self {
+        self.ichibu.core.setpoint = setpoint;
+        self
+    }
+    pub fn build(self) -> Result<IchibuV1, Error> {
+        self.ichibu.validate()?;
+        Ok(self.ichibu)
+    }
+}
+/// Builds an [`IchibuV2`], starting from its defaults.
+#[derive(Debug, Clone, Default)]
+pub struct IchibuV2Builder {
+    ichibu: IchibuV2,
+}
+impl IchibuV2Builder {
+    /// Sets the device, or clears it with `None`.
+    pub fn device(mut self, device: Option<Device>) -> Self {
+        self.ichibu.device = device;
+        self
+    }
+    pub fn conveyor_motor(mut self, conveyor_motor: Motor) -> Self {
+        self.ichibu.core.conveyor_motor = conveyor_motor;
+        self
+    }
+    pub fn hatch(mut self, hatch: Hatch) -> Self {
+        self.ichibu.hatch = hatch;
+        self
+    }
+    pub fn photo_eye(mut self, photo_eye: PhotoEye) -> Self {
+        self.ichibu.core.photo_eye = photo_eye;
+        self
+    }
+    pub fn pins(mut self, pins: Pins) -> Self {
+        self.ichibu.core.pins = pins;
+        self
+    }
+    pub fn dispense(mut self, dispense: Dispense) -> Self {
+        self.ichibu.core.dispense = dispense;
+        self
+    }
+    pub fn setpoint(mut self, setpoint: Setpoint) -> Self {
+        self.ichibu.core.setpoint = setpoint;
+        self
+    }
+    pub fn build(self) -> Result<IchibuV2, Error> {
+        self.ichibu.validate()?;
+        Ok(self.ichibu)
+    }
+}
+
+impl Ichibu {
+    pub fn device(&self) -> Option<&Device> {
+        match self {
+            Ichibu::V1(ichibu) => ichibu.device.as_ref(),
+            Ichibu::V2(ichibu) => ichibu.device.as_ref(),
+        }
+    }
+    pub fn core(&self) -> &IchibuCore {
+        match self {
+            Ichibu::V1(ichibu) => &ichibu.core,
+            Ichibu::V2(ichibu) => &ichibu.core,
+        }
+    }
+    pub fn core_mut(&mut self) -> &mut IchibuCore {
+        match self {
+            Ichibu::V1(ichibu) => &mut ichibu.core,
+            Ichibu::V2(ichibu) => &mut ichibu.core,
+        }
+    }
+    /// The hatch, which only V2 dispensers have.
+    pub fn hatch(&self) -> Option<&Hatch> {
+        match self {
+            Ichibu::V1(_) => None,
+            Ichibu::V2(ichibu) => Some(&ichibu.hatch),
+        }
+    }
+    pub fn hatch_mut(&mut self) -> Option<&mut Hatch> {
+        match self {
+            Ichibu::V1(_) => None,
+            Ichibu::V2(ichibu) => Some(&mut ichibu.hatch),
+        }
+    }
+    /// Checks every setting and that the device belongs to this generation, e.g. after
+    /// loading a config file.
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            Ichibu::V1(ichibu) => ichibu.validate(),
+            Ichibu::V2(ichibu) => ichibu.validate(),
+        }
+    }
+    /// Converts to a V2 config. V2 configs are returned unchanged.
+    pub fn upgrade(self) -> Upgrade {
+        match self {
+            Ichibu::V1(ichibu) => ichibu.upgrade(),
+            Ichibu::V2(config) => Upgrade {
+                config,
+                defaulted: Vec::new(),
+            },
+        }
+    }
+}
+
+impl IchibuV1 {
+    /// Converts to a V2 config, giving the new hardware its default settings and moving the
+    /// device over to `Model::IchibuV2`.
+    pub fn upgrade(self) -> Upgrade {
+        let hatch = Hatch::default();
+        let mut defaulted = Vec::new();
+        if let Ok(value) = serde_yaml::to_value(&hatch) {
+            leaf_paths("hatch", &value, &mut defaulted);
+        }
+        defaulted.sort();
+        let device = self.device.map(|device| Device {
+            model: Model::IchibuV2,
+            ..device
+        });
+        Upgrade {
+            config: IchibuV2 {
+                device,
+                core: self.core,
+                hatch,
+            },
+            defaulted,
+        }
+    }
+}
+
+fn leaf_paths(path: &str, value: &serde_yaml::Value, paths: &mut Vec<String>) {
+    match value {
+        serde_yaml::Value::Mapping(map) => {
+            for (key, value) in map {
+                let key = key.as_str().unwrap_or_default();
+                leaf_paths(&format!("{path}.{key}"), value, paths);
+            }
+        }
+        _ => paths.push(path.to_string()),
+    }
+}
+
+impl<'de> Deserialize<'de> for Ichibu {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Any self-describing value would do; YAML's, unlike TOML's, has a null.
+        let value = serde_yaml::Value::deserialize(deserializer)?;
+        let model = match value.get("device") {
+            Some(device) if !device.is_null() => {
+                Some(Model::deserialize(&device["model"]).map_err(D::Error::custom)?)
+            }
+            _ => None,
+        };
+        let is_v2 = match model {
+            Some(Model::IchibuV1) => false,
+            Some(Model::IchibuV2) => true,
+            Some(model) => {
+                return Err(D::Error::custom(format!("{model} is not an Ichibu model")));
+            }
+            None => value.get("hatch").is_some(),
+        };
+        if is_v2 {
+            IchibuV2::deserialize(value).map(Ichibu::V2)
+        } else {
+            IchibuV1::deserialize(value).map(Ichibu::V1)
+        }
+        .map_err(D::Error::custom)
+    }
+}
 impl Read for Ichibu {}
-#[derive(Deserialize, Serialize, Debug)]
+#[cfg(feature = "write")]
+impl FromBackend for Ichibu {}
+/// Load cell settings of a standalone scale.
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ScaleConfig {
+    /// Serial number of the Phidget bridge the load cell is wired to.
+    #[cfg_attr(feature = "schema", schemars(range(min = 0)))]
     phidget_id: isize,
+    /// Calibration coefficients, absent until the scale has been calibrated.
     coefficients: Option<[f64; 4]>,
 }
 impl ScaleConfig {
@@ -35,14 +408,259 @@ impl ScaleConfig {
 
 #[cfg(feature = "write")]
 impl Generate<'_> for Ichibu {
-    fn to_toml_string(&self) -> Result<String, Error> {
-        if self.device.is_none() {
+    fn to_string_as(&self, format: Format) -> Result<String, Error> {
+        if self.device().is_none() {
             return Err(Error::NoSerialNumber);
         }
-        toml::to_string(self).map_err(Error::TomlGeneration)
+        format.serialize(self)
+    }
+}
+#[cfg(all(feature = "write", feature = "pins"))]
+impl Ichibu {
+    /// Hashes any plaintext PINs left in an existing config file. TOML files only have their
+    /// `[pins]` table rewritten. Returns whether anything changed. Recorded with the
+    /// [default auditor](crate::audit::set_default_auditor), if set.
+    ///
+    /// The file isn't snapshotted, since the snapshot would keep the plaintext PINs, and
+    /// PINs left in plaintext in its existing snapshots are hashed too.
+    pub fn migrate_pins_in_file(file_path: &std::path::Path) -> Result<bool, Error> {
+        let old = Ichibu::read(file_path)?;
+        let mut ichibu = old.clone();
+        let migrated = ichibu.core_mut().pins_mut().migrate()?;
+        if migrated {
+            audited(
+                default_auditor().as_ref(),
+                ichibu.device(),
+                Operation::Edit,
+                file_source(file_path),
+                diff(&old, &ichibu)?,
+                || ichibu.write_pins(file_path),
+            )?;
+        }
+        for snapshot in snapshot::Snapshots::new(file_path).list()? {
+            let mut ichibu = Ichibu::read(&snapshot.path)?;
+            if ichibu.core_mut().pins_mut().migrate()? {
+                ichibu.write_pins(&snapshot.path)?;
+            }
+        }
+        Ok(migrated)
+    }
+    /// Writes these PINs over the ones in `file_path`, without a snapshot.
+    fn write_pins(&self, file_path: &std::path::Path) -> Result<(), Error> {
+        let format = Format::from_path(file_path);
+        if format == Format::Toml {
+            let mut document = TomlDocument::open(file_path)?;
+            document.merge(Some("pins"), self.core().pins())?;
+            document.save_without_snapshot()
+        } else {
+            std::fs::write(file_path, format.serialize(self)?)
+                .map_err(|e| Error::file_system(file_path, e))
+        }
     }
 }
 #[cfg(feature = "write")]
-impl Generate<'_> for ScaleConfig {}
+impl ScaleConfig {
+    /// Writes these settings back to an existing scale file, e.g. after `update_coefficients`.
+    /// TOML files keep their comments and layout; only changed keys are rewritten. Recorded
+    /// with the [default auditor](crate::audit::set_default_auditor), if set.
+    pub fn edit_config_file(&self, file_path: &std::path::Path) -> Result<(), Error> {
+        if !file_path.exists() {
+            return Err(Error::FileNotFound(file_path.to_path_buf()));
+        }
+        let auditor = default_auditor();
+        let changes = match &auditor {
+            Some(_) => diff(&ScaleConfig::read(file_path)?, self)?,
+            None => Vec::new(),
+        };
+        audited(
+            auditor.as_ref(),
+            None,
+            Operation::Edit,
+            file_source(file_path),
+            changes,
+            || {
+                let format = Format::from_path(file_path);
+                if format == Format::Toml {
+                    let mut document = TomlDocument::open(file_path)?;
+                    document.merge(None, self)?;
+                    return document.save();
+                }
+                snapshot::before_write(file_path)?;
+                std::fs::write(file_path, self.to_string_as(format)?)
+                    .map_err(|e| Error::file_system(file_path, e))
+            },
+        )
+    }
+}
 #[cfg(feature = "write")]
+impl Generate<'_> for ScaleConfig {}
 impl Read for ScaleConfig {}
+#[cfg(feature = "write")]
+impl FromBackend for ScaleConfig {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Format;
+
+    const CONFIG: &str = include_str!("../config.toml");
+
+    fn v1_config() -> String {
+        let start = CONFIG.find("[hatch]").unwrap();
+        let end = CONFIG.find("[photo_eye]").unwrap();
+        format!("{}{}", &CONFIG[..start], &CONFIG[end..]).replace("IchibuV2", "IchibuV1")
+    }
+
+    #[test]
+    fn test_generation_selected_by_model() {
+        let ichibu: Ichibu = Format::Toml.deserialize(CONFIG).unwrap();
+        assert!(matches!(ichibu, Ichibu::V2(_)));
+        assert!(ichibu.hatch().is_some());
+
+        let ichibu: Ichibu = Format::Toml.deserialize(&v1_config()).unwrap();
+        assert!(matches!(ichibu, Ichibu::V1(_)));
+        assert_eq!(ichibu.device().unwrap().model, Model::IchibuV1);
+
+        let error = Format::Toml
+            .deserialize::<Ichibu>(&CONFIG.replace("IchibuV2", "LibraV0"))
+            .unwrap_err();
+        assert!(error.to_string().contains("LibraV0 is not an Ichibu model"));
+    }
+
+    #[test]
+    fn test_v2_config_requires_hatch() {
+        let v2_without_hatch = v1_config().replace("IchibuV1", "IchibuV2");
+        assert!(
+            Format::Toml
+                .deserialize::<Ichibu>(&v2_without_hatch)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_upgrade_reports_defaulted_fields() {
+        let ichibu: Ichibu = Format::Toml.deserialize(&v1_config()).unwrap();
+        let upgrade = ichibu.upgrade();
+        assert_eq!(
+            upgrade.defaulted,
+            [
+                "hatch.close_input",
+                "hatch.motor.acceleration",
+                "hatch.motor.id",
+                "hatch.motor.scale",
+                "hatch.motor.velocity",
+                "hatch.open_input",
+            ]
+        );
+        let upgraded = Ichibu::V2(upgrade.config);
+        assert_eq!(upgraded.device().unwrap().model, Model::IchibuV2);
+        let shipped: Ichibu = Format::Toml.deserialize(CONFIG).unwrap();
+        assert_eq!(
+            serde_json::to_value(&upgraded).unwrap(),
+            serde_json::to_value(&shipped).unwrap()
+        );
+
+        assert!(shipped.upgrade().defaulted.is_empty());
+    }
+
+    #[test]
+    fn test_default_matches_shipped_config() {
+        let shipped: Ichibu = Format::Toml.deserialize(CONFIG).unwrap();
+        assert_eq!(shipped, Ichibu::default());
+        shipped.validate().unwrap();
+
+        let v1: Ichibu = Format::Toml.deserialize(&v1_config()).unwrap();
+        assert_eq!(v1, Ichibu::V1(IchibuV1::default()));
+    }
+
+    #[test]
+    fn test_builder_checks_generation() {
+        let ichibu = IchibuV2::builder()
+            .dispense(Dispense::new(30))
+            .build()
+            .unwrap();
+        assert_eq!(ichibu.core().dispense().timeout(), 30);
+
+        let error = IchibuV1::builder()
+            .device(Some(Device::new(Model::IchibuV2, "I1")))
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid device.model: expected IchibuV1, got IchibuV2"
+        );
+    }
+
+    #[cfg(feature = "pins")]
+    #[test]
+    fn test_builder_hashes_pins() {
+        let ichibu = IchibuV2::builder()
+            .pins(Pins::builder().operator("4321").build().unwrap())
+            .build()
+            .unwrap();
+        assert!(ichibu.core().pins().operator().verify("4321"));
+    }
+
+    #[cfg(all(feature = "write", feature = "pins"))]
+    #[test]
+    fn test_migrate_pins_in_file() {
+        let dir = std::env::temp_dir().join("menu-test-migrate_pins");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let start = CONFIG.find("[pins]").unwrap();
+        let end = CONFIG.find("[dispense]").unwrap();
+        let original = format!(
+            "{}# Set at install\n[pins]\nmanager = \"2744\"\noperator = \"200\"\nsudo = \"101010101\"\n\n{}",
+            &CONFIG[..start],
+            &CONFIG[end..]
+        );
+        std::fs::write(&path, &original).unwrap();
+        // An earlier edit left the plaintext PINs in a snapshot.
+        snapshot::Snapshots::new(&path).take().unwrap();
+
+        assert!(Ichibu::migrate_pins_in_file(&path).unwrap());
+        assert!(!Ichibu::migrate_pins_in_file(&path).unwrap());
+        let content = std::fs::read_to_string(&path).unwrap();
+        let mut leaked = Vec::new();
+        let mut dirs = vec![dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if ["\"2744\"", "\"200\"", "\"101010101\""]
+                    .iter()
+                    .any(|pin| std::fs::read_to_string(&path).unwrap().contains(pin))
+                {
+                    leaked.push(path);
+                }
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(leaked.is_empty(), "plaintext PINs left in {leaked:?}");
+        assert!(content.contains("# Set at install\n[pins]\nmanager = \"$argon2id$"));
+        let ichibu: Ichibu = Format::Toml.deserialize(&content).unwrap();
+        let pins = ichibu.core().pins();
+        assert_eq!(pins.verify("2744"), Some(crate::pin::AccessLevel::Manager));
+        assert_eq!(
+            content.split("[pins]").next(),
+            original.split("[pins]").next()
+        );
+    }
+
+    #[test]
+    fn test_edit_through_accessors() {
+        let mut ichibu = Ichibu::default();
+        ichibu
+            .core_mut()
+            .setpoint_mut()
+            .set_filling_threshold(650.)
+            .unwrap();
+        ichibu.hatch_mut().unwrap().set_inputs(3, 4).unwrap();
+        assert_eq!(ichibu.core().setpoint().filling_threshold(), 650.);
+        assert_eq!(ichibu.hatch().unwrap().open_input(), 3);
+        assert_ne!(ichibu, Ichibu::default());
+    }
+}
@@ -1,37 +1,578 @@
+use crate::error::Error;
+use crate::pin::Pin;
+#[cfg(feature = "pins")]
+use crate::pin::{AccessLevel, Lockout, Params, PinMode, check_digits};
 use serde::{Deserialize, Serialize};
 
 pub type Io = usize;
-#[derive(Deserialize, Serialize, Debug)]
+/// A stepper motor driven through the motor controller.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Motor {
+    /// Motor controller channel.
     id: Io,
+    /// Steps per revolution.
+    #[cfg_attr(feature = "schema", schemars(range(min = 1)))]
     scale: usize,
+    /// Acceleration in revolutions per second squared.
+    #[cfg_attr(feature = "schema", schemars(range(min = 0.0)))]
     acceleration: f64,
+    /// Velocity in revolutions per second.
+    #[cfg_attr(feature = "schema", schemars(range(min = 0.0)))]
     velocity: f64,
 }
-#[derive(Deserialize, Serialize, Debug)]
+/// Dispensing behaviour.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Dispense {
+    /// Seconds before a dispense is abandoned, or 0 to wait indefinitely.
     timeout: usize,
 }
-#[derive(Deserialize, Serialize, Debug)]
+/// Weight thresholds, in grams.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Setpoint {
+    /// Weight at or below which the dispenser counts as empty.
+    #[cfg_attr(feature = "schema", schemars(range(min = 0.0)))]
     empty: f64,
+    /// Weight above which the dispenser counts as being refilled.
+    #[cfg_attr(feature = "schema", schemars(range(min = 0.0)))]
     filling_threshold: f64,
 }
-#[derive(Deserialize, Serialize, Debug)]
+/// The hatch and the limit switches reporting its position.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Hatch {
+    /// Motor opening and closing the hatch.
     motor: Motor,
+    /// Digital input that is high while the hatch is open.
     open_input: Io,
+    /// Digital input that is high while the hatch is closed.
     close_input: Io,
 }
-#[derive(Deserialize, Serialize, Debug)]
+/// The photo eye detecting objects on the conveyor.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PhotoEye {
+    /// Digital input the photo eye is wired to.
     input: Io,
+    /// Number of samples debounced into one reading.
+    #[cfg_attr(feature = "schema", schemars(range(min = 1)))]
     sample_number: usize,
+    /// Milliseconds between samples.
+    #[cfg_attr(feature = "schema", schemars(range(min = 1)))]
     sample_period: usize,
 }
-#[derive(Deserialize, Serialize, Debug)]
+/// PINs unlocking each access level on the dispenser.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Pins {
-    manager: String,
-    operator: String,
-    sudo: String,
+    manager: Pin,
+    operator: Pin,
+    sudo: Pin,
+}
+
+// Defaults match the shipped `config.toml`.
+
+impl Default for Motor {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            scale: 800,
+            acceleration: 1.25,
+            velocity: 0.5,
+        }
+    }
+}
+impl Default for Setpoint {
+    fn default() -> Self {
+        Self {
+            empty: 100.,
+            filling_threshold: 500.,
+        }
+    }
+}
+impl Default for Hatch {
+    fn default() -> Self {
+        Self {
+            motor: Motor::default(),
+            open_input: 1,
+            close_input: 2,
+        }
+    }
+}
+impl Default for PhotoEye {
+    fn default() -> Self {
+        Self {
+            input: 0,
+            sample_number: 25,
+            sample_period: 50,
+        }
+    }
+}
+/// The shipped PINs: 2744 for managers, 200 for operators and 101010101 for sudo, stored
+/// only as hashes.
+impl Default for Pins {
+    fn default() -> Self {
+        Self {
+            manager: Pin::from_hash(
+                "$argon2id$v=19$m=19456,t=2,p=1$j8W/4VtSd0ivyaFJRUSmIg$xh3EN1Kdmqrxzv/nQgxi3lDyFp3jju3rF9yLZkvb4YI",
+            ),
+            operator: Pin::from_hash(
+                "$argon2id$v=19$m=19456,t=2,p=1$6Y0hu6OAzta9LPp3c8bzaQ$bA9HBiaRZ7tRPuk8+a/fJDRE4Lrh78aFw/tFhdWa5Rc",
+            ),
+            sudo: Pin::from_hash(
+                "$argon2id$v=19$m=19456,t=2,p=1$k8v1KfuNcJma1j6sbdv8Ew$YP7hjcios77WNdeWurplVeXa7yUuVUY2DFRFHOFCP8k",
+            ),
+        }
+    }
+}
+
+fn at_least(field: &'static str, value: usize, min: usize) -> Result<usize, Error> {
+    if value < min {
+        return Err(Error::validation(
+            field,
+            format!("{value} is less than {min}"),
+        ));
+    }
+    Ok(value)
+}
+fn non_negative(field: &'static str, value: f64) -> Result<f64, Error> {
+    if !value.is_finite() || value < 0. {
+        return Err(Error::validation(
+            field,
+            format!("{value} is not a finite, non-negative number"),
+        ));
+    }
+    Ok(value)
+}
+#[cfg(feature = "pins")]
+fn hashed(field: &'static str, pin: &str) -> Result<Pin, Error> {
+    check_digits(field, pin)?;
+    Pin::hash(pin)
+}
+
+impl Motor {
+    pub fn builder() -> MotorBuilder {
+        MotorBuilder::default()
+    }
+    pub fn id(&self) -> Io {
+        self.id
+    }
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+    pub fn acceleration(&self) -> f64 {
+        self.acceleration
+    }
+    pub fn velocity(&self) -> f64 {
+        self.velocity
+    }
+    pub fn set_id(&mut self, id: Io) {
+        self.id = id;
+    }
+    pub fn set_scale(&mut self, scale: usize) -> Result<(), Error> {
+        self.scale = at_least("motor.scale", scale, 1)?;
+        Ok(())
+    }
+    pub fn set_acceleration(&mut self, acceleration: f64) -> Result<(), Error> {
+        self.acceleration = non_negative("motor.acceleration", acceleration)?;
+        Ok(())
+    }
+    pub fn set_velocity(&mut self, velocity: f64) -> Result<(), Error> {
+        self.velocity = non_negative("motor.velocity", velocity)?;
+        Ok(())
+    }
+    /// Checks every field, e.g. after loading a config file.
+    pub fn validate(&self) -> Result<(), Error> {
+        at_least("motor.scale", self.scale, 1)?;
+        non_negative("motor.acceleration", self.acceleration)?;
+        non_negative("motor.velocity", self.velocity)?;
+        Ok(())
+    }
+}
+/// Builds a [`Motor`], starting from its defaults.
+#[derive(Debug, Clone, Default)]
+pub struct MotorBuilder {
+    motor: Motor,
+}
+impl MotorBuilder {
+    pub fn id(mut self, id: Io) -> Self {
+        self.motor.id = id;
+        self
+    }
+    pub fn scale(mut self, scale: usize) -> Self {
+        self.motor.scale = scale;
+        self
+    }
+    pub fn acceleration(mut self, acceleration: f64) -> Self {
+        self.motor.acceleration = acceleration;
+        self
+    }
+    pub fn velocity(mut self, velocity: f64) -> Self {
+        self.motor.velocity = velocity;
+        self
+    }
+    pub fn build(self) -> Result<Motor, Error> {
+        self.motor.validate()?;
+        Ok(self.motor)
+    }
+}
+
+impl Dispense {
+    pub fn new(timeout: usize) -> Self {
+        Self { timeout }
+    }
+    pub fn timeout(&self) -> usize {
+        self.timeout
+    }
+    pub fn set_timeout(&mut self, timeout: usize) {
+        self.timeout = timeout;
+    }
+}
+
+impl Setpoint {
+    pub fn builder() -> SetpointBuilder {
+        SetpointBuilder::default()
+    }
+    pub fn empty(&self) -> f64 {
+        self.empty
+    }
+    pub fn filling_threshold(&self) -> f64 {
+        self.filling_threshold
+    }
+    pub fn set_empty(&mut self, empty: f64) -> Result<(), Error> {
+        self.empty = non_negative("setpoint.empty", empty)?;
+        Ok(())
+    }
+    pub fn set_filling_threshold(&mut self, filling_threshold: f64) -> Result<(), Error> {
+        self.filling_threshold = non_negative("setpoint.filling_threshold", filling_threshold)?;
+        Ok(())
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        non_negative("setpoint.empty", self.empty)?;
+        non_negative("setpoint.filling_threshold", self.filling_threshold)?;
+        Ok(())
+    }
+}
+/// Builds a [`Setpoint`], starting from its defaults.
+#[derive(Debug, Clone, Default)]
+pub struct SetpointBuilder {
+    setpoint: Setpoint,
+}
+impl SetpointBuilder {
+    pub fn empty(mut self, empty: f64) -> Self {
+        self.setpoint.empty = empty;
+        self
+    }
+    pub fn filling_threshold(mut self, filling_threshold: f64) -> Self {
+        self.setpoint.filling_threshold = filling_threshold;
+        self
+    }
+    pub fn build(self) -> Result<Setpoint, Error> {
+        self.setpoint.validate()?;
+        Ok(self.setpoint)
+    }
+}
+
+impl Hatch {
+    pub fn builder() -> HatchBuilder {
+        HatchBuilder::default()
+    }
+    pub fn motor(&self) -> &Motor {
+        &self.motor
+    }
+    /// The motor's own setters keep it valid.
+    pub fn motor_mut(&mut self) -> &mut Motor {
+        &mut self.motor
+    }
+    pub fn open_input(&self) -> Io {
+        self.open_input
+    }
+    pub fn close_input(&self) -> Io {
+        self.close_input
+    }
+    pub fn set_inputs(&mut self, open_input: Io, close_input: Io) -> Result<(), Error> {
+        distinct_inputs(open_input, close_input)?;
+        self.open_input = open_input;
+        self.close_input = close_input;
+        Ok(())
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        self.motor.validate()?;
+        distinct_inputs(self.open_input, self.close_input)
+    }
+}
+fn distinct_inputs(open_input: Io, close_input: Io) -> Result<(), Error> {
+    if open_input == close_input {
+        return Err(Error::validation(
+            "hatch.close_input",
+            format!("input {close_input} is already the open input"),
+        ));
+    }
+    Ok(())
+}
+/// Builds a [`Hatch`], starting from its defaults.
+#[derive(Debug, Clone, Default)]
+pub struct HatchBuilder {
+    hatch: Hatch,
+}
+impl HatchBuilder {
+    pub fn motor(mut self, motor: Motor) -> Self {
+        self.hatch.motor = motor;
+        self
+    }
+    pub fn open_input(mut self, open_input: Io) -> Self {
+        self.hatch.open_input = open_input;
+        self
+    }
+    pub fn close_input(mut self, close_input: Io) -> Self {
+        self.hatch.close_input = close_input;
+        self
+    }
+    pub fn build(self) -> Result<Hatch, Error> {
+        self.hatch.validate()?;
+        Ok(self.hatch)
+    }
+}
+
+impl PhotoEye {
+    pub fn builder() -> PhotoEyeBuilder {
+        PhotoEyeBuilder::default()
+    }
+    pub fn input(&self) -> Io {
+        self.input
+    }
+    pub fn sample_number(&self) -> usize {
+        self.sample_number
+    }
+    pub fn sample_period(&self) -> usize {
+        self.sample_period
+    }
+    pub fn set_input(&mut self, input: Io) {
+        self.input = input;
+    }
+    pub fn set_sample_number(&mut self, sample_number: usize) -> Result<(), Error> {
+        self.sample_number = at_least("photo_eye.sample_number", sample_number, 1)?;
+        Ok(())
+    }
+    pub fn set_sample_period(&mut self, sample_period: usize) -> Result<(), Error> {
+        self.sample_period = at_least("photo_eye.sample_period", sample_period, 1)?;
+        Ok(())
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        at_least("photo_eye.sample_number", self.sample_number, 1)?;
+        at_least("photo_eye.sample_period", self.sample_period, 1)?;
+        Ok(())
+    }
+}
+/// Builds a [`PhotoEye`], starting from its defaults.
+#[derive(Debug, Clone, Default)]
+pub struct PhotoEyeBuilder {
+    photo_eye: PhotoEye,
+}
+impl PhotoEyeBuilder {
+    pub fn input(mut self, input: Io) -> Self {
+        self.photo_eye.input = input;
+        self
+    }
+    pub fn sample_number(mut self, sample_number: usize) -> Self {
+        self.photo_eye.sample_number = sample_number;
+        self
+    }
+    pub fn sample_period(mut self, sample_period: usize) -> Self {
+        self.photo_eye.sample_period = sample_period;
+        self
+    }
+    pub fn build(self) -> Result<PhotoEye, Error> {
+        self.photo_eye.validate()?;
+        Ok(self.photo_eye)
+    }
+}
+
+impl Pins {
+    #[cfg(feature = "pins")]
+    pub fn builder() -> PinsBuilder {
+        PinsBuilder::default()
+    }
+    pub fn manager(&self) -> &Pin {
+        &self.manager
+    }
+    pub fn operator(&self) -> &Pin {
+        &self.operator
+    }
+    pub fn sudo(&self) -> &Pin {
+        &self.sudo
+    }
+    #[cfg(feature = "pins")]
+    pub fn set_manager(&mut self, manager: &str) -> Result<(), Error> {
+        self.manager = hashed("pins.manager", manager)?;
+        Ok(())
+    }
+    #[cfg(feature = "pins")]
+    pub fn set_operator(&mut self, operator: &str) -> Result<(), Error> {
+        self.operator = hashed("pins.operator", operator)?;
+        Ok(())
+    }
+    #[cfg(feature = "pins")]
+    pub fn set_sudo(&mut self, sudo: &str) -> Result<(), Error> {
+        self.sudo = hashed("pins.sudo", sudo)?;
+        Ok(())
+    }
+    #[cfg(feature = "pins")]
+    /// The highest access level `pin` unlocks. Every level is checked, so the time taken
+    /// doesn't reveal which one matched. Plaintext PINs never match; see [`PinMode`].
+    pub fn verify(&self, pin: &str) -> Option<AccessLevel> {
+        self.verify_with(pin, PinMode::Strict)
+    }
+    #[cfg(feature = "pins")]
+    pub fn verify_with(&self, pin: &str, mode: PinMode) -> Option<AccessLevel> {
+        let matches = [
+            (AccessLevel::Sudo, self.sudo.verify_with(pin, mode)),
+            (AccessLevel::Manager, self.manager.verify_with(pin, mode)),
+            (AccessLevel::Operator, self.operator.verify_with(pin, mode)),
+        ];
+        matches
+            .into_iter()
+            .find_map(|(access_level, matched)| matched.then_some(access_level))
+    }
+    #[cfg(feature = "pins")]
+    /// Like [`Pins::verify`], counting failures against `lockout`.
+    pub fn unlock(&self, lockout: &mut Lockout, pin: &str) -> Result<AccessLevel, Error> {
+        lockout.verify(pin, |pin| self.verify(pin))
+    }
+    pub fn needs_migration(&self) -> bool {
+        [&self.manager, &self.operator, &self.sudo]
+            .iter()
+            .any(|pin| !pin.is_hashed())
+    }
+    #[cfg(feature = "pins")]
+    /// Hashes any plaintext PINs. Returns whether anything changed.
+    pub fn migrate(&mut self) -> Result<bool, Error> {
+        self.migrate_with(&Params::default())
+    }
+    #[cfg(feature = "pins")]
+    /// Like [`Pins::migrate`], with explicit Argon2id cost parameters.
+    pub fn migrate_with(&mut self, params: &Params) -> Result<bool, Error> {
+        let manager = self.manager.migrate_with(params)?;
+        let operator = self.operator.migrate_with(params)?;
+        let sudo = self.sudo.migrate_with(params)?;
+        Ok(manager || operator || sudo)
+    }
+    pub fn validate(&self) -> Result<(), Error> {
+        self.manager.validate("pins.manager")?;
+        self.operator.validate("pins.operator")?;
+        self.sudo.validate("pins.sudo")
+    }
+}
+#[cfg(feature = "pins")]
+/// Builds [`Pins`], starting from their defaults. Every PIN is hashed by `build`.
+#[derive(Debug, Clone, Default)]
+pub struct PinsBuilder {
+    pins: Pins,
+    params: Params,
+}
+#[cfg(feature = "pins")]
+impl PinsBuilder {
+    /// Argon2id cost parameters to hash with, [`Params::default`] unless set.
+    pub fn params(mut self, params: Params) -> Self {
+        self.params = params;
+        self
+    }
+    pub fn manager(mut self, manager: &str) -> Self {
+        self.pins.manager = Pin::plaintext(manager);
+        self
+    }
+    pub fn operator(mut self, operator: &str) -> Self {
+        self.pins.operator = Pin::plaintext(operator);
+        self
+    }
+    pub fn sudo(mut self, sudo: &str) -> Self {
+        self.pins.sudo = Pin::plaintext(sudo);
+        self
+    }
+    pub fn build(mut self) -> Result<Pins, Error> {
+        self.pins.validate()?;
+        self.pins.migrate_with(&self.params)?;
+        Ok(self.pins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "pins")]
+    use crate::pin::tests::fast;
+
+    #[test]
+    fn test_builders_validate() {
+        let motor = Motor::builder().id(3).velocity(2.0).build().unwrap();
+        assert_eq!((motor.id(), motor.scale(), motor.velocity()), (3, 800, 2.0));
+
+        let error = Motor::builder().scale(0).build().unwrap_err();
+        assert_eq!(error.code(), "validation");
+        assert_eq!(error.to_string(), "Invalid motor.scale: 0 is less than 1");
+
+        assert!(Setpoint::builder().empty(f64::NAN).build().is_err());
+        assert!(PhotoEye::builder().sample_period(0).build().is_err());
+        assert!(Hatch::builder().open_input(2).build().is_err());
+    }
+
+    #[test]
+    fn test_setters_reject_invalid_values() {
+        let mut hatch = Hatch::default();
+        hatch.motor_mut().set_acceleration(2.5).unwrap();
+        assert!(hatch.motor_mut().set_velocity(-1.0).is_err());
+        assert_eq!(hatch.motor().velocity(), 0.5);
+        assert!(hatch.set_inputs(4, 4).is_err());
+        hatch.set_inputs(4, 5).unwrap();
+        assert_eq!((hatch.open_input(), hatch.close_input()), (4, 5));
+    }
+
+    #[cfg(feature = "pins")]
+    #[test]
+    fn test_pins_validate() {
+        assert!(Pins::builder().sudo("12a4").build().is_err());
+        assert!(Pins::builder().operator("").build().is_err());
+
+        let mut pins = Pins::default();
+        assert!(pins.set_manager("12 34").is_err());
+        pins.set_manager("1234").unwrap();
+        assert!(pins.manager().is_hashed());
+        assert_eq!(pins.verify("1234"), Some(AccessLevel::Manager));
+    }
+
+    #[cfg(feature = "pins")]
+    #[test]
+    fn test_pins_verify_and_migrate() {
+        let defaults = Pins::default();
+        assert!(!defaults.needs_migration());
+        assert_eq!(defaults.verify("2744"), Some(AccessLevel::Manager));
+        assert_eq!(defaults.verify("200"), Some(AccessLevel::Operator));
+        assert_eq!(defaults.verify("101010101"), Some(AccessLevel::Sudo));
+        assert_eq!(defaults.verify("0000"), None);
+
+        let mut pins: Pins =
+            toml::from_str("manager = \"2744\"\noperator = \"200\"\nsudo = \"101010101\"").unwrap();
+        assert!(pins.needs_migration());
+        assert_eq!(pins.verify("200"), None);
+        assert_eq!(
+            pins.verify_with("200", PinMode::AllowPlaintext),
+            Some(AccessLevel::Operator)
+        );
+        assert!(pins.migrate_with(&fast()).unwrap());
+        assert!(!pins.needs_migration());
+        assert_eq!(pins.verify("200"), Some(AccessLevel::Operator));
+        assert!(!format!("{pins:?}").contains("2744"));
+
+        let shared = Pins::builder()
+            .params(fast())
+            .manager("1")
+            .sudo("1")
+            .build()
+            .unwrap();
+        assert_eq!(shared.verify("1"), Some(AccessLevel::Sudo));
+        let mut lockout = Lockout::new(2, std::time::Duration::from_secs(60));
+        assert_eq!(shared.unlock(&mut lockout, "1").unwrap(), AccessLevel::Sudo);
+    }
 }
@@ -1,11 +1,41 @@
 pub mod action;
+pub mod address;
+#[cfg(feature = "audit")]
+pub mod audit;
+#[cfg(feature = "json")]
+pub mod authorize;
 pub mod backend;
+#[cfg(feature = "bundle")]
+pub mod bundle;
 pub mod device;
+#[cfg(feature = "json")]
+pub mod diff;
+#[cfg(feature = "json")]
+pub mod discovery;
+#[cfg(feature = "write")]
+mod edit;
 pub mod error;
+pub mod format;
 #[cfg(feature = "write")]
 pub mod generate;
 pub mod ichibu;
 pub mod ichibu_items;
 pub mod libra;
 pub mod libra_data;
+pub mod model;
+pub mod pin;
+#[cfg(feature = "write")]
+pub mod pull;
 pub mod read;
+#[cfg(feature = "address")]
+pub mod reporter;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod secret;
+#[cfg(feature = "write")]
+pub mod snapshot;
+#[cfg(feature = "write")]
+pub mod subscribe;
+#[cfg(feature = "test-server")]
+pub mod test_server;
+pub mod token;
@@ -1,9 +1,21 @@
+#[cfg(feature = "write")]
+use crate::audit::{Auditor, Operation, Source, audited, default_auditor};
 use crate::device::{Device, Model};
 #[cfg(feature = "write")]
+use crate::diff::{diff, diff_optional};
+#[cfg(feature = "write")]
+use crate::edit::TomlDocument;
+#[cfg(feature = "write")]
 use crate::error::Error;
 #[cfg(feature = "write")]
+use crate::format::Format;
+#[cfg(feature = "write")]
 use crate::generate::Generate;
+#[cfg(feature = "write")]
+use crate::pull::FromBackend;
 use crate::read::Read;
+#[cfg(feature = "write")]
+use crate::snapshot::{self, Snapshot};
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "write")]
 use std::fs;
@@ -13,7 +25,9 @@ use std::fs::File;
 use std::io::Write;
 use std::time::Duration;
 
+/// A Libra scale together with its configuration.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Libra {
     pub config: Config,
     pub device: Device,
@@ -22,48 +36,142 @@ pub struct Libra {
 impl Libra {
     pub fn new_config_file(libras: Vec<Self>, file_path: &std::path::Path) -> Result<(), Error> {
         if file_path.exists() {
-            return Err(Error::NotImplemented);
+            return Err(Error::FileExists(file_path.to_path_buf()));
         }
-        let map: std::collections::BTreeMap<String, Libra> = libras
-            .into_iter()
-            .map(|libra| (libra.device.to_string(), libra))
-            .collect();
-
-        let toml_string = toml::to_string(&map).map_err(Error::TomlGeneration)?;
+        let file_string = Self::serialize_file(libras, file_path)?;
 
         if let Some(parent) = file_path.parent() {
-            fs::create_dir_all(parent).map_err(Error::FileSystem)?;
+            fs::create_dir_all(parent).map_err(|e| Error::file_system(parent, e))?;
         }
 
-        let mut file = File::create_new(file_path).map_err(Error::FileSystem)?;
-        file.write_all(toml_string.as_bytes())
-            .map_err(Error::FileSystem)?;
+        let mut file = File::create_new(file_path).map_err(|e| Error::file_system(file_path, e))?;
+        file.write_all(file_string.as_bytes())
+            .map_err(|e| Error::file_system(file_path, e))?;
 
         Ok(())
     }
-    pub fn edit_config_file(self, file_path: &std::path::Path) -> Result<(), Error> {
-        if !file_path.exists() {
-            return Err(Error::FileNotFound);
-        }
-        let mut libras_from_file = Libra::read_as_vec(file_path)?;
-        let mut found = false;
-        for libra in libras_from_file.iter_mut() {
-            if PartialEq::eq(&libra.device, &self.device) {
-                libra.config = self.config.clone();
-                found = true;
-                break;
+    /// Serializes `libras` keyed by device, in the format of `file_path`.
+    fn serialize_file(libras: Vec<Self>, file_path: &std::path::Path) -> Result<String, Error> {
+        let map: std::collections::BTreeMap<String, Libra> = libras
+            .into_iter()
+            .map(|libra| (libra.device.to_string(), libra))
+            .collect();
+        Format::from_path(file_path).serialize(&map)
+    }
+    /// Rewrites an existing config file with `libras` in one rename, so a failed write
+    /// leaves the old file in place.
+    #[cfg(feature = "write")]
+    fn replace_config_file(libras: Vec<Self>, file_path: &std::path::Path) -> Result<(), Error> {
+        let file_string = Self::serialize_file(libras, file_path)?;
+        snapshot::write_atomically(file_path, file_string.as_bytes())
+    }
+    /// Writes the configs fetched by [`ConfigBackend::get_configs`] into a new config file,
+    /// returning the devices whose fetch failed.
+    ///
+    /// [`ConfigBackend::get_configs`]: crate::backend::ConfigBackend::get_configs
+    pub fn new_config_file_from_results(
+        results: Vec<(Device, Result<Config, Error>)>,
+        file_path: &std::path::Path,
+    ) -> Result<Vec<(Device, Error)>, Error> {
+        let mut libras = Vec::new();
+        let mut failures = Vec::new();
+        for (device, result) in results {
+            match result {
+                Ok(config) => libras.push(Libra { config, device }),
+                Err(error) => failures.push((device, error)),
             }
         }
-        if !found {
-            return Err(Error::LibraNotFound);
-        }
-        fs::remove_file(file_path)?;
-        Libra::new_config_file(libras_from_file, file_path)?;
-        Ok(())
+        Self::new_config_file(libras, file_path)?;
+        Ok(failures)
     }
+    /// Replaces `self.device`'s entry in a multi-Libra config file, recording the changed
+    /// fields with the [default auditor](crate::audit::set_default_auditor), if set.
+    pub fn edit_config_file(self, file_path: &std::path::Path) -> Result<(), Error> {
+        self.edit_config_file_inner(file_path, default_auditor().as_ref())
+    }
+    /// Like [`Libra::edit_config_file`], recording the changed fields with `auditor`.
+    pub fn edit_config_file_audited(
+        self,
+        file_path: &std::path::Path,
+        auditor: &Auditor,
+    ) -> Result<(), Error> {
+        self.edit_config_file_inner(file_path, Some(auditor))
+    }
+    /// Adds an entry to a multi-Libra config file, recording it with the default auditor,
+    /// if set.
     pub fn add_to_config_file(self, file_path: &std::path::Path) -> Result<(), Error> {
+        self.add_to_config_file_inner(file_path, default_auditor().as_ref())
+    }
+    /// Like [`Libra::add_to_config_file`], recording the new entry with `auditor`.
+    pub fn add_to_config_file_audited(
+        self,
+        file_path: &std::path::Path,
+        auditor: &Auditor,
+    ) -> Result<(), Error> {
+        self.add_to_config_file_inner(file_path, Some(auditor))
+    }
+    /// Removes `device`'s entry from a multi-Libra config file, recording it with the
+    /// default auditor, if set.
+    pub fn remove_from_config_file(
+        device: Device,
+        file_path: &std::path::Path,
+    ) -> Result<(), Error> {
+        Libra::remove_from_config_file_inner(device, file_path, default_auditor().as_ref())
+    }
+    /// Like [`Libra::remove_from_config_file`], recording the removed entry with `auditor`.
+    pub fn remove_from_config_file_audited(
+        device: Device,
+        file_path: &std::path::Path,
+        auditor: &Auditor,
+    ) -> Result<(), Error> {
+        Libra::remove_from_config_file_inner(device, file_path, Some(auditor))
+    }
+    fn edit_config_file_inner(
+        self,
+        file_path: &std::path::Path,
+        auditor: Option<&Auditor>,
+    ) -> Result<(), Error> {
+        if !file_path.exists() {
+            return Err(Error::FileNotFound(file_path.to_path_buf()));
+        }
+        let mut libras_from_file = Libra::read_as_map(file_path)?;
+        let Some((key, libra)) = libras_from_file
+            .iter_mut()
+            .find(|(_, libra)| libra.device == self.device)
+        else {
+            return Err(Error::LibraNotFound {
+                device: self.device,
+                path: file_path.to_path_buf(),
+            });
+        };
+        let changes = diff(libra, &self)?;
+        libra.config = self.config.clone();
+        let key = key.clone();
+        audited(
+            auditor,
+            Some(&self.device),
+            Operation::Edit,
+            file_source(file_path),
+            changes,
+            || {
+                if Format::from_path(file_path) == Format::Toml {
+                    let mut document = TomlDocument::open(file_path)?;
+                    document.merge(Some(&key), &libras_from_file[&key])?;
+                    document.save()
+                } else {
+                    snapshot::before_write(file_path)?;
+                    Libra::replace_config_file(libras_from_file.into_values().collect(), file_path)
+                }
+            },
+        )
+    }
+    fn add_to_config_file_inner(
+        self,
+        file_path: &std::path::Path,
+        auditor: Option<&Auditor>,
+    ) -> Result<(), Error> {
         if !file_path.exists() {
-            return Err(Error::FileNotFound);
+            return Err(Error::FileNotFound(file_path.to_path_buf()));
         }
         let mut libras_from_file = Libra::read_as_vec(file_path)?;
         let mut already_exists = false;
@@ -74,29 +182,148 @@ impl Libra {
             }
         }
         if already_exists {
-            return Err(Error::LibraAlreadyExists);
+            return Err(Error::LibraAlreadyExists {
+                device: self.device,
+                path: file_path.to_path_buf(),
+            });
         }
-        fs::remove_file(file_path)?;
-        libras_from_file.push(self);
-        Libra::new_config_file(libras_from_file, file_path)?;
-        Ok(())
+        let changes = diff_optional(None, Some(&self))?;
+        let device = self.device.clone();
+        audited(
+            auditor,
+            Some(&device),
+            Operation::Add,
+            file_source(file_path),
+            changes,
+            || {
+                if Format::from_path(file_path) == Format::Toml {
+                    let mut document = TomlDocument::open(file_path)?;
+                    document.insert(&self.device.to_string(), &self)?;
+                    document.save()
+                } else {
+                    snapshot::before_write(file_path)?;
+                    libras_from_file.push(self);
+                    Libra::replace_config_file(libras_from_file, file_path)
+                }
+            },
+        )
     }
-    pub fn remove_from_config_file(
+    fn remove_from_config_file_inner(
         device: Device,
         file_path: &std::path::Path,
+        auditor: Option<&Auditor>,
     ) -> Result<(), Error> {
         if !file_path.exists() {
-            return Err(Error::FileNotFound);
+            return Err(Error::FileNotFound(file_path.to_path_buf()));
         }
-        let mut libras_from_file = Libra::read_as_vec(file_path)?;
-        let original_length = libras_from_file.len();
-        libras_from_file.retain(|x| x.device != device);
-        if original_length == libras_from_file.len() {
-            return Err(Error::LibraNotFound);
+        let mut libras_from_file = Libra::read_as_map(file_path)?;
+        let Some(removed) = libras_from_file
+            .values()
+            .find(|libra| libra.device == device)
+            .cloned()
+        else {
+            return Err(Error::LibraNotFound {
+                device,
+                path: file_path.to_path_buf(),
+            });
+        };
+        libras_from_file.retain(|_, x| x.device != device);
+        audited(
+            auditor,
+            Some(&device),
+            Operation::Remove,
+            file_source(file_path),
+            diff_optional(Some(&removed), None)?,
+            || {
+                if Format::from_path(file_path) == Format::Toml {
+                    let mut document = TomlDocument::open(file_path)?;
+                    document.retain(|key| libras_from_file.contains_key(key));
+                    document.save()
+                } else {
+                    snapshot::before_write(file_path)?;
+                    Libra::replace_config_file(libras_from_file.into_values().collect(), file_path)
+                }
+            },
+        )
+    }
+    /// Applies `patch` to `device`'s entry in a multi-Libra config file.
+    pub fn patch_config_file(
+        device: &Device,
+        patch: &ConfigPatch,
+        file_path: &std::path::Path,
+    ) -> Result<(), Error> {
+        Self::patch_config_file_inner(device, patch, file_path, default_auditor().as_ref())
+    }
+    /// Like [`Libra::patch_config_file`], recording the changed fields with `auditor`.
+    pub fn patch_config_file_audited(
+        device: &Device,
+        patch: &ConfigPatch,
+        file_path: &std::path::Path,
+        auditor: &Auditor,
+    ) -> Result<(), Error> {
+        Self::patch_config_file_inner(device, patch, file_path, Some(auditor))
+    }
+    fn patch_config_file_inner(
+        device: &Device,
+        patch: &ConfigPatch,
+        file_path: &std::path::Path,
+        auditor: Option<&Auditor>,
+    ) -> Result<(), Error> {
+        if !file_path.exists() {
+            return Err(Error::FileNotFound(file_path.to_path_buf()));
         }
-        fs::remove_file(file_path)?;
-        Libra::new_config_file(libras_from_file, file_path)?;
-        Ok(())
+        let Some(mut libra) = Libra::read_as_map(file_path)?
+            .into_values()
+            .find(|libra| libra.device == *device)
+        else {
+            return Err(Error::LibraNotFound {
+                device: device.clone(),
+                path: file_path.to_path_buf(),
+            });
+        };
+        patch.apply(&mut libra.config);
+        libra.edit_config_file_inner(file_path, auditor)
+    }
+    /// Puts `device`'s entry back the way it was in `snapshot`, adding it if it has since been
+    /// removed. The rest of the file is left alone. Recorded with the default auditor, if set.
+    pub fn restore_from_snapshot(
+        device: &Device,
+        file_path: &std::path::Path,
+        snapshot: &Snapshot,
+    ) -> Result<(), Error> {
+        let Some(libra) = Libra::read_as_map(&snapshot.path)?
+            .into_values()
+            .find(|libra| libra.device == *device)
+        else {
+            return Err(Error::LibraNotFound {
+                device: device.clone(),
+                path: snapshot.path.clone(),
+            });
+        };
+        let in_file = Libra::read_as_map(file_path)?
+            .values()
+            .any(|libra| libra.device == *device);
+        if in_file {
+            libra.edit_config_file(file_path)
+        } else {
+            libra.add_to_config_file(file_path)
+        }
+    }
+    /// Reads a multi-Libra config file keyed by table name.
+    fn read_as_map(
+        file_path: &std::path::Path,
+    ) -> Result<std::collections::BTreeMap<String, Libra>, Error> {
+        let file_as_string =
+            fs::read_to_string(file_path).map_err(|e| Error::file_system(file_path, e))?;
+        Format::from_path(file_path)
+            .deserialize(&file_as_string)
+            .map_err(|e| e.with_path(file_path))
+    }
+}
+#[cfg(feature = "write")]
+pub(crate) fn file_source(file_path: &std::path::Path) -> Source {
+    Source::File {
+        path: file_path.to_path_buf(),
     }
 }
 impl Default for Libra {
@@ -110,18 +337,38 @@ impl Default for Libra {
 impl Read for Libra {}
 #[cfg(feature = "write")]
 impl Generate<'_> for Libra {}
+#[cfg(feature = "write")]
+impl FromBackend for Libra {}
 
+/// Settings for a single Libra load cell.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Config {
+    /// Serial number of the Phidget bridge the load cell is wired to.
+    #[cfg_attr(feature = "schema", schemars(range(min = 0)))]
     pub phidget_id: i32,
+    /// Channel of the load cell on the Phidget bridge.
+    #[cfg_attr(feature = "schema", schemars(range(min = 0)))]
     pub load_cell_id: i32,
+    /// Multiplier converting a raw bridge reading into grams.
     pub gain: f64,
+    /// Grams subtracted from every reading after applying the gain.
     pub offset: f64,
+    /// Where the scale is installed, e.g. a kitchen station.
+    #[cfg_attr(feature = "schema", schemars(length(min = 1)))]
     pub location: String,
+    /// Ingredient the scale is holding.
+    #[cfg_attr(feature = "schema", schemars(length(min = 1)))]
     pub ingredient: String,
+    /// Time between heartbeats sent to the backend.
     pub heartbeat_period: Duration,
+    /// Number of samples averaged into one reading.
+    #[cfg_attr(feature = "schema", schemars(range(min = 1)))]
     pub buffer_length: usize,
+    /// Largest spread between buffered samples for a reading to count as stable.
+    #[cfg_attr(feature = "schema", schemars(range(min = 0.0)))]
     pub max_noise: f64,
+    /// Time between samples taken from the Phidget bridge.
     pub phidget_sample_period: Duration,
 }
 impl Default for Config {
@@ -143,13 +390,71 @@ impl Default for Config {
 impl Read for Config {}
 #[cfg(feature = "write")]
 impl Generate<'_> for Config {}
+#[cfg(feature = "write")]
+impl FromBackend for Config {}
+
+/// A partial update of a [`Config`]: only the fields that are set get changed.
+///
+/// Serializes to a JSON Merge Patch (RFC 7396) of the config.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ConfigPatch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phidget_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_cell_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gain: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingredient: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heartbeat_period: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_noise: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phidget_sample_period: Option<Duration>,
+}
+impl ConfigPatch {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+    /// Applies the set fields to `config`.
+    pub fn apply(&self, config: &mut Config) {
+        fn set<T: Clone>(field: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *field = value.clone();
+            }
+        }
+        set(&mut config.phidget_id, &self.phidget_id);
+        set(&mut config.load_cell_id, &self.load_cell_id);
+        set(&mut config.gain, &self.gain);
+        set(&mut config.offset, &self.offset);
+        set(&mut config.location, &self.location);
+        set(&mut config.ingredient, &self.ingredient);
+        set(&mut config.heartbeat_period, &self.heartbeat_period);
+        set(&mut config.buffer_length, &self.buffer_length);
+        set(&mut config.max_noise, &self.max_noise);
+        set(
+            &mut config.phidget_sample_period,
+            &self.phidget_sample_period,
+        );
+    }
+}
 
 #[cfg(test)]
 #[cfg(feature = "write")]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use super::*;
+    use crate::audit::AuditLog;
     use crate::device::{Device, Model};
     use crate::error::Error;
+    use crate::snapshot::Snapshots;
     use std::collections::BTreeMap;
     use std::fs::{self, File};
     use std::path::{Path, PathBuf};
@@ -180,6 +485,7 @@ mod tests {
             if self.path.exists() {
                 let _ = fs::remove_file(&self.path);
             }
+            let _ = fs::remove_dir_all(Snapshots::new(&self.path).dir());
         }
     }
 
@@ -237,9 +543,8 @@ mod tests {
         let result = Libra::new_config_file(vec![], file_path);
         assert!(result.is_err());
         match result.unwrap_err() {
-            // This currently returns NotImplemented, which seems incorrect.
-            Error::NotImplemented => {}
-            other => panic!("Expected Error::NotImplemented, got {:?}", other),
+            Error::FileExists(path) => assert_eq!(path, file_path),
+            other => panic!("Expected Error::FileExists, got {:?}", other),
         }
     }
 
@@ -266,13 +571,177 @@ mod tests {
         assert!(libras_from_file.contains(&libra2));
     }
 
+    #[test]
+    fn test_patch_config_file() {
+        let test_file = TestFile::new("patch_config.toml");
+        let file_path = test_file.path();
+        let libra1 = Libra {
+            device: Device::new(Model::LibraV0, "L1"),
+            ..Libra::default()
+        };
+        let libra2 = Libra {
+            device: Device::new(Model::LibraV0, "L2"),
+            ..Libra::default()
+        };
+        Libra::new_config_file(vec![libra1.clone(), libra2.clone()], file_path).unwrap();
+
+        let patch = ConfigPatch {
+            ingredient: Some("Tofu".into()),
+            gain: Some(2.5),
+            ..ConfigPatch::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&patch).unwrap(),
+            serde_json::json!({ "gain": 2.5, "ingredient": "Tofu" })
+        );
+        Libra::patch_config_file(&libra1.device, &patch, file_path).unwrap();
+
+        let libras = Libra::read_as_vec(file_path).unwrap();
+        let patched = libras
+            .iter()
+            .find(|libra| libra.device == libra1.device)
+            .unwrap();
+        assert_eq!(patched.config.ingredient, "Tofu");
+        assert_eq!(patched.config.gain, 2.5);
+        assert_eq!(patched.config.location, libra1.config.location);
+        assert!(libras.contains(&libra2));
+        assert!(matches!(
+            Libra::patch_config_file(&Device::new(Model::LibraV0, "L3"), &patch, file_path),
+            Err(Error::LibraNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_config_file_in_other_formats() {
+        for format in [Format::Json, Format::Yaml] {
+            let test_file = TestFile::new(&format!("other_formats.{}", format.extension()));
+            let file_path = test_file.path();
+
+            let libra1 = Libra::default();
+            let mut libra2 = Libra::default();
+            libra2.device = Device::new(Model::LibraV0, "L1");
+            Libra::new_config_file(vec![libra1.clone(), libra2.clone()], file_path).unwrap();
+            let content = fs::read_to_string(file_path).unwrap();
+            assert!(toml::from_str::<toml::Value>(&content).is_err());
+
+            let mut edited_libra2 = libra2.clone();
+            edited_libra2.config.location = "Kitchen Station 2".to_string();
+            edited_libra2.clone().edit_config_file(file_path).unwrap();
+
+            let libras_from_file = Libra::read_as_vec(file_path).unwrap();
+            assert_eq!(libras_from_file.len(), 2);
+            assert!(libras_from_file.contains(&libra1));
+            assert!(libras_from_file.contains(&edited_libra2));
+        }
+    }
+
+    #[test]
+    fn test_rewrites_replace_the_file_in_one_step() {
+        let test_file = TestFile::new("atomic_rewrite.json");
+        let file_path = test_file.path();
+        let temporary = file_path.with_extension("json.tmp");
+        let _ = fs::remove_dir_all(&temporary);
+        let libra = Libra::default();
+        Libra::new_config_file(vec![libra.clone()], file_path).unwrap();
+        let original = fs::read_to_string(file_path).unwrap();
+
+        // A write that can't complete leaves the old file in place.
+        fs::create_dir(&temporary).unwrap();
+        let mut edited = libra.clone();
+        edited.config.location = "Kitchen Station 2".to_string();
+        assert!(edited.clone().edit_config_file(file_path).is_err());
+        assert_eq!(fs::read_to_string(file_path).unwrap(), original);
+
+        fs::remove_dir(&temporary).unwrap();
+        edited.clone().edit_config_file(file_path).unwrap();
+        let _ = fs::remove_dir_all(Snapshots::new(file_path).dir());
+        assert!(!temporary.exists());
+        assert_eq!(Libra::read_as_vec(file_path).unwrap(), [edited]);
+    }
+
+    #[test]
+    fn test_generate_picks_format_from_extension() {
+        let test_file = TestFile::new("generate_format.yaml");
+        let file_path = test_file.path();
+
+        let config = Config::default();
+        config.clone().generate(file_path).unwrap();
+
+        let content = fs::read_to_string(file_path).unwrap();
+        assert_eq!(content, serde_yaml::to_string(&config).unwrap());
+        assert_eq!(Config::read(file_path).unwrap(), config);
+        assert!(matches!(
+            Config::read_with_format(file_path, Format::Toml),
+            Err(Error::TomlRead { .. })
+        ));
+    }
+
+    #[test]
+    fn test_edit_config_file_preserves_comments() {
+        let test_file = TestFile::new("edit_config_comments.toml");
+        let file_path = test_file.path();
+
+        let libra1 = Libra::default();
+        let mut libra2 = Libra::default();
+        libra2.device = Device::new(Model::LibraV0, "L1");
+        Libra::new_config_file(vec![libra1.clone(), libra2.clone()], file_path).unwrap();
+        let original = fs::read_to_string(file_path)
+            .unwrap()
+            .replace("gain = 1.0\n", "gain = 1.0 # calibrated by hand\n");
+        let original = format!("# Station scales\n{original}");
+        fs::write(file_path, &original).unwrap();
+
+        let mut edited_libra2 = libra2.clone();
+        edited_libra2.config.ingredient = "Fries".to_string();
+        edited_libra2.clone().edit_config_file(file_path).unwrap();
+
+        // "LibraV0-L1" sorts first, so only its ingredient line changes.
+        let content = fs::read_to_string(file_path).unwrap();
+        let expected = original.replacen(
+            "ingredient = \"Fake Chicken Wings\"",
+            "ingredient = \"Fries\"",
+            1,
+        );
+        assert_eq!(content, expected);
+        let libras_from_file = Libra::read_as_vec(file_path).unwrap();
+        assert!(libras_from_file.contains(&libra1));
+        assert!(libras_from_file.contains(&edited_libra2));
+    }
+
+    #[test]
+    fn test_add_and_remove_preserve_comments() {
+        let test_file = TestFile::new("add_remove_comments.toml");
+        let file_path = test_file.path();
+
+        let libra1 = Libra::default();
+        Libra::new_config_file(vec![libra1.clone()], file_path).unwrap();
+        let original = format!(
+            "# Station scales\n{}",
+            fs::read_to_string(file_path).unwrap()
+        );
+        fs::write(file_path, &original).unwrap();
+
+        let mut libra2 = Libra::default();
+        libra2.device = Device::new(Model::LibraV0, "L1");
+        libra2.clone().add_to_config_file(file_path).unwrap();
+
+        let content = fs::read_to_string(file_path).unwrap();
+        assert!(content.starts_with(&original));
+        let libras_from_file = Libra::read_as_vec(file_path).unwrap();
+        assert_eq!(libras_from_file.len(), 2);
+        assert!(libras_from_file.contains(&libra2));
+
+        Libra::remove_from_config_file(libra2.device, file_path).unwrap();
+        assert_eq!(fs::read_to_string(file_path).unwrap(), original);
+    }
+
     #[test]
     fn test_edit_config_file_not_found() {
         let test_file = TestFile::new("edit_config_not_found.toml");
         let libra = Libra::default();
         let result = libra.edit_config_file(test_file.path());
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::FileNotFound));
+        assert!(matches!(result.unwrap_err(), Error::FileNotFound(_)));
     }
 
     #[test]
@@ -289,7 +758,7 @@ mod tests {
 
         let result = libra_to_edit.edit_config_file(file_path);
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::LibraNotFound));
+        assert!(matches!(result.unwrap_err(), Error::LibraNotFound { .. }));
     }
 
     #[test]
@@ -318,7 +787,7 @@ mod tests {
         let libra = Libra::default();
         let result = libra.add_to_config_file(test_file.path());
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::FileNotFound));
+        assert!(matches!(result.unwrap_err(), Error::FileNotFound(_)));
     }
 
     #[test]
@@ -331,7 +800,10 @@ mod tests {
 
         let result = libra1.add_to_config_file(file_path);
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::LibraAlreadyExists));
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::LibraAlreadyExists { .. }
+        ));
     }
 
     #[test]
@@ -359,7 +831,7 @@ mod tests {
         let device = Device::new(Model::LibraV0, "L1");
         let result = Libra::remove_from_config_file(device, test_file.path());
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::FileNotFound));
+        assert!(matches!(result.unwrap_err(), Error::FileNotFound(_)));
     }
 
     #[test]
@@ -374,6 +846,95 @@ mod tests {
 
         let result = Libra::remove_from_config_file(device_to_remove, file_path);
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::LibraNotFound));
+        assert!(matches!(result.unwrap_err(), Error::LibraNotFound { .. }));
+    }
+
+    #[test]
+    fn test_audited_mutations() {
+        let test_file = TestFile::new("audited_mutations.toml");
+        let audit_file = TestFile::new("audited_mutations.audit.jsonl");
+        let file_path = test_file.path();
+        let auditor = Auditor::new(AuditLog::new(audit_file.path()), "ana");
+
+        let libra1 = Libra::default();
+        let mut libra2 = Libra::default();
+        libra2.device = Device::new(Model::LibraV0, "L1");
+        Libra::new_config_file(vec![libra1.clone()], file_path).unwrap();
+
+        libra2
+            .clone()
+            .add_to_config_file_audited(file_path, &auditor)
+            .unwrap();
+        let mut edited = libra1.clone();
+        edited.config.gain = 2.5;
+        edited
+            .clone()
+            .edit_config_file_audited(file_path, &auditor)
+            .unwrap();
+        edited
+            .edit_config_file_audited(file_path, &auditor)
+            .unwrap();
+        Libra::remove_from_config_file_audited(libra2.device.clone(), file_path, &auditor).unwrap();
+
+        let entries = auditor.log.entries().unwrap();
+        let operations: Vec<_> = entries.iter().map(|entry| entry.operation).collect();
+        assert_eq!(
+            operations,
+            [Operation::Add, Operation::Edit, Operation::Remove],
+            "the no-op edit isn't recorded"
+        );
+        assert_eq!(entries[1].device.as_ref(), Some(&libra1.device));
+        assert_eq!(entries[1].changes.len(), 1);
+        assert_eq!(entries[1].changes[0].path, "config.gain");
+        assert!(entries[0].changes.iter().all(|change| change.old.is_none()));
+        assert!(entries[2].changes.iter().all(|change| change.new.is_none()));
+        assert_eq!(
+            entries[2].source,
+            Source::File {
+                path: file_path.to_path_buf()
+            }
+        );
+        assert_eq!(auditor.log.verify().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_edits_are_snapshotted_and_restorable() {
+        let test_file = TestFile::new("snapshot_libra.yaml");
+        let file_path = test_file.path();
+        let snapshots = Snapshots::new(file_path);
+
+        let libra1 = Libra::default();
+        let mut libra2 = Libra::default();
+        libra2.device = Device::new(Model::LibraV0, "L1");
+        Libra::new_config_file(vec![libra1.clone(), libra2.clone()], file_path).unwrap();
+        assert!(snapshots.list().unwrap().is_empty());
+
+        let mut edited1 = libra1.clone();
+        edited1.config.gain = 9.0;
+        edited1.clone().edit_config_file(file_path).unwrap();
+        let mut edited2 = libra2.clone();
+        edited2.config.gain = 7.0;
+        edited2.clone().edit_config_file(file_path).unwrap();
+        Libra::remove_from_config_file(libra2.device.clone(), file_path).unwrap();
+
+        let list = snapshots.list().unwrap();
+        assert_eq!(list.len(), 3);
+        let original = &list[0];
+
+        Libra::restore_from_snapshot(&libra2.device, file_path, original).unwrap();
+        let libras = Libra::read_as_vec(file_path).unwrap();
+        assert!(libras.contains(&libra2));
+        assert!(libras.contains(&edited1), "other entries are left alone");
+
+        snapshots.restore(original).unwrap();
+        let libras = Libra::read_as_vec(file_path).unwrap();
+        assert_eq!(libras.len(), 2);
+        assert!(libras.contains(&libra1) && libras.contains(&libra2));
+
+        let missing = Device::new(Model::LibraV0, "L9");
+        assert!(matches!(
+            Libra::restore_from_snapshot(&missing, file_path, original),
+            Err(Error::LibraNotFound { .. })
+        ));
     }
 }
@@ -3,12 +3,21 @@ use crate::device::Device;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A single event reported by a Libra scale.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct LibraData {
     pub device: Device,
     pub location: String,
     pub ingredient: String,
     pub data_action: Action,
+    /// Grams involved in the event, e.g. the amount served.
     pub amount: f64,
+    /// When the event happened, as
+    /// `[year, ordinal day, hour, minute, second, nanosecond, offset hours, offset minutes, offset seconds]`.
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "(i32, u16, u8, u8, u8, u32, i8, i8, i8)")
+    )]
     pub timestamp: OffsetDateTime,
 }
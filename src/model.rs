@@ -0,0 +1,217 @@
+use crate::action::Action;
+use crate::device::{Charset, SerialRules};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Hardware a model may be fitted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    LoadCell,
+    Conveyor,
+    Hatch,
+    PhotoEye,
+}
+
+/// The config type a model is provisioned with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfigType {
+    /// [`crate::libra::Config`]
+    Libra,
+    /// [`crate::ichibu::Ichibu`]
+    Ichibu,
+}
+
+/// Everything the crate knows about a model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelSpec {
+    /// Path segment identifying the model in backend URLs.
+    pub route: &'static str,
+    pub config: ConfigType,
+    /// Actions the model reports.
+    pub actions: &'static [Action],
+    pub capabilities: &'static [Capability],
+    pub serial_rules: SerialRules,
+}
+
+const ALL_ACTIONS: &[Action] = &[
+    Action::Served,
+    Action::RanOut,
+    Action::Refilled,
+    Action::Starting,
+    Action::Heartbeat,
+    Action::Offline,
+];
+
+/// Declares the `Model` enum from one spec per model. The variant name doubles as the
+/// model's textual and serialized form, so a new model only needs an entry here.
+macro_rules! models {
+    ($($(#[$meta:meta])* $name:ident => $spec:expr,)+) => {
+        #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+        pub enum Model {
+            $($(#[$meta])* $name,)+
+        }
+
+        impl Model {
+            /// Every registered model, in declaration order.
+            pub const ALL: &'static [Model] = &[$(Model::$name,)+];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Model::$name => stringify!($name),)+
+                }
+            }
+            pub fn spec(&self) -> &'static ModelSpec {
+                match self {
+                    $(Model::$name => {
+                        const SPEC: ModelSpec = $spec;
+                        &SPEC
+                    })+
+                }
+            }
+        }
+    };
+}
+
+models! {
+    /// First generation dispenser.
+    IchibuV1 => ModelSpec {
+        route: "IchibuV1",
+        config: ConfigType::Ichibu,
+        actions: ALL_ACTIONS,
+        capabilities: &[Capability::LoadCell, Capability::Conveyor, Capability::PhotoEye],
+        serial_rules: SerialRules {
+            prefix: "I",
+            min_length: 2,
+            max_length: 16,
+            charset: Charset::Alphanumeric,
+            checksum: false,
+        },
+    },
+    /// Second generation dispenser, adding a motorized hatch.
+    IchibuV2 => ModelSpec {
+        route: "IchibuV2",
+        config: ConfigType::Ichibu,
+        actions: ALL_ACTIONS,
+        capabilities: &[
+            Capability::LoadCell,
+            Capability::Conveyor,
+            Capability::Hatch,
+            Capability::PhotoEye,
+        ],
+        serial_rules: SerialRules {
+            prefix: "I",
+            min_length: 2,
+            max_length: 16,
+            charset: Charset::Alphanumeric,
+            checksum: false,
+        },
+    },
+    LibraV0 => ModelSpec {
+        route: "LibraV0",
+        config: ConfigType::Libra,
+        actions: ALL_ACTIONS,
+        capabilities: &[Capability::LoadCell],
+        serial_rules: SerialRules {
+            prefix: "L",
+            min_length: 2,
+            max_length: 16,
+            charset: Charset::Alphanumeric,
+            checksum: false,
+        },
+    },
+    /// Libra with checksummed serial numbers.
+    LibraV1 => ModelSpec {
+        route: "LibraV1",
+        config: ConfigType::Libra,
+        actions: ALL_ACTIONS,
+        capabilities: &[Capability::LoadCell],
+        serial_rules: SerialRules {
+            prefix: "L",
+            min_length: 6,
+            max_length: 16,
+            charset: Charset::Alphanumeric,
+            checksum: true,
+        },
+    },
+}
+
+impl Model {
+    pub fn route(&self) -> &'static str {
+        self.spec().route
+    }
+    pub fn config_type(&self) -> ConfigType {
+        self.spec().config
+    }
+    pub fn serial_rules(&self) -> SerialRules {
+        self.spec().serial_rules
+    }
+    pub fn supports(&self, action: &Action) -> bool {
+        self.spec().actions.contains(action)
+    }
+    pub fn has(&self, capability: Capability) -> bool {
+        self.spec().capabilities.contains(&capability)
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Model::ALL
+            .iter()
+            .find(|model| model.name() == s)
+            .cloned()
+            .ok_or_else(|| format!("Invalid model: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Device;
+
+    #[test]
+    fn test_models_parse_by_name() {
+        for model in Model::ALL {
+            assert_eq!(Model::from_str(model.name()).as_ref(), Ok(model));
+            assert_eq!(model.to_string(), format!("{model:?}"));
+            assert_eq!(
+                serde_json::to_string(model).unwrap(),
+                format!("\"{}\"", model.name())
+            );
+        }
+        assert_eq!(
+            Model::from_str("LibraV9"),
+            Err("Invalid model: LibraV9".to_string())
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        assert!(Model::IchibuV2.has(Capability::Hatch));
+        assert!(!Model::IchibuV1.has(Capability::Hatch));
+        assert!(!Model::LibraV0.has(Capability::Conveyor));
+        assert!(Model::LibraV0.supports(&Action::Served));
+        assert_eq!(Model::LibraV1.config_type(), ConfigType::Libra);
+        assert_eq!(Model::IchibuV1.config_type(), ConfigType::Ichibu);
+    }
+
+    #[test]
+    fn test_spec_drives_serial_validation() {
+        let serial_number = Model::LibraV1
+            .serial_rules()
+            .with_checksum("L00001")
+            .unwrap();
+        assert!(Device::try_new(Model::LibraV1, &serial_number).is_ok());
+        assert!(Device::try_new(Model::LibraV1, "L000010").is_err());
+        assert!(Device::try_new(Model::LibraV0, "L000010").is_ok());
+    }
+}
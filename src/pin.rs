@@ -0,0 +1,314 @@
+use crate::error::Error;
+#[cfg(feature = "pins")]
+pub use argon2::Params;
+#[cfg(feature = "pins")]
+use argon2::password_hash::rand_core::OsRng;
+#[cfg(feature = "pins")]
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+#[cfg(feature = "pins")]
+use argon2::{Algorithm, Argon2, Version};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+#[cfg(feature = "pins")]
+use subtle::ConstantTimeEq;
+
+/// A PIN as stored in a config file: a salted Argon2 hash in PHC format, or a legacy
+/// plaintext PIN that has yet to be migrated.
+///
+/// `Debug` and `Display` never show the stored value.
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+#[serde(transparent)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Pin(
+    #[cfg_attr(
+        feature = "schema",
+        schemars(regex(pattern = r"^([0-9]+|\$argon2.+)$"))
+    )]
+    String,
+);
+
+/// Access levels unlocked by the dispenser's PINs, from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum AccessLevel {
+    Operator,
+    Manager,
+    Sudo,
+}
+
+impl fmt::Display for AccessLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccessLevel::Operator => write!(f, "operator"),
+            AccessLevel::Manager => write!(f, "manager"),
+            AccessLevel::Sudo => write!(f, "sudo"),
+        }
+    }
+}
+
+#[cfg(feature = "pins")]
+/// Whether PINs still stored in plaintext may unlock anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PinMode {
+    /// Only hashed PINs match, so hand-editing a config back to plaintext locks that PIN
+    /// out instead of weakening it.
+    #[default]
+    Strict,
+    /// Plaintext PINs also match, for devices whose configs haven't been migrated yet.
+    AllowPlaintext,
+}
+
+impl Pin {
+    #[cfg(feature = "pins")]
+    /// Hashes `pin` with a fresh salt at the production cost, [`Params::default`].
+    pub fn hash(pin: &str) -> Result<Self, Error> {
+        Self::hash_with(pin, &Params::default())
+    }
+    #[cfg(feature = "pins")]
+    /// Like [`Pin::hash`], with explicit Argon2id cost parameters.
+    pub fn hash_with(pin: &str, params: &Params) -> Result<Self, Error> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+            .hash_password(pin.as_bytes(), &salt)
+            .map_err(Error::PinHash)?;
+        Ok(Self(hash.to_string()))
+    }
+    /// Wraps a PIN as it would be read from a legacy config file.
+    pub fn plaintext(pin: &str) -> Self {
+        Self(pin.to_string())
+    }
+    /// Wraps an Argon2 hash in PHC format, such as the hashes of the shipped PINs.
+    pub(crate) fn from_hash(hash: &str) -> Self {
+        Self(hash.to_string())
+    }
+    pub fn is_hashed(&self) -> bool {
+        self.0.starts_with("$argon2")
+    }
+    #[cfg(feature = "pins")]
+    /// Checks `candidate` against this PIN. Plaintext PINs never match; see [`PinMode`].
+    pub fn verify(&self, candidate: &str) -> bool {
+        self.verify_with(candidate, PinMode::Strict)
+    }
+    #[cfg(feature = "pins")]
+    /// Like [`Pin::verify`]. With [`PinMode::AllowPlaintext`], plaintext PINs are compared
+    /// in constant time.
+    pub fn verify_with(&self, candidate: &str, mode: PinMode) -> bool {
+        if !self.is_hashed() {
+            return mode == PinMode::AllowPlaintext
+                && bool::from(self.0.as_bytes().ct_eq(candidate.as_bytes()));
+        }
+        PasswordHash::new(&self.0).is_ok_and(|hash| {
+            // The cost parameters come from the stored hash, not the hasher.
+            Argon2::default()
+                .verify_password(candidate.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+    #[cfg(feature = "pins")]
+    /// Replaces a plaintext PIN with its hash. Returns whether anything changed.
+    pub fn migrate(&mut self) -> Result<bool, Error> {
+        self.migrate_with(&Params::default())
+    }
+    #[cfg(feature = "pins")]
+    /// Like [`Pin::migrate`], with explicit Argon2id cost parameters.
+    pub fn migrate_with(&mut self, params: &Params) -> Result<bool, Error> {
+        if self.is_hashed() {
+            return Ok(false);
+        }
+        *self = Pin::hash_with(&self.0, params)?;
+        Ok(true)
+    }
+    pub(crate) fn validate(&self, field: &'static str) -> Result<(), Error> {
+        #[cfg(feature = "pins")]
+        if self.is_hashed() {
+            return PasswordHash::new(&self.0)
+                .map(|_| ())
+                .map_err(|e| Error::validation(field, e.to_string()));
+        }
+        // Without the `pins` feature there is no parser to check a hash with.
+        #[cfg(not(feature = "pins"))]
+        if self.is_hashed() {
+            return Ok(());
+        }
+        check_digits(field, &self.0)
+    }
+}
+
+pub(crate) fn check_digits(field: &'static str, pin: &str) -> Result<(), Error> {
+    if pin.is_empty() || !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(Error::validation(field, "PINs must be one or more digits"));
+    }
+    Ok(())
+}
+
+impl fmt::Debug for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_hashed() {
+            write!(f, "Pin(<hashed>)")
+        } else {
+            write!(f, "Pin(<redacted>)")
+        }
+    }
+}
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
+/// Counts consecutive failed PIN attempts and locks out further attempts after too many.
+///
+/// The count lives in memory only: restarting the process clears it. Keep one `Lockout`
+/// per device for the lifetime of the process that checks its PINs.
+#[derive(Debug, Clone)]
+pub struct Lockout {
+    max_failures: u32,
+    duration: Duration,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Five attempts, then a five minute lockout.
+impl Default for Lockout {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(300))
+    }
+}
+
+impl Lockout {
+    pub fn new(max_failures: u32, duration: Duration) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            duration,
+            failures: 0,
+            locked_until: None,
+        }
+    }
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.locked_until = None;
+    }
+    /// Checks `pin` with `check`, returning the access level it unlocks.
+    pub fn verify(
+        &mut self,
+        pin: &str,
+        check: impl FnOnce(&str) -> Option<AccessLevel>,
+    ) -> Result<AccessLevel, Error> {
+        self.verify_at(Instant::now(), pin, check)
+    }
+    /// Like [`Lockout::verify`], as if called at `now`.
+    pub fn verify_at(
+        &mut self,
+        now: Instant,
+        pin: &str,
+        check: impl FnOnce(&str) -> Option<AccessLevel>,
+    ) -> Result<AccessLevel, Error> {
+        if let Some(locked_until) = self.locked_until {
+            if now < locked_until {
+                return Err(Error::LockedOut {
+                    retry_after: locked_until - now,
+                });
+            }
+            self.reset();
+        }
+        if let Some(access_level) = check(pin) {
+            self.failures = 0;
+            return Ok(access_level);
+        }
+        self.failures += 1;
+        if self.failures >= self.max_failures {
+            self.locked_until = Some(now + self.duration);
+            return Err(Error::LockedOut {
+                retry_after: self.duration,
+            });
+        }
+        Err(Error::WrongPin {
+            attempts_remaining: self.max_failures - self.failures,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[cfg(feature = "pins")]
+    /// The cheapest parameters Argon2 accepts, for tests that don't check the cost.
+    pub(crate) fn fast() -> Params {
+        Params::new(Params::MIN_M_COST, Params::MIN_T_COST, 1, None).unwrap()
+    }
+
+    #[cfg(feature = "pins")]
+    #[test]
+    fn test_hash_and_verify() {
+        let pin = Pin::hash("2744").unwrap();
+        assert!(pin.is_hashed());
+        assert!(pin.0.contains(&format!("m={}", Params::DEFAULT_M_COST)));
+        assert!(pin.verify("2744"));
+        assert!(!pin.verify("2745"));
+        let cheap = Pin::hash_with("2744", &fast()).unwrap();
+        assert_ne!(pin, cheap, "salts must differ");
+        assert!(cheap.verify("2744"));
+        pin.validate("pin").unwrap();
+    }
+
+    #[cfg(feature = "pins")]
+    #[test]
+    fn test_migrate_plaintext() {
+        let mut pin = Pin::plaintext("200");
+        assert!(!pin.verify("200"), "strict mode rejects plaintext");
+        assert!(pin.verify_with("200", PinMode::AllowPlaintext));
+        assert!(!pin.verify_with("2000", PinMode::AllowPlaintext));
+        assert!(pin.migrate_with(&fast()).unwrap());
+        assert!(pin.is_hashed() && pin.verify("200"));
+        assert!(!pin.migrate_with(&fast()).unwrap());
+    }
+
+    #[test]
+    fn test_redacted() {
+        let pin = Pin::plaintext("101010101");
+        assert_eq!(format!("{pin:?}"), "Pin(<redacted>)");
+        assert_eq!(pin.to_string(), "<redacted>");
+        let pin = Pin::from_hash("$argon2id$v=19$m=8,t=1,p=1$c2FsdHNhbHQ$aGFzaA");
+        assert_eq!(format!("{pin:?}"), "Pin(<hashed>)");
+    }
+
+    #[test]
+    fn test_lockout() {
+        let check = |pin: &str| (pin == "1234").then_some(AccessLevel::Manager);
+        let mut lockout = Lockout::new(3, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert!(matches!(
+            lockout.verify_at(start, "0", check),
+            Err(Error::WrongPin {
+                attempts_remaining: 2
+            })
+        ));
+        assert_eq!(
+            lockout.verify_at(start, "1234", check).unwrap(),
+            AccessLevel::Manager
+        );
+        assert_eq!(lockout.failures(), 0);
+
+        lockout.verify_at(start, "0", check).unwrap_err();
+        lockout.verify_at(start, "0", check).unwrap_err();
+        let error = lockout.verify_at(start, "0", check).unwrap_err();
+        assert_eq!(error.code(), "locked_out");
+        let later = start + Duration::from_secs(30);
+        assert!(matches!(
+            lockout.verify_at(later, "1234", check),
+            Err(Error::LockedOut { retry_after }) if retry_after == Duration::from_secs(30)
+        ));
+        let after = start + Duration::from_secs(60);
+        assert_eq!(
+            lockout.verify_at(after, "1234", check).unwrap(),
+            AccessLevel::Manager
+        );
+    }
+}
@@ -1,22 +1,216 @@
-#[cfg(feature = "write")]
+use crate::backend::ConfigBackend;
+#[cfg(feature = "bundle")]
+use crate::bundle::{BundleError, SignedBundle};
+use crate::device::Device;
 use crate::error::Error;
-#[cfg(feature = "write")]
+use reqwest::{Method, StatusCode};
 use serde::de::DeserializeOwned;
-#[cfg(feature = "write")]
+
+/// Configs that can be fetched as JSON from any backend URL, authenticated and timed out
+/// like the rest of [`ConfigBackend`]'s requests.
+///
+/// When the backend pins a bundle key, configs are only accepted as bundles signed for the
+/// device, so they must be pulled with [`FromBackend::pull_for_device`].
 pub trait FromBackend {
-    fn pull(url: &str) -> Result<Self, Error>
+    fn pull(backend: &ConfigBackend, url: &str) -> Result<Self, Error>
     where
         Self: Sized + DeserializeOwned,
     {
-        let client = reqwest::blocking::Client::new();
-        let response = client
-            .get(url)
-            .timeout(std::time::Duration::from_secs(60))
-            .send()
-            .map_err(Error::Reqwest)?
-            .text()
-            .map_err(Error::Reqwest)?;
-        let instance = serde_json::from_str(&response).map_err(Error::SerdeJson)?;
-        Ok(instance)
+        decode(backend, &fetch(backend, url)?, None)
+    }
+    fn pull_for_device(backend: &ConfigBackend, url: &str, device: &Device) -> Result<Self, Error>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        decode(backend, &fetch(backend, url)?, Some(device))
+    }
+    #[allow(async_fn_in_trait)]
+    async fn pull_async(backend: &ConfigBackend, url: &str) -> Result<Self, Error>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        decode(backend, &fetch_async(backend, url).await?, None)
+    }
+    #[allow(async_fn_in_trait)]
+    async fn pull_for_device_async(
+        backend: &ConfigBackend,
+        url: &str,
+        device: &Device,
+    ) -> Result<Self, Error>
+    where
+        Self: Sized + DeserializeOwned,
+    {
+        decode(backend, &fetch_async(backend, url).await?, Some(device))
+    }
+}
+
+fn fetch(backend: &ConfigBackend, url: &str) -> Result<String, Error> {
+    let response = backend.send(Method::GET, url, |request| request)?;
+    if response.status() != StatusCode::OK {
+        return Err(Error::backend(Method::GET, url, response));
+    }
+    response
+        .text()
+        .map_err(|e| Error::reqwest(Method::GET, url, e))
+}
+
+async fn fetch_async(backend: &ConfigBackend, url: &str) -> Result<String, Error> {
+    let response = backend
+        .send_async(Method::GET, url, |request| request)
+        .await?;
+    if response.status() != StatusCode::OK {
+        return Err(Error::backend_async(Method::GET, url, response).await);
+    }
+    response
+        .text()
+        .await
+        .map_err(|e| Error::reqwest(Method::GET, url, e))
+}
+
+/// Parses a pulled body, opening it as a signed bundle when the backend pins a bundle key.
+fn decode<T: DeserializeOwned>(
+    backend: &ConfigBackend,
+    body: &str,
+    device: Option<&Device>,
+) -> Result<T, Error> {
+    #[cfg(feature = "bundle")]
+    if let Some(verifier) = &backend.bundle_verifier {
+        let device = device.ok_or(BundleError::NoDevice)?;
+        let bundle: SignedBundle = serde_json::from_str(body).map_err(Error::json_read)?;
+        return verifier.open(&bundle, device);
+    }
+    #[cfg(not(feature = "bundle"))]
+    let _ = (backend, device);
+    serde_json::from_str(body).map_err(Error::json_read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ichibu::{Ichibu, ScaleConfig};
+    use crate::libra::Libra;
+
+    const ICHIBU: &str = include_str!("../config.toml");
+
+    #[test]
+    fn test_pull_libra() {
+        let mut server = mockito::Server::new();
+        let libra = Libra::default();
+        let mock = server
+            .mock("GET", "/libras/L1")
+            .match_header("authorization", "Bearer test-token")
+            .with_body(serde_json::to_string(&libra).unwrap())
+            .create();
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let pulled = Libra::pull(&backend, &format!("{}/libras/L1", server.url())).unwrap();
+
+        mock.assert();
+        assert_eq!(pulled, libra);
+    }
+
+    #[test]
+    fn test_pull_ichibu() {
+        let mut server = mockito::Server::new();
+        let ichibu: Ichibu = toml::from_str(ICHIBU).unwrap();
+        let mock = server
+            .mock("GET", "/ichibus/I0")
+            .with_body(serde_json::to_string(&ichibu).unwrap())
+            .create();
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let pulled = Ichibu::pull(&backend, &format!("{}/ichibus/I0", server.url())).unwrap();
+
+        mock.assert();
+        assert_eq!(pulled, ichibu);
+    }
+
+    #[test]
+    fn test_pull_error_status_and_body() {
+        let mut server = mockito::Server::new();
+        server
+            .mock("GET", "/scale")
+            .with_status(404)
+            .with_body("no such scale")
+            .create();
+        server
+            .mock("GET", "/garbled")
+            .with_body("{\"phidget_id\":")
+            .create();
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        match ScaleConfig::pull(&backend, &format!("{}/scale", server.url())) {
+            Err(Error::Backend { status, body, .. }) => {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(body, "no such scale");
+            }
+            other => panic!("Expected Error::Backend, got {other:?}"),
+        }
+        let error = ScaleConfig::pull(&backend, &format!("{}/garbled", server.url())).unwrap_err();
+        assert_eq!(error.code(), "json_read");
+    }
+
+    #[tokio::test]
+    async fn test_pull_scale_config_async() {
+        let mut server = mockito::Server::new_async().await;
+        let scale_config: ScaleConfig = toml::from_str(include_str!("../scale.toml")).unwrap();
+        let mock = server
+            .mock("GET", "/scale")
+            .match_header("authorization", "Bearer test-token")
+            .with_body(serde_json::to_string(&scale_config).unwrap())
+            .create_async()
+            .await;
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let pulled = ScaleConfig::pull_async(&backend, &format!("{}/scale", server.url()))
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(pulled, scale_config);
+    }
+
+    #[cfg(feature = "bundle")]
+    #[test]
+    fn test_pull_verifies_bundles() {
+        use crate::bundle::{BundleError, BundleSigner};
+        use crate::device::Model;
+
+        let mut server = mockito::Server::new();
+        let url = format!("{}/libras/L1", server.url());
+        let device = Device::new(Model::LibraV0, "L1");
+        let config = crate::libra::Config {
+            gain: 4.0,
+            ..Default::default()
+        };
+        let signer = BundleSigner::generate();
+        let expires_at = time::OffsetDateTime::now_utc() + time::Duration::hours(1);
+        let bundle = signer.sign(&device, expires_at, &config).unwrap();
+        server
+            .mock("GET", "/libras/L1")
+            .with_body(serde_json::to_string(&bundle).unwrap())
+            .create();
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string())
+            .with_bundle_verifier(signer.verifier());
+
+        let pulled = crate::libra::Config::pull_for_device(&backend, &url, &device).unwrap();
+        assert_eq!(pulled, config);
+        assert!(matches!(
+            crate::libra::Config::pull(&backend, &url),
+            Err(Error::Bundle(BundleError::NoDevice))
+        ));
+        let other = Device::new(Model::LibraV0, "L2");
+        assert!(matches!(
+            crate::libra::Config::pull_for_device(&backend, &url, &other),
+            Err(Error::Bundle(BundleError::WrongDevice { .. }))
+        ));
+
+        // An unsigned config is rejected rather than trusted.
+        server
+            .mock("GET", "/libras/L1")
+            .with_body(serde_json::to_string(&config).unwrap())
+            .create();
+        let error = crate::libra::Config::pull_for_device(&backend, &url, &device).unwrap_err();
+        assert_eq!(error.code(), "json_read");
     }
 }
@@ -1,4 +1,6 @@
 use crate::error::Error;
+use crate::format::Format;
+use crate::secret;
 use serde::Deserialize;
 use std::fs;
 use std::path::Path;
@@ -9,26 +11,42 @@ pub trait Read {
         Self: Sized,
         for<'de> Self: Deserialize<'de>,
     {
-        let file_as_string = fs::read_to_string(path).map_err(Error::FileSystem)?;
-        toml::from_str(&file_as_string).map_err(Error::TomlRead)
+        Self::read_with_format(path, Format::from_path(path))
+    }
+    fn read_with_format(path: &Path, format: Format) -> Result<Self, Error>
+    where
+        Self: Sized,
+        for<'de> Self: Deserialize<'de>,
+    {
+        let file_as_string = read_decrypted(path, format)?;
+        format
+            .deserialize(&file_as_string)
+            .map_err(|e| e.with_path(path))
     }
     fn read_as_vec(path: &Path) -> Result<Vec<Self>, Error>
     where
         Self: Sized,
         for<'de> Self: Deserialize<'de>,
     {
-        let file_as_string = fs::read_to_string(path).map_err(Error::FileSystem)?;
-        let toml_value: toml::Value = toml::from_str(&file_as_string).map_err(Error::TomlRead)?;
+        Self::read_as_vec_with_format(path, Format::from_path(path))
+    }
+    fn read_as_vec_with_format(path: &Path, format: Format) -> Result<Vec<Self>, Error>
+    where
+        Self: Sized,
+        for<'de> Self: Deserialize<'de>,
+    {
+        let file_as_string = read_decrypted(path, format)?;
+        format
+            .deserialize_tables(&file_as_string)
+            .map_err(|e| e.with_path(path))
+    }
+}
 
-        if let toml::Value::Table(table) = toml_value {
-            table
-                .into_iter()
-                .map(|(_, value)| value.try_into::<Self>())
-                .collect::<Result<Vec<Self>, _>>()
-                .map_err(Error::TomlRead)
-        } else {
-            let custom_error = "Expected TOML root to be a table of tables";
-            Err(Error::Custom(custom_error.parse().unwrap()))
-        }
+/// Reads a config file, decrypting any `!enc:` values in TOML.
+fn read_decrypted(path: &Path, format: Format) -> Result<String, Error> {
+    let file_as_string = fs::read_to_string(path).map_err(|e| Error::file_system(path, e))?;
+    match format {
+        Format::Toml => secret::decrypt_toml(file_as_string),
+        _ => Ok(file_as_string),
     }
 }
@@ -0,0 +1,342 @@
+use crate::address::{Address, AddressBook, Host};
+use crate::backend::ConfigBackend;
+use crate::device::Device;
+use crate::error::Error;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Lists the device's local IP addresses.
+pub trait InterfaceSource {
+    fn addresses(&self) -> Result<Vec<IpAddr>, Error>;
+}
+
+impl<F: Fn() -> Result<Vec<IpAddr>, Error>> InterfaceSource for F {
+    fn addresses(&self) -> Result<Vec<IpAddr>, Error> {
+        self()
+    }
+}
+
+/// The address of the interface the default route goes through, found by connecting a UDP
+/// socket, which sends nothing. Finds nothing on a network without a default route.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoutedInterface;
+
+impl InterfaceSource for RoutedInterface {
+    fn addresses(&self) -> Result<Vec<IpAddr>, Error> {
+        // TEST-NET-1: never routed anywhere, but picks the outgoing interface.
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).map_err(Error::LocalAddress)?;
+        socket
+            .connect((Ipv4Addr::new(192, 0, 2, 1), 9))
+            .map_err(Error::LocalAddress)?;
+        let ip = socket.local_addr().map_err(Error::LocalAddress)?.ip();
+        Ok(if ip.is_unspecified() {
+            vec![]
+        } else {
+            vec![ip]
+        })
+    }
+}
+
+/// The addresses of every network interface that is up, read with `getifaddrs`, so a
+/// device on an isolated LAN without a default route still finds its address. The default
+/// route's address, when there is one, comes first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalInterfaces;
+
+impl InterfaceSource for LocalInterfaces {
+    fn addresses(&self) -> Result<Vec<IpAddr>, Error> {
+        let mut addresses = RoutedInterface.addresses().unwrap_or_default();
+        let interfaces = if_addrs::get_if_addrs().map_err(Error::LocalAddress)?;
+        for interface in interfaces {
+            if interface.is_oper_up() && !addresses.contains(&interface.ip()) {
+                addresses.push(interface.ip());
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+/// How an [`AddressReporter`] watches for and reports address changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReporterOptions {
+    /// Port the device serves on, reported along with its IP address.
+    pub port: Option<u16>,
+    /// Pause between checks of the local interfaces.
+    pub check_interval: Duration,
+    /// How long a new address must hold before it is reported, so a flapping interface
+    /// isn't reported on every change. The first address is reported right away.
+    pub debounce: Duration,
+    /// First delay before retrying a failed report; doubles with each failure.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ReporterOptions {
+    fn default() -> Self {
+        Self {
+            port: None,
+            check_interval: Duration::from_secs(30),
+            debounce: Duration::from_secs(5),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Reports the device's own network address to the backend on startup and whenever it
+/// changes.
+pub struct AddressReporter<'a> {
+    backend: &'a ConfigBackend,
+    device: Device,
+    source: Box<dyn InterfaceSource + 'a>,
+    options: ReporterOptions,
+    book: Option<AddressBook>,
+    reported: Option<Address>,
+    pending: Option<(Address, Instant)>,
+    backoff: Option<Duration>,
+    last_error: Option<Error>,
+}
+
+impl ConfigBackend {
+    /// A reporter for `device`'s address, read from the local network interfaces.
+    pub fn address_reporter(&self, device: Device) -> AddressReporter<'_> {
+        AddressReporter {
+            backend: self,
+            device,
+            source: Box::new(LocalInterfaces),
+            options: ReporterOptions::default(),
+            book: None,
+            reported: None,
+            pending: None,
+            backoff: None,
+            last_error: None,
+        }
+    }
+}
+
+impl<'a> AddressReporter<'a> {
+    pub fn with_source(mut self, source: impl InterfaceSource + 'a) -> Self {
+        self.source = Box::new(source);
+        self
+    }
+    pub fn with_options(mut self, options: ReporterOptions) -> Self {
+        self.options = options;
+        self
+    }
+    /// Also records each reported address in `book`.
+    pub fn with_address_book(mut self, book: AddressBook) -> Self {
+        self.book = Some(book);
+        self
+    }
+    /// The address last accepted by the backend.
+    pub fn reported(&self) -> Option<&Address> {
+        self.reported.as_ref()
+    }
+    /// The error behind the current backoff, cleared once a report succeeds.
+    pub fn last_error(&self) -> Option<&Error> {
+        self.last_error.as_ref()
+    }
+    /// The address to report: the first routable IPv4 address, else the first IPv6 one, else
+    /// an IPv4 link-local one. IPv6 link-local addresses are never reported, as they can't be
+    /// reached without the interface's scope id.
+    pub fn current_address(&self) -> Result<Option<Address>, Error> {
+        let addresses = self.source.addresses()?;
+        let usable = |ip: &&IpAddr| {
+            !ip.is_loopback()
+                && !ip.is_unspecified()
+                && !matches!(ip, IpAddr::V6(v6) if v6.is_unicast_link_local())
+        };
+        let mut candidates = addresses.iter().filter(usable);
+        let ip = candidates
+            .clone()
+            .find(|ip| matches!(ip, IpAddr::V4(v4) if !v4.is_link_local()))
+            .or_else(|| candidates.clone().find(|ip| ip.is_ipv6()))
+            .or_else(|| candidates.next());
+        Ok(ip.map(|ip| Address::new(Host::Ip(*ip), self.options.port)))
+    }
+    /// Checks the interfaces and reports the address if it changed and has settled,
+    /// returning how long to wait before the next check.
+    pub fn step(&mut self) -> Duration {
+        let address = match self.current_address() {
+            Ok(Some(address)) => address,
+            Ok(None) => {
+                self.pending = None;
+                return self.options.check_interval;
+            }
+            Err(error) => return self.fail(error),
+        };
+        if self.reported.as_ref() == Some(&address) {
+            self.pending = None;
+            return self.options.check_interval;
+        }
+        let settled = match &self.pending {
+            _ if self.reported.is_none() => true,
+            Some((pending, since)) => {
+                *pending == address && since.elapsed() >= self.options.debounce
+            }
+            None => false,
+        };
+        if !settled {
+            if self
+                .pending
+                .as_ref()
+                .is_none_or(|(pending, _)| *pending != address)
+            {
+                self.pending = Some((address, Instant::now()));
+            }
+            return self.options.debounce;
+        }
+        if let Err(error) = self.report(&address) {
+            return self.fail(error);
+        }
+        self.reported = Some(address);
+        self.pending = None;
+        self.backoff = None;
+        self.last_error = None;
+        self.options.check_interval
+    }
+    /// Reports the address until `stop` returns true, checked before every step.
+    pub fn run_until(&mut self, mut stop: impl FnMut(&Self) -> bool) {
+        while !stop(self) {
+            std::thread::sleep(self.step());
+        }
+    }
+    fn report(&self, address: &Address) -> Result<(), Error> {
+        self.backend.put_address(self.device.clone(), address)?;
+        if let Some(book) = &self.book {
+            book.record(&self.device, address)?;
+        }
+        Ok(())
+    }
+    /// Records a failure and returns how long to wait before retrying.
+    fn fail(&mut self, error: Error) -> Duration {
+        let backoff = match self.backoff {
+            Some(backoff) => (backoff * 2).min(self.options.max_backoff),
+            None => self.options.initial_backoff,
+        };
+        self.backoff = Some(backoff);
+        self.last_error = Some(error);
+        backoff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Model;
+    use mockito::Matcher;
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    fn options() -> ReporterOptions {
+        ReporterOptions {
+            port: Some(8080),
+            check_interval: Duration::from_millis(1),
+            debounce: Duration::from_millis(20),
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    #[test]
+    fn test_reports_on_startup_and_after_debounced_change() {
+        let mut server = mockito::Server::new();
+        let first = server
+            .mock("PUT", "/address/LibraV0/L1")
+            .match_body(Matcher::Json(json!({"address": "10.0.0.7:8080"})))
+            .expect(1)
+            .create();
+        let outage = server
+            .mock("PUT", "/address/LibraV0/L1")
+            .match_body(Matcher::Json(json!({"address": "10.0.0.9:8080"})))
+            .with_status(503)
+            .expect(1)
+            .create();
+        let second = server
+            .mock("PUT", "/address/LibraV0/L1")
+            .match_body(Matcher::Json(json!({"address": "10.0.0.9:8080"})))
+            .expect(1)
+            .create();
+        // A blip to .8 that reverts within the debounce is never reported.
+        let interfaces = RefCell::new(vec![
+            vec!["127.0.0.1", "10.0.0.7"],
+            vec!["10.0.0.8"],
+            vec!["10.0.0.7"],
+            vec!["10.0.0.9"],
+        ]);
+        let source = || {
+            let mut interfaces = interfaces.borrow_mut();
+            let current = if interfaces.len() > 1 {
+                interfaces.remove(0)
+            } else {
+                interfaces[0].clone()
+            };
+            Ok(current.iter().map(|ip| ip.parse().unwrap()).collect())
+        };
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let mut reporter = backend
+            .address_reporter(Device::new(Model::LibraV0, "L1"))
+            .with_source(source)
+            .with_options(options());
+        reporter.run_until(|reporter| {
+            reporter
+                .reported()
+                .is_some_and(|address| address.to_string() == "10.0.0.9:8080")
+        });
+
+        first.assert();
+        outage.assert();
+        second.assert();
+        assert!(reporter.last_error().is_none());
+    }
+
+    #[test]
+    fn test_current_address_prefers_routable_ipv4() {
+        let backend = ConfigBackend::new("http://localhost".to_string(), String::new());
+        let reporter = backend
+            .address_reporter(Device::new(Model::LibraV0, "L1"))
+            .with_source(|| {
+                Ok(["::1", "fe80::1", "169.254.0.3", "192.168.1.20"]
+                    .iter()
+                    .map(|ip| ip.parse().unwrap())
+                    .collect())
+            });
+        assert_eq!(
+            reporter.current_address().unwrap().unwrap().to_string(),
+            "192.168.1.20"
+        );
+        let reporter = reporter.with_source(|| {
+            Ok(["fe80::1", "169.254.0.3", "fd00::7"]
+                .iter()
+                .map(|ip| ip.parse().unwrap())
+                .collect())
+        });
+        assert_eq!(
+            reporter.current_address().unwrap().unwrap().to_string(),
+            "fd00::7"
+        );
+        let reporter = reporter.with_source(|| {
+            Ok(vec![
+                "fe80::1".parse().unwrap(),
+                "169.254.0.3".parse().unwrap(),
+            ])
+        });
+        assert_eq!(
+            reporter.current_address().unwrap().unwrap().to_string(),
+            "169.254.0.3"
+        );
+        let reporter = reporter.with_source(|| Ok(vec!["fe80::1".parse().unwrap()]));
+        assert_eq!(reporter.current_address().unwrap(), None);
+        let mut reporter = reporter.with_source(|| Ok(vec![]));
+        assert_eq!(reporter.current_address().unwrap(), None);
+        assert_eq!(reporter.step(), ReporterOptions::default().check_interval);
+        assert_eq!(reporter.reported(), None);
+    }
+
+    #[test]
+    fn test_local_interfaces_include_loopback() {
+        let addresses = LocalInterfaces.addresses().unwrap();
+        assert!(addresses.iter().any(IpAddr::is_loopback));
+    }
+}
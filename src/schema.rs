@@ -0,0 +1,84 @@
+use crate::device::Device;
+use crate::error::Error;
+use crate::ichibu::{Ichibu, ScaleConfig};
+use crate::libra::{Config, Libra};
+use crate::libra_data::LibraData;
+use schemars::{JsonSchema, Schema, schema_for};
+use std::fs;
+use std::path::Path;
+
+/// Directory, relative to the crate root, holding the committed schema documents.
+pub const SCHEMA_DIR: &str = "schemas";
+
+/// JSON Schema documents for every config type, keyed by type name.
+pub fn schemas() -> Vec<(&'static str, Schema)> {
+    vec![
+        named::<Libra>("Libra"),
+        named::<Config>("Config"),
+        named::<Ichibu>("Ichibu"),
+        named::<ScaleConfig>("ScaleConfig"),
+        named::<Device>("Device"),
+        named::<LibraData>("LibraData"),
+    ]
+}
+
+fn named<T: JsonSchema>(name: &'static str) -> (&'static str, Schema) {
+    (name, schema_for!(T))
+}
+
+/// Renders a schema the way it is committed, as pretty JSON with a trailing newline.
+pub fn to_json_string(schema: &Schema) -> Result<String, Error> {
+    let mut json = serde_json::to_string_pretty(schema).map_err(Error::JsonGeneration)?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Writes every schema to `{dir}/{name}.schema.json`, overwriting existing files.
+pub fn write_schemas(dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dir).map_err(|e| Error::file_system(dir, e))?;
+    for (name, schema) in schemas() {
+        let path = dir.join(format!("{name}.schema.json"));
+        fs::write(&path, to_json_string(&schema)?).map_err(|e| Error::file_system(&path, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn schema_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(SCHEMA_DIR)
+    }
+
+    /// Run with `MENU_BLESS_SCHEMAS=1` to regenerate the committed schemas after changing a type.
+    #[test]
+    fn test_committed_schemas_match_types() {
+        if std::env::var_os("MENU_BLESS_SCHEMAS").is_some() {
+            write_schemas(&schema_dir()).unwrap();
+        }
+        for (name, schema) in schemas() {
+            let path = schema_dir().join(format!("{name}.schema.json"));
+            let committed = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Couldn't read {}: {e}", path.display()));
+            assert_eq!(
+                committed,
+                to_json_string(&schema).unwrap(),
+                "{name} schema drifted from the Rust type, rerun the tests with MENU_BLESS_SCHEMAS=1"
+            );
+        }
+    }
+
+    #[test]
+    fn test_schema_validation_ranges() {
+        let schema = serde_json::to_value(schema_for!(Config)).unwrap();
+        let properties = &schema["properties"];
+        assert_eq!(properties["buffer_length"]["minimum"], 1);
+        assert_eq!(properties["max_noise"]["minimum"], 0.0);
+        assert_eq!(
+            properties["gain"]["description"],
+            "Multiplier converting a raw bridge reading into grams."
+        );
+    }
+}
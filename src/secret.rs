@@ -0,0 +1,333 @@
+use crate::error::Error;
+#[cfg(feature = "secret")]
+use base64::Engine;
+#[cfg(feature = "secret")]
+use base64::engine::general_purpose::STANDARD as BASE64;
+#[cfg(feature = "secret")]
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+#[cfg(feature = "secret")]
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+#[cfg(feature = "secret")]
+use std::fs;
+#[cfg(feature = "secret")]
+use std::io::Write;
+#[cfg(feature = "secret")]
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Formatted, Item, Value};
+
+/// Marks a TOML string value as encrypted: `"!enc:<base64 nonce and ciphertext>"`.
+pub const ENCRYPTED_PREFIX: &str = "!enc:";
+/// Environment variable naming the key file used to decrypt config files.
+#[cfg(feature = "secret")]
+pub const KEY_FILE_VAR: &str = "MENU_KEY_FILE";
+
+#[cfg(feature = "secret")]
+const NONCE_LEN: usize = 12;
+
+/// A value that never shows up in `Debug` or `Display` output, so it can't leak into logs.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret<String> {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(<redacted>)")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<redacted>")
+    }
+}
+
+/// Key for the encrypted values in config files.
+#[cfg(feature = "secret")]
+#[derive(Clone)]
+pub struct SecretKey {
+    key: Key,
+}
+
+#[cfg(feature = "secret")]
+impl SecretKey {
+    pub fn generate() -> Self {
+        Self {
+            key: ChaCha20Poly1305::generate_key(&mut OsRng),
+        }
+    }
+    /// Reads a base64 key, as written by [`SecretKey::save`].
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path).map_err(|e| Error::file_system(path, e))?;
+        let bytes = BASE64
+            .decode(content.trim())
+            .ok()
+            .filter(|bytes| bytes.len() == 32)
+            .ok_or_else(|| Error::validation("key", "expected 32 base64-encoded bytes"))?;
+        Ok(Self {
+            key: *Key::from_slice(&bytes),
+        })
+    }
+    /// Reads the key file named by `MENU_KEY_FILE`.
+    pub fn from_env() -> Result<Self, Error> {
+        let path = std::env::var(KEY_FILE_VAR).map_err(|source| Error::Env {
+            name: KEY_FILE_VAR.to_string(),
+            source,
+        })?;
+        Self::from_file(&PathBuf::from(path))
+    }
+    /// Writes the key as base64, readable only by its owner on Unix.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(BASE64.encode(self.key).as_bytes()))
+            .map_err(|e| Error::file_system(path, e))
+    }
+    /// Encrypts `plaintext` into an `!enc:` value, with a fresh nonce each time.
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&self.key)
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("encrypting into a Vec can't fail");
+        format!(
+            "{ENCRYPTED_PREFIX}{}",
+            BASE64.encode([nonce.as_slice(), &ciphertext].concat())
+        )
+    }
+    /// Decrypts an `!enc:` value.
+    pub fn decrypt(&self, value: &str) -> Result<String, Error> {
+        let undecryptable = || Error::validation("secret", "can't be decrypted with this key");
+        let bytes = value
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .and_then(|encoded| BASE64.decode(encoded).ok())
+            .filter(|bytes| bytes.len() > NONCE_LEN)
+            .ok_or_else(undecryptable)?;
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = ChaCha20Poly1305::new(&self.key)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| undecryptable())?;
+        String::from_utf8(plaintext).map_err(|_| undecryptable())
+    }
+    /// Replaces every `!enc:` string value in a TOML document with its plaintext. Comments,
+    /// other strings and the document's formatting are left alone.
+    pub fn decrypt_toml(&self, input: &str) -> Result<String, Error> {
+        let mut document = input
+            .parse::<DocumentMut>()
+            .map_err(|e| Error::toml_edit(input, e))?;
+        decrypt_item(document.as_item_mut(), self)?;
+        Ok(document.to_string())
+    }
+}
+
+#[cfg(feature = "secret")]
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey(<redacted>)")
+    }
+}
+
+/// Stands in for [`SecretKey`] without the `secret` feature. It has no values: files with
+/// `!enc:` values can't be read then, so there is never a key to decrypt them with.
+#[cfg(not(feature = "secret"))]
+#[derive(Clone)]
+pub(crate) enum SecretKey {}
+
+#[cfg(not(feature = "secret"))]
+impl SecretKey {
+    pub(crate) fn from_env() -> Result<Self, Error> {
+        Err(Error::validation(
+            "secret",
+            "encrypted values need the `secret` feature",
+        ))
+    }
+    #[cfg(feature = "write")]
+    pub(crate) fn encrypt(&self, _plaintext: &str) -> String {
+        match *self {}
+    }
+    pub(crate) fn decrypt(&self, _value: &str) -> Result<String, Error> {
+        match *self {}
+    }
+}
+
+pub(crate) fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// Whether any string value in `item` is encrypted.
+pub(crate) fn contains_encrypted(item: &Item) -> bool {
+    match item {
+        Item::Value(value) => value_contains_encrypted(value),
+        Item::Table(table) => table.iter().any(|(_, item)| contains_encrypted(item)),
+        Item::ArrayOfTables(tables) => tables
+            .iter()
+            .any(|table| table.iter().any(|(_, item)| contains_encrypted(item))),
+        Item::None => false,
+    }
+}
+
+fn value_contains_encrypted(value: &Value) -> bool {
+    match value {
+        Value::String(string) => is_encrypted(string.value()),
+        Value::Array(array) => array.iter().any(value_contains_encrypted),
+        Value::InlineTable(table) => table
+            .iter()
+            .any(|(_, value)| value_contains_encrypted(value)),
+        _ => false,
+    }
+}
+
+/// Decrypts every encrypted string value in `item`, keeping its surrounding whitespace and
+/// comments.
+fn decrypt_item(item: &mut Item, key: &SecretKey) -> Result<(), Error> {
+    match item {
+        Item::Value(value) => decrypt_value(value, key),
+        Item::Table(table) => table
+            .iter_mut()
+            .try_for_each(|(_, item)| decrypt_item(item, key)),
+        Item::ArrayOfTables(tables) => tables.iter_mut().try_for_each(|table| {
+            table
+                .iter_mut()
+                .try_for_each(|(_, item)| decrypt_item(item, key))
+        }),
+        Item::None => Ok(()),
+    }
+}
+
+fn decrypt_value(value: &mut Value, key: &SecretKey) -> Result<(), Error> {
+    match value {
+        Value::String(string) if is_encrypted(string.value()) => {
+            let mut plaintext = Formatted::new(key.decrypt(string.value())?);
+            *plaintext.decor_mut() = string.decor().clone();
+            *string = plaintext;
+            Ok(())
+        }
+        Value::Array(array) => array
+            .iter_mut()
+            .try_for_each(|value| decrypt_value(value, key)),
+        Value::InlineTable(table) => table
+            .iter_mut()
+            .try_for_each(|(_, value)| decrypt_value(value, key)),
+        _ => Ok(()),
+    }
+}
+
+/// Decrypts a TOML document's `!enc:` string values with the key from `MENU_KEY_FILE`,
+/// loading the key only when the document has any. A document that doesn't parse is
+/// returned as is, for the caller to report.
+pub(crate) fn decrypt_toml(input: String) -> Result<String, Error> {
+    let Ok(mut document) = input.parse::<DocumentMut>() else {
+        return Ok(input);
+    };
+    if !contains_encrypted(document.as_item()) {
+        return Ok(input);
+    }
+    decrypt_item(document.as_item_mut(), &SecretKey::from_env()?)?;
+    Ok(document.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_never_prints() {
+        let token: Secret<String> = "hunter2".into();
+        assert_eq!(format!("{token}"), "<redacted>");
+        assert!(!format!("{token:?}").contains("hunter2"));
+        assert_eq!(token.expose(), "hunter2");
+        assert_eq!(serde_json::to_string(&token).unwrap(), "\"hunter2\"");
+    }
+
+    #[cfg(not(feature = "secret"))]
+    #[test]
+    fn test_encrypted_values_need_the_feature() {
+        assert_eq!(decrypt_toml("a = 1\n".into()).unwrap(), "a = 1\n");
+        let unencrypted = "a = \"x !enc:AAAA\" # \"!enc:AAAA\"\nb = [{ c = \"1 !enc:\" }]\n";
+        assert_eq!(decrypt_toml(unencrypted.into()).unwrap(), unencrypted);
+        assert_eq!(decrypt_toml("a = \"".into()).unwrap(), "a = \"");
+        assert_eq!(
+            decrypt_toml("a = \"!enc:AAAA\"\n".into())
+                .unwrap_err()
+                .code(),
+            "validation"
+        );
+    }
+
+    #[cfg(feature = "secret")]
+    #[test]
+    fn test_encrypt_round_trip() {
+        let key = SecretKey::generate();
+        let encrypted = key.encrypt("2744");
+        assert!(encrypted.starts_with(ENCRYPTED_PREFIX));
+        assert_ne!(encrypted, key.encrypt("2744"));
+        assert_eq!(key.decrypt(&encrypted).unwrap(), "2744");
+        assert_eq!(
+            SecretKey::generate()
+                .decrypt(&encrypted)
+                .unwrap_err()
+                .code(),
+            "validation"
+        );
+    }
+
+    #[cfg(feature = "secret")]
+    #[test]
+    fn test_decrypt_toml() {
+        let key = SecretKey::generate();
+        let input = format!(
+            "[pins]\nmanager = \"{}\"\noperator = '{}'\nsudo = \"101010101\" # or \"!enc:...\"\n\
+             [[tokens]]\nvalues = [\"x !enc:\", {{ id = \"{}\" }}]\n",
+            key.encrypt("27\"44"),
+            key.encrypt("200"),
+            key.encrypt("t0k"),
+        );
+        let output = key.decrypt_toml(&input).unwrap();
+        assert_eq!(
+            output,
+            "[pins]\nmanager = '27\"44'\noperator = \"200\"\nsudo = \"101010101\" # or \"!enc:...\"\n\
+             [[tokens]]\nvalues = [\"x !enc:\", { id = \"t0k\" }]\n"
+        );
+        let value: toml::Table = toml::from_str(&output).unwrap();
+        assert_eq!(value["pins"]["manager"].as_str(), Some("27\"44"));
+    }
+
+    #[cfg(feature = "secret")]
+    #[test]
+    fn test_key_file() {
+        let path = std::env::temp_dir().join("menu-test-secret.key");
+        let key = SecretKey::generate();
+        key.save(&path).unwrap();
+        let loaded = SecretKey::from_file(&path).unwrap();
+        assert_eq!(loaded.decrypt(&key.encrypt("token")).unwrap(), "token");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+use crate::audit::{self, Operation, default_auditor};
+use crate::diff::{FieldChange, diff_values};
+use crate::error::Error;
+use crate::format::Format;
+use crate::libra::file_source;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+const RETENTION_FILE: &str = "retention.toml";
+
+/// How many snapshots of a config file to keep. Snapshots beyond either limit are pruned
+/// after each new one is taken, though the newest is always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Most snapshots to keep, or `None` for no limit.
+    pub max_count: Option<usize>,
+    /// Oldest snapshot to keep, or `None` for no limit.
+    pub max_age: Option<Duration>,
+}
+
+/// Twenty snapshots, none older than 30 days.
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_count: Some(20),
+            max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        }
+    }
+}
+
+/// A copy of a config file taken before it was written.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Snapshot {
+    pub taken_at: OffsetDateTime,
+    pub path: PathBuf,
+}
+
+/// The snapshots of one config file, kept in a hidden sibling directory named
+/// `.{file name}.snapshots`. The retention policy is stored there too, so it applies to every
+/// write made through the crate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshots {
+    file_path: PathBuf,
+    dir: PathBuf,
+}
+
+impl Snapshots {
+    pub fn new(file_path: &Path) -> Self {
+        let file_name = file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self {
+            file_path: file_path.to_path_buf(),
+            dir: file_path.with_file_name(format!(".{file_name}.snapshots")),
+        }
+    }
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+    pub fn retention(&self) -> Result<RetentionPolicy, Error> {
+        let path = self.dir.join(RETENTION_FILE);
+        if !path.exists() {
+            return Ok(RetentionPolicy::default());
+        }
+        let content = fs::read_to_string(&path).map_err(|e| Error::file_system(&path, e))?;
+        toml::from_str(&content).map_err(|e| Error::toml_read(&content, e).with_path(&path))
+    }
+    pub fn set_retention(&self, retention: RetentionPolicy) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir).map_err(|e| Error::file_system(&self.dir, e))?;
+        let path = self.dir.join(RETENTION_FILE);
+        let content = toml::to_string(&retention).map_err(Error::TomlGeneration)?;
+        fs::write(&path, content).map_err(|e| Error::file_system(&path, e))
+    }
+    /// Copies the config file into a new snapshot, then prunes old ones. Returns `None` if
+    /// the file doesn't exist yet.
+    pub fn take(&self) -> Result<Option<Snapshot>, Error> {
+        if !self.file_path.exists() {
+            return Ok(None);
+        }
+        fs::create_dir_all(&self.dir).map_err(|e| Error::file_system(&self.dir, e))?;
+        let mut taken_at = OffsetDateTime::now_utc();
+        let mut path = self.snapshot_path(taken_at);
+        while path.exists() {
+            taken_at += Duration::from_nanos(1);
+            path = self.snapshot_path(taken_at);
+        }
+        fs::copy(&self.file_path, &path).map_err(|e| Error::file_system(&path, e))?;
+        self.prune(&self.retention()?)?;
+        Ok(Some(Snapshot { taken_at, path }))
+    }
+    /// Every snapshot, oldest first.
+    pub fn list(&self) -> Result<Vec<Snapshot>, Error> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let entries = fs::read_dir(&self.dir).map_err(|e| Error::file_system(&self.dir, e))?;
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| Error::file_system(&self.dir, e))?.path();
+            let taken_at = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<i128>().ok())
+                .and_then(|nanos| OffsetDateTime::from_unix_timestamp_nanos(nanos).ok());
+            if let Some(taken_at) = taken_at {
+                snapshots.push(Snapshot { taken_at, path });
+            }
+        }
+        snapshots.sort();
+        Ok(snapshots)
+    }
+    /// Removes the snapshots `retention` doesn't keep, returning how many were removed.
+    pub fn prune(&self, retention: &RetentionPolicy) -> Result<usize, Error> {
+        let snapshots = self.list()?;
+        let now = OffsetDateTime::now_utc();
+        let count = snapshots.len();
+        let mut removed = 0;
+        // The newest snapshot is always kept, so a bad write can still be undone.
+        for (index, snapshot) in snapshots.iter().enumerate().take(count.saturating_sub(1)) {
+            let too_many = retention
+                .max_count
+                .is_some_and(|max_count| count - index > max_count);
+            let too_old = retention
+                .max_age
+                .is_some_and(|max_age| now - snapshot.taken_at > max_age);
+            if too_many || too_old {
+                fs::remove_file(&snapshot.path)
+                    .map_err(|e| Error::file_system(&snapshot.path, e))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+    /// Replaces the whole config file with `snapshot`. The current file is snapshotted
+    /// first, so a restore can itself be undone. Recorded as an edit with the default
+    /// auditor, if set.
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<(), Error> {
+        let content = fs::read_to_string(&snapshot.path)
+            .map_err(|e| Error::file_system(&snapshot.path, e))?;
+        let current = match fs::read_to_string(&self.file_path) {
+            Ok(current) => Some(current),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(Error::file_system(&self.file_path, e)),
+        };
+        let changes = self.changes(current.as_deref(), &content);
+        let auditor = default_auditor();
+        let pending = audit::begin(
+            auditor.as_ref(),
+            None,
+            Operation::Edit,
+            file_source(&self.file_path),
+            changes,
+        )?;
+        pending.finish(
+            self.take()
+                .and_then(|_| write_atomically(&self.file_path, content.as_bytes())),
+        )
+    }
+    /// The fields changed by replacing `old` with `new`, a missing file counting as an empty
+    /// table. If either doesn't parse, the whole text is reported as one change.
+    fn changes(&self, old: Option<&str>, new: &str) -> Vec<FieldChange> {
+        let format = Format::from_path(&self.file_path);
+        let parse = |content: Option<&str>| match content {
+            Some(content) => format.deserialize::<Value>(content).ok(),
+            None => Some(Value::Object(Default::default())),
+        };
+        match (parse(old), parse(Some(new))) {
+            (Some(old), Some(new)) => diff_values(&old, &new),
+            _ if old == Some(new) => Vec::new(),
+            _ => vec![FieldChange {
+                path: String::new(),
+                old: old.map(Value::from),
+                new: Some(Value::from(new)),
+            }],
+        }
+    }
+    fn snapshot_path(&self, taken_at: OffsetDateTime) -> PathBuf {
+        let mut path = self.dir.join(taken_at.unix_timestamp_nanos().to_string());
+        if let Some(extension) = self.file_path.extension() {
+            path.set_extension(extension);
+        }
+        path
+    }
+}
+
+/// Snapshots `file_path` before the crate writes to it.
+pub(crate) fn before_write(file_path: &Path) -> Result<(), Error> {
+    Snapshots::new(file_path).take().map(|_| ())
+}
+
+/// Writes `contents` to a sibling temporary file and renames it over `file_path`, so the
+/// file is never missing or half-written, even if the process dies mid-write.
+pub(crate) fn write_atomically(file_path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let mut temporary = file_path.to_path_buf().into_os_string();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    fs::write(&temporary, contents).map_err(|e| Error::file_system(&temporary, e))?;
+    fs::rename(&temporary, file_path).map_err(|e| Error::file_system(file_path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestFile(PathBuf);
+    impl TestFile {
+        fn new(name: &str, content: &str) -> Self {
+            let mut path = std::env::temp_dir();
+            path.push(format!("menu-test-{name}"));
+            let _ = fs::remove_dir_all(Snapshots::new(&path).dir());
+            fs::write(&path, content).unwrap();
+            Self(path)
+        }
+    }
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+            let _ = fs::remove_dir_all(Snapshots::new(&self.0).dir());
+        }
+    }
+
+    #[test]
+    fn test_take_list_and_restore() {
+        let file = TestFile::new("snapshot_restore.toml", "a = 1\n");
+        let snapshots = Snapshots::new(&file.0);
+        assert!(
+            snapshots
+                .dir()
+                .ends_with(".menu-test-snapshot_restore.toml.snapshots")
+        );
+
+        let first = snapshots.take().unwrap().unwrap();
+        assert_eq!(first.path.extension().unwrap(), "toml");
+        fs::write(&file.0, "a = 2\n").unwrap();
+        snapshots.take().unwrap();
+        fs::write(&file.0, "a = 3\n").unwrap();
+        assert_eq!(snapshots.list().unwrap().len(), 2);
+        assert_eq!(snapshots.list().unwrap()[0], first);
+
+        snapshots.restore(&first).unwrap();
+        assert_eq!(fs::read_to_string(&file.0).unwrap(), "a = 1\n");
+        let list = snapshots.list().unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(fs::read_to_string(&list[2].path).unwrap(), "a = 3\n");
+    }
+
+    #[test]
+    fn test_restore_changes() {
+        let file = TestFile::new("snapshot_changes.toml", "a = 1\nb = 2\n");
+        let snapshots = Snapshots::new(&file.0);
+        let changes = snapshots.changes(Some("a = 1\nb = 2\n"), "a = 1\nb = 3\n");
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, "b");
+        assert_eq!(snapshots.changes(None, "a = 1\n")[0].path, "a");
+        let unparsed = snapshots.changes(Some("a = "), "a = 1\n");
+        assert_eq!(unparsed[0].path, "");
+        assert_eq!(unparsed[0].old, Some(Value::from("a = ")));
+        assert!(snapshots.changes(Some("a = "), "a = ").is_empty());
+    }
+
+    #[test]
+    fn test_retention() {
+        let file = TestFile::new("snapshot_retention.json", "{}");
+        let snapshots = Snapshots::new(&file.0);
+        assert_eq!(snapshots.retention().unwrap(), RetentionPolicy::default());
+        let retention = RetentionPolicy {
+            max_count: Some(2),
+            max_age: None,
+        };
+        snapshots.set_retention(retention).unwrap();
+        assert_eq!(snapshots.retention().unwrap(), retention);
+
+        for _ in 0..4 {
+            snapshots.take().unwrap();
+        }
+        assert_eq!(snapshots.list().unwrap().len(), 2);
+
+        let removed = snapshots
+            .prune(&RetentionPolicy {
+                max_count: None,
+                max_age: Some(Duration::ZERO),
+            })
+            .unwrap();
+        assert_eq!(removed, 1);
+        assert_eq!(snapshots.list().unwrap().len(), 1, "the newest is kept");
+    }
+
+    #[test]
+    fn test_missing_file_has_no_snapshot() {
+        let mut path = std::env::temp_dir();
+        path.push("menu-test-snapshot_missing.toml");
+        assert_eq!(Snapshots::new(&path).take().unwrap(), None);
+        assert!(Snapshots::new(&path).list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+use crate::backend::ConfigBackend;
+use crate::backend::write::Versioned;
+use crate::device::Device;
+use crate::error::Error;
+use crate::libra::Config;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How a [`Subscription`] polls and backs off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscribeOptions {
+    /// How long the backend may hold each request open waiting for a change. Devices are
+    /// polled one after another, so a change can take up to this long per device to arrive.
+    pub wait: Duration,
+    /// Pause between rounds of requests, for backends that answer without waiting.
+    pub poll_interval: Duration,
+    /// First delay before reconnecting after a failed request; doubles with each failure.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for SubscribeOptions {
+    fn default() -> Self {
+        Self {
+            wait: Duration::from_secs(30),
+            poll_interval: Duration::from_secs(5),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A config that changed on the backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigChange {
+    pub device: Device,
+    pub config: Config,
+    pub etag: Option<String>,
+}
+
+/// Watches the backend for config changes to a set of devices by long-polling each with
+/// a conditional GET.
+///
+/// The first round yields every device's current config. A device whose request fails is
+/// retried with exponential backoff while the others keep being polled, so iterating never
+/// ends; [`Subscription::last_error`] tells why devices are backing off.
+pub struct Subscription<'a> {
+    backend: &'a ConfigBackend,
+    options: SubscribeOptions,
+    watches: Vec<Watch>,
+    pending: VecDeque<ConfigChange>,
+    last_error: Option<Error>,
+}
+
+/// One subscribed device and where polling it stands.
+struct Watch {
+    device: Device,
+    etag: Option<String>,
+    /// The config last yielded, which tells a resent config from a change when the backend
+    /// sends no ETag.
+    config: Option<Config>,
+    /// The current backoff and when the device may be polled again, while it's failing.
+    backoff: Option<(Duration, Instant)>,
+}
+
+impl ConfigBackend {
+    pub fn subscribe(&self, devices: &[Device], options: SubscribeOptions) -> Subscription<'_> {
+        Subscription {
+            backend: self,
+            options,
+            watches: devices
+                .iter()
+                .map(|device| Watch {
+                    device: device.clone(),
+                    etag: None,
+                    config: None,
+                    backoff: None,
+                })
+                .collect(),
+            pending: VecDeque::new(),
+            last_error: None,
+        }
+    }
+}
+
+impl Subscription<'_> {
+    /// The latest error behind a backoff, cleared once no device is backing off.
+    pub fn last_error(&self) -> Option<&Error> {
+        self.last_error.as_ref()
+    }
+    /// Like [`Iterator::next`], for async callers.
+    pub async fn next_async(&mut self) -> ConfigChange {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return change;
+            }
+            let wait = self.wait();
+            for index in self.due() {
+                let watch = &self.watches[index];
+                let result = self
+                    .backend
+                    .fetch_config_async(&watch.device, watch.etag.as_deref(), wait)
+                    .await;
+                self.record(index, result);
+            }
+            if self.pending.is_empty() {
+                tokio::time::sleep(self.pause()).await;
+            }
+        }
+    }
+    /// Long-polls once every device has been fetched, or is failing, so the first round
+    /// yields every config without waiting on any of them.
+    fn wait(&self) -> Option<Duration> {
+        self.watches
+            .iter()
+            .all(|watch| watch.config.is_some() || watch.backoff.is_some())
+            .then_some(self.options.wait)
+    }
+    /// The devices to poll this round: all but those still backing off.
+    fn due(&self) -> Vec<usize> {
+        let now = Instant::now();
+        (0..self.watches.len())
+            .filter(|&index| {
+                self.watches[index]
+                    .backoff
+                    .is_none_or(|(_, retry_at)| retry_at <= now)
+            })
+            .collect()
+    }
+    /// How long to sleep after a round without changes: the poll interval, or less if a
+    /// failing device is due sooner.
+    fn pause(&self) -> Duration {
+        let retry = self
+            .watches
+            .iter()
+            .filter_map(|watch| watch.backoff.map(|(_, retry_at)| retry_at))
+            .min()
+            .map(|retry_at| retry_at.saturating_duration_since(Instant::now()));
+        let polling = self.watches.iter().any(|watch| watch.backoff.is_none());
+        match retry {
+            Some(retry) if polling => retry.min(self.options.poll_interval),
+            Some(retry) => retry,
+            None => self.options.poll_interval,
+        }
+    }
+    /// Queues a changed config, or backs the device off after a failure.
+    fn record(&mut self, index: usize, result: Result<Option<Versioned<Config>>, Error>) {
+        let watch = &mut self.watches[index];
+        let versioned = match result {
+            Ok(versioned) => versioned,
+            Err(error) => {
+                let backoff = match watch.backoff {
+                    Some((backoff, _)) => (backoff * 2).min(self.options.max_backoff),
+                    None => self.options.initial_backoff,
+                };
+                watch.backoff = Some((backoff, Instant::now() + backoff));
+                self.last_error = Some(error);
+                return;
+            }
+        };
+        // Backends that ignore `If-None-Match`, or send no ETag at all, resend unchanged
+        // configs.
+        if let Some(versioned) = versioned
+            && match &versioned.etag {
+                Some(_) => versioned.etag != watch.etag,
+                None => watch.config.as_ref() != Some(&versioned.value),
+            }
+        {
+            watch.etag.clone_from(&versioned.etag);
+            watch.config = Some(versioned.value.clone());
+            self.pending.push_back(ConfigChange {
+                device: watch.device.clone(),
+                config: versioned.value,
+                etag: versioned.etag,
+            });
+        }
+        watch.backoff = None;
+        if self.watches.iter().all(|watch| watch.backoff.is_none()) {
+            self.last_error = None;
+        }
+    }
+}
+
+impl Iterator for Subscription<'_> {
+    type Item = ConfigChange;
+
+    /// Blocks until one of the devices' configs changes.
+    fn next(&mut self) -> Option<ConfigChange> {
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Some(change);
+            }
+            let wait = self.wait();
+            for index in self.due() {
+                let watch = &self.watches[index];
+                let result = self
+                    .backend
+                    .fetch_config(&watch.device, watch.etag.as_deref(), wait);
+                self.record(index, result);
+            }
+            if self.pending.is_empty() {
+                std::thread::sleep(self.pause());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Model;
+    use mockito::Matcher;
+
+    fn options() -> SubscribeOptions {
+        SubscribeOptions {
+            wait: Duration::from_secs(20),
+            poll_interval: Duration::from_millis(1),
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    #[test]
+    fn test_subscription_yields_changes_and_recovers() {
+        let mut server = mockito::Server::new();
+        let device = Device::new(Model::LibraV0, "L1");
+        let changed = Config {
+            ingredient: "Tofu".into(),
+            ..Config::default()
+        };
+        let initial = server
+            .mock("GET", "/LibraV0/L1")
+            .match_header("if-none-match", Matcher::Missing)
+            .with_header("etag", "v1")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .create();
+        let unchanged = server
+            .mock("GET", "/LibraV0/L1")
+            .match_header("if-none-match", "v1")
+            .match_header("prefer", "wait=20")
+            .with_status(304)
+            .expect(1)
+            .create();
+        let outage = server
+            .mock("GET", "/LibraV0/L1")
+            .match_header("if-none-match", "v1")
+            .with_status(503)
+            .expect(2)
+            .create();
+        server
+            .mock("GET", "/LibraV0/L1")
+            .match_header("if-none-match", "v1")
+            .with_header("etag", "v2")
+            .with_body(serde_json::to_string(&changed).unwrap())
+            .create();
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let mut subscription = backend.subscribe(std::slice::from_ref(&device), options());
+        let first = subscription.next().unwrap();
+        assert_eq!(first.config, Config::default());
+        let second = subscription.next().unwrap();
+        assert_eq!(second.device, device);
+        assert_eq!(second.config, changed);
+        assert_eq!(second.etag.as_deref(), Some("v2"));
+        assert!(subscription.last_error().is_none());
+
+        initial.assert();
+        unchanged.assert();
+        outage.assert();
+    }
+
+    #[test]
+    fn test_subscription_without_etags_skips_unchanged_configs() {
+        let mut server = mockito::Server::new();
+        let changed = Config {
+            ingredient: "Tofu".into(),
+            ..Config::default()
+        };
+        let initial = server
+            .mock("GET", "/LibraV0/L1")
+            .match_header("prefer", Matcher::Missing)
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .expect(1)
+            .create();
+        let resent = server
+            .mock("GET", "/LibraV0/L1")
+            .match_header("prefer", "wait=20")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .expect(2)
+            .create();
+        server
+            .mock("GET", "/LibraV0/L1")
+            .match_header("prefer", "wait=20")
+            .with_body(serde_json::to_string(&changed).unwrap())
+            .create();
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let device = Device::new(Model::LibraV0, "L1");
+        let mut subscription = backend.subscribe(&[device], options());
+        let first = subscription.next().unwrap();
+        assert_eq!(first.config, Config::default());
+        assert_eq!(first.etag, None);
+        assert_eq!(subscription.next().unwrap().config, changed);
+        initial.assert();
+        resent.assert();
+    }
+
+    #[test]
+    fn test_subscription_keeps_polling_around_a_failing_device() {
+        let mut server = mockito::Server::new();
+        let devices = [
+            Device::new(Model::LibraV0, "L1"),
+            Device::new(Model::LibraV0, "L2"),
+        ];
+        let missing = server
+            .mock("GET", "/LibraV0/L1")
+            .with_status(404)
+            .expect_at_least(2)
+            .create();
+        server
+            .mock("GET", "/LibraV0/L2")
+            .match_header("if-none-match", Matcher::Missing)
+            .with_header("etag", "b1")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .create();
+        let changed = Config {
+            ingredient: "Tofu".into(),
+            ..Config::default()
+        };
+        server
+            .mock("GET", "/LibraV0/L2")
+            .match_header("if-none-match", "b1")
+            .match_header("prefer", "wait=20")
+            .with_status(304)
+            .expect(2)
+            .create();
+        server
+            .mock("GET", "/LibraV0/L2")
+            .match_header("if-none-match", "b1")
+            .with_header("etag", "b2")
+            .with_body(serde_json::to_string(&changed).unwrap())
+            .create();
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let mut subscription = backend.subscribe(&devices, options());
+        assert_eq!(subscription.next().unwrap().etag.as_deref(), Some("b1"));
+        let next = subscription.next().unwrap();
+        assert_eq!(next.device, devices[1]);
+        assert_eq!(next.config, changed);
+        // L1 is still backing off, and says why.
+        assert_eq!(subscription.last_error().unwrap().code(), "backend");
+        missing.assert();
+    }
+
+    #[tokio::test]
+    async fn test_subscription_async_reports_backoff_error() {
+        let mut server = mockito::Server::new_async().await;
+        let devices = [
+            Device::new(Model::LibraV0, "L1"),
+            Device::new(Model::LibraV0, "L2"),
+        ];
+        server
+            .mock("GET", "/LibraV0/L1")
+            .with_header("etag", "a1")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/LibraV0/L2")
+            .with_status(500)
+            .expect(1)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/LibraV0/L2")
+            .with_header("etag", "b1")
+            .with_body(serde_json::to_string(&Config::default()).unwrap())
+            .create_async()
+            .await;
+
+        let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+        let mut subscription = backend.subscribe(&devices, options());
+        assert_eq!(subscription.next_async().await.device, devices[0]);
+        // L2 failed in the same round; the change already seen is still delivered.
+        assert_eq!(subscription.last_error().unwrap().code(), "backend");
+        let next = subscription.next_async().await;
+        assert_eq!(next.device, devices[1]);
+        assert_eq!(next.etag.as_deref(), Some("b1"));
+        assert!(subscription.last_error().is_none());
+    }
+}
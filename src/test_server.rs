@@ -0,0 +1,562 @@
+use crate::backend::ConfigBackend;
+use crate::device::{Device, Model};
+use crate::libra_data::LibraData;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tiny_http::{Header, Request, Response, Server};
+
+/// Token the fake accepts unless changed with [`TestServer::set_token`].
+pub const TEST_TOKEN: &str = "test-token";
+
+/// A failure the fake injects into a request instead of (or before) handling it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Waits before handling the request normally.
+    Latency(Duration),
+    /// Answers with this status, e.g. 500 or 503.
+    Status(u16),
+    /// Answers 401 as if the token had expired.
+    Unauthorized,
+}
+
+/// A request the fake received.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+}
+
+/// In-process, stateful fake of the Caldo backend for end-to-end tests.
+///
+/// Serves every route [`ConfigBackend`] uses from in-memory state on a local port, with
+/// ETags, merge patches, pagination, addresses and data uploads. Faults queued with
+/// [`TestServer::inject`] apply to the following requests, one each.
+pub struct TestServer {
+    url: String,
+    server: Arc<Server>,
+    state: Arc<Mutex<State>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+struct Entry {
+    device: Device,
+    config: Value,
+    version: u64,
+}
+
+struct State {
+    token: String,
+    devices: BTreeMap<(String, String), Entry>,
+    addresses: BTreeMap<(String, String), String>,
+    uploads: Vec<LibraData>,
+    faults: VecDeque<Fault>,
+    latency: Duration,
+    page_size: usize,
+    next_serial: u32,
+    requests: Vec<RecordedRequest>,
+}
+
+struct Reply {
+    status: u16,
+    body: String,
+    etag: Option<u64>,
+}
+
+impl Reply {
+    fn json(status: u16, body: &impl Serialize) -> Self {
+        Self {
+            status,
+            body: serde_json::to_string(body).unwrap_or_default(),
+            etag: None,
+        }
+    }
+    fn status(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            body: body.to_string(),
+            etag: None,
+        }
+    }
+    fn with_etag(mut self, version: u64) -> Self {
+        self.etag = Some(version);
+        self
+    }
+}
+
+impl TestServer {
+    /// Starts the fake on a free local port.
+    pub fn start() -> Self {
+        let server = Arc::new(Server::http("127.0.0.1:0").expect("binding a local port"));
+        let port = server
+            .server_addr()
+            .to_ip()
+            .expect("listening on TCP")
+            .port();
+        let state = Arc::new(Mutex::new(State {
+            token: TEST_TOKEN.to_string(),
+            devices: BTreeMap::new(),
+            addresses: BTreeMap::new(),
+            uploads: Vec::new(),
+            faults: VecDeque::new(),
+            latency: Duration::ZERO,
+            page_size: 50,
+            next_serial: 1,
+            requests: Vec::new(),
+        }));
+        let handle = {
+            let server = server.clone();
+            let state = state.clone();
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let state = state.clone();
+                    std::thread::spawn(move || handle(request, &state));
+                }
+            })
+        };
+        Self {
+            url: format!("http://127.0.0.1:{port}"),
+            server,
+            state,
+            handle: Some(handle),
+        }
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    /// A backend client pointed at the fake, using the accepted token.
+    pub fn backend(&self) -> ConfigBackend {
+        ConfigBackend::new(self.url.clone(), self.state().token.clone())
+    }
+    pub fn set_token(&self, token: &str) {
+        self.state().token = token.to_string();
+    }
+    /// Delay added to every request.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state().latency = latency;
+    }
+    /// Number of devices per page when listing.
+    pub fn set_page_size(&self, page_size: usize) {
+        self.state().page_size = page_size.max(1);
+    }
+    /// Queues a fault for the next request that doesn't already have one.
+    pub fn inject(&self, fault: Fault) {
+        self.state().faults.push_back(fault);
+    }
+    /// Registers a device with `config`, as if it had been provisioned earlier.
+    pub fn insert_device<T: Serialize>(&self, device: &Device, config: &T) {
+        let config = serde_json::to_value(config).expect("configs serialize to JSON");
+        self.state().devices.insert(
+            key(&device.model, device.serial_number.as_str()),
+            Entry {
+                device: device.clone(),
+                config,
+                version: 1,
+            },
+        );
+    }
+    /// The config the fake holds for `device`, if it is registered.
+    pub fn config<T: DeserializeOwned>(&self, device: &Device) -> Option<T> {
+        let state = self.state();
+        let entry = state
+            .devices
+            .get(&key(&device.model, device.serial_number.as_str()))?;
+        serde_json::from_value(entry.config.clone()).ok()
+    }
+    pub fn devices(&self) -> Vec<Device> {
+        let state = self.state();
+        state
+            .devices
+            .values()
+            .map(|entry| entry.device.clone())
+            .collect()
+    }
+    pub fn address(&self, device: &Device) -> Option<String> {
+        let state = self.state();
+        state
+            .addresses
+            .get(&key(&device.model, device.serial_number.as_str()))
+            .cloned()
+    }
+    /// Data uploaded to `POST /data`, in arrival order.
+    pub fn uploaded_data(&self) -> Vec<LibraData> {
+        self.state().uploads.clone()
+    }
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state().requests.clone()
+    }
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn key(model: &Model, serial_number: &str) -> (String, String) {
+    (model.route().to_string(), serial_number.to_string())
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+fn handle(mut request: Request, state: &Mutex<State>) {
+    let method = request.method().as_str().to_string();
+    let url = request.url().to_string();
+    let (fault, latency) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(RecordedRequest {
+            method: method.clone(),
+            url: url.clone(),
+        });
+        (state.faults.pop_front(), state.latency)
+    };
+    std::thread::sleep(latency);
+    let reply = match fault {
+        Some(Fault::Status(status)) => Some(Reply::status(status, "injected fault")),
+        Some(Fault::Unauthorized) => Some(Reply::status(401, "token expired")),
+        Some(Fault::Latency(delay)) => {
+            std::thread::sleep(delay);
+            None
+        }
+        None => None,
+    };
+    let reply = reply.unwrap_or_else(|| {
+        let mut body = String::new();
+        let _ = request.as_reader().read_to_string(&mut body);
+        let mut state = state.lock().unwrap();
+        let authorized = header(&request, "Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| token == state.token);
+        if !authorized {
+            return Reply::status(401, "missing or wrong bearer token");
+        }
+        route(&mut state, &request, &method, &url, &body)
+    });
+    let mut response = Response::from_string(reply.body).with_status_code(reply.status);
+    if let Some(version) = reply.etag {
+        let etag = format!("\"{version}\"");
+        response.add_header(Header::from_bytes("ETag", etag).expect("valid header"));
+    }
+    let _ = request.respond(response);
+}
+
+fn route(state: &mut State, request: &Request, method: &str, url: &str, body: &str) -> Reply {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let model = |route: &str| {
+        Model::ALL
+            .iter()
+            .find(|model| model.route() == route)
+            .cloned()
+    };
+    match (method, segments.as_slice()) {
+        ("POST", ["data"]) => upload(state, body),
+        ("GET" | "PUT", ["address", route, serial_number]) => match model(route) {
+            Some(model) => address(state, method, key(&model, serial_number), body),
+            None => Reply::status(404, "unknown model"),
+        },
+        ("POST" | "GET", [route]) => match model(route) {
+            Some(model) if method == "POST" => create(state, model, body),
+            Some(model) => list(state, model, query),
+            None => Reply::status(404, "unknown model"),
+        },
+        ("POST", [route, serial_number, "transfer"]) => match model(route) {
+            Some(model) => transfer(state, key(&model, serial_number), body),
+            None => Reply::status(404, "unknown model"),
+        },
+        (_, [route, serial_number]) => match model(route) {
+            Some(model) => device(state, request, method, key(&model, serial_number), body),
+            None => Reply::status(404, "unknown model"),
+        },
+        _ => Reply::status(404, "no such route"),
+    }
+}
+
+fn create(state: &mut State, model: Model, body: &str) -> Reply {
+    let Ok(config) = serde_json::from_str::<Value>(body) else {
+        return Reply::status(400, "config must be JSON");
+    };
+    let rules = model.serial_rules();
+    let digits = rules
+        .min_length
+        .saturating_sub(rules.prefix.len() + usize::from(rules.checksum));
+    let body = format!("{}{:0digits$}", rules.prefix, state.next_serial);
+    state.next_serial += 1;
+    let serial_number = rules.with_checksum(&body).unwrap_or(body);
+    let device = Device::new(model.clone(), &serial_number);
+    state.devices.insert(
+        key(&model, &serial_number),
+        Entry {
+            device: device.clone(),
+            config,
+            version: 1,
+        },
+    );
+    Reply::json(201, &device)
+}
+
+fn list(state: &State, model: Model, query: &str) -> Reply {
+    let start: usize = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("page_token="))
+        .and_then(|token| token.parse().ok())
+        .unwrap_or(0);
+    let devices: Vec<&Device> = state
+        .devices
+        .values()
+        .filter(|entry| entry.device.model == model)
+        .map(|entry| &entry.device)
+        .collect();
+    let end = (start + state.page_size).min(devices.len());
+    let page = devices.get(start..end).unwrap_or_default();
+    let next_page_token = (end < devices.len()).then(|| end.to_string());
+    Reply::json(
+        200,
+        &json!({ "devices": page, "next_page_token": next_page_token }),
+    )
+}
+
+fn device(
+    state: &mut State,
+    request: &Request,
+    method: &str,
+    key: (String, String),
+    body: &str,
+) -> Reply {
+    let Some(entry) = state.devices.get_mut(&key) else {
+        return Reply::status(404, "no such device");
+    };
+    let current = format!("\"{}\"", entry.version);
+    match method {
+        "GET" => {
+            if header(request, "If-None-Match") == Some(current.as_str()) {
+                return Reply::status(304, "").with_etag(entry.version);
+            }
+            Reply::json(200, &entry.config).with_etag(entry.version)
+        }
+        "PUT" | "PATCH" => {
+            if let Some(expected) = header(request, "If-Match")
+                && expected != current
+                && expected != "*"
+            {
+                return Reply::status(412, "config was changed");
+            }
+            let Ok(new) = serde_json::from_str::<Value>(body) else {
+                return Reply::status(400, "config must be JSON");
+            };
+            if method == "PUT" {
+                entry.config = new;
+            } else {
+                merge_patch(&mut entry.config, new);
+            }
+            entry.version += 1;
+            Reply::status(200, "").with_etag(entry.version)
+        }
+        "DELETE" => {
+            state.devices.remove(&key);
+            state.addresses.remove(&key);
+            Reply::status(204, "")
+        }
+        _ => Reply::status(405, "method not allowed"),
+    }
+}
+
+fn transfer(state: &mut State, key: (String, String), body: &str) -> Reply {
+    let Some(entry) = state.devices.get_mut(&key) else {
+        return Reply::status(404, "no such device");
+    };
+    let Some(location) = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|body| body.get("location").cloned())
+    else {
+        return Reply::status(400, "expected a location");
+    };
+    merge_patch(&mut entry.config, json!({ "location": location }));
+    entry.version += 1;
+    Reply::status(200, "")
+}
+
+fn address(state: &mut State, method: &str, key: (String, String), body: &str) -> Reply {
+    if method == "PUT" {
+        let Some(address) = serde_json::from_str::<Value>(body)
+            .ok()
+            .and_then(|body| body.get("address")?.as_str().map(str::to_string))
+        else {
+            return Reply::status(400, "expected an address");
+        };
+        state.addresses.insert(key, address);
+        return Reply::status(200, "");
+    }
+    match state.addresses.get(&key) {
+        Some(address) => Reply::json(200, &json!({ "address": address })),
+        None => Reply::status(404, "no address"),
+    }
+}
+
+fn upload(state: &mut State, body: &str) -> Reply {
+    let data = serde_json::from_str::<Vec<LibraData>>(body)
+        .or_else(|_| serde_json::from_str::<LibraData>(body).map(|data| vec![data]));
+    match data {
+        Ok(data) => {
+            state.uploads.extend(data);
+            Reply::status(201, "")
+        }
+        Err(e) => Reply::status(400, &e.to_string()),
+    }
+}
+
+/// Applies a JSON Merge Patch (RFC 7396).
+fn merge_patch(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = json!({});
+    }
+    let target = target.as_object_mut().expect("just made an object");
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(&key);
+        } else {
+            merge_patch(target.entry(key).or_insert(Value::Null), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Action;
+    use crate::error::Error;
+    use crate::libra::{Config, ConfigPatch};
+    use std::time::Instant;
+
+    #[test]
+    fn test_config_lifecycle() {
+        let server = TestServer::start();
+        let backend = server.backend();
+
+        let device = backend
+            .make_new_device(Model::LibraV1, Config::default())
+            .unwrap();
+        assert!(Device::try_new(Model::LibraV1, device.serial_number.as_str()).is_ok());
+        assert_eq!(server.devices(), std::slice::from_ref(&device));
+
+        let versioned = backend.get_versioned_config(device.clone()).unwrap();
+        assert_eq!(versioned.value, Config::default());
+        let etag = versioned.etag.unwrap();
+        assert_eq!(
+            backend
+                .get_config_if_changed(device.clone(), &etag)
+                .unwrap(),
+            None
+        );
+
+        let patch = ConfigPatch {
+            ingredient: Some("Tofu".into()),
+            ..ConfigPatch::default()
+        };
+        backend.patch_config(device.clone(), &patch).unwrap();
+        let error = backend
+            .edit_config_if_match(device.clone(), Config::default(), &etag)
+            .unwrap_err();
+        match error {
+            Error::Conflict { current, .. } => assert_eq!(current.value.ingredient, "Tofu"),
+            other => panic!("Expected Error::Conflict, got {other:?}"),
+        }
+
+        backend.transfer_device(device.clone(), "Line 2").unwrap();
+        let config: Config = server.config(&device).unwrap();
+        assert_eq!(config.location, "Line 2");
+        assert_eq!(config.ingredient, "Tofu");
+
+        backend
+            .put_address(device.clone(), &"10.0.0.7".parse().unwrap())
+            .unwrap();
+        assert_eq!(
+            backend.get_address(device.clone()).unwrap().to_string(),
+            "10.0.0.7"
+        );
+
+        backend.delete_device(device.clone()).unwrap();
+        assert!(server.devices().is_empty());
+        assert_eq!(server.address(&device), None);
+    }
+
+    #[test]
+    fn test_list_pages_and_uploads() {
+        let server = TestServer::start();
+        server.set_page_size(2);
+        for serial_number in ["L1", "L2", "L3"] {
+            server.insert_device(
+                &Device::new(Model::LibraV0, serial_number),
+                &Config::default(),
+            );
+        }
+        server.insert_device(&Device::new(Model::IchibuV2, "I1"), &json!({}));
+        let listed = server.backend().list_devices(Model::LibraV0).unwrap();
+        assert_eq!(listed.len(), 3);
+        assert_eq!(server.requests().len(), 2);
+
+        let data = LibraData {
+            device: Device::new(Model::LibraV0, "L1"),
+            location: "Line 1".into(),
+            ingredient: "Tofu".into(),
+            data_action: Action::Served,
+            amount: 120.,
+            timestamp: time::OffsetDateTime::UNIX_EPOCH,
+        };
+        let response = reqwest::blocking::Client::new()
+            .post(format!("{}/data", server.url()))
+            .bearer_auth(TEST_TOKEN)
+            .json(&[&data])
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        assert_eq!(server.uploaded_data()[0].amount, 120.);
+    }
+
+    #[test]
+    fn test_injected_faults() {
+        let server = TestServer::start();
+        let device = Device::new(Model::LibraV0, "L1");
+        server.insert_device(&device, &Config::default());
+        let backend = server.backend();
+
+        server.inject(Fault::Status(503));
+        server.inject(Fault::Unauthorized);
+        server.inject(Fault::Latency(Duration::from_millis(50)));
+        let status = |result: Result<Config, Error>| match result {
+            Err(Error::Backend { status, .. }) => status.as_u16(),
+            other => panic!("Expected Error::Backend, got {other:?}"),
+        };
+        assert_eq!(status(backend.get_config(device.clone())), 503);
+        assert_eq!(status(backend.get_config(device.clone())), 401);
+        let started = Instant::now();
+        assert_eq!(
+            backend.get_config(device.clone()).unwrap(),
+            Config::default()
+        );
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        server.set_token("rotated");
+        assert_eq!(status(backend.get_config(device)), 401);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::backend::ConfigBackend;
+#[cfg(any(feature = "write", feature = "address"))]
+use crate::backend::{Clients, TIMEOUT};
+use crate::error::Error;
+use crate::secret::Secret;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+#[cfg(any(feature = "write", feature = "address"))]
+use std::sync::Arc;
+use std::sync::Mutex;
+#[cfg(any(feature = "write", feature = "address"))]
+use std::time::Instant;
+use std::time::{Duration, SystemTime};
+
+/// Tokens this close to expiring are refreshed before they're used.
+pub const REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+pub type TokenFuture<'a> = Pin<Box<dyn Future<Output = Result<Secret<String>, Error>> + Send + 'a>>;
+
+/// Supplies the bearer token for backend requests.
+pub trait TokenProvider: Send + Sync {
+    /// Returns a token that is valid for at least [`REFRESH_MARGIN`], refreshing it if needed.
+    fn token(&self) -> Result<Secret<String>, Error>;
+    /// Like [`TokenProvider::token`], for async callers.
+    fn token_async(&self) -> TokenFuture<'_> {
+        Box::pin(std::future::ready(self.token()))
+    }
+    /// Drops the current token after the backend rejected it. Returns whether the next call
+    /// to [`TokenProvider::token`] may yield a different one.
+    fn invalidate(&self) -> bool {
+        false
+    }
+    /// Called when the provider is installed on `backend`, e.g. to share its HTTP clients.
+    fn attach(&mut self, _backend: &ConfigBackend) {}
+}
+
+/// A token that never changes.
+pub struct StaticToken(Secret<String>);
+
+impl StaticToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(Secret::new(token.into()))
+    }
+}
+
+impl TokenProvider for StaticToken {
+    fn token(&self) -> Result<Secret<String>, Error> {
+        Ok(self.0.clone())
+    }
+}
+
+/// A token read from a file, reloaded whenever the file changes, e.g. when a sidecar
+/// rotates it.
+pub struct FileToken {
+    path: PathBuf,
+    cached: Mutex<Option<(Secret<String>, SystemTime)>>,
+}
+
+impl FileToken {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            cached: Mutex::new(None),
+        }
+    }
+}
+
+impl TokenProvider for FileToken {
+    fn token(&self) -> Result<Secret<String>, Error> {
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| Error::file_system(&self.path, e))?;
+        let mut cached = self.cached.lock().unwrap();
+        if let Some((token, loaded)) = cached.as_ref()
+            && *loaded == modified
+        {
+            return Ok(token.clone());
+        }
+        let content =
+            fs::read_to_string(&self.path).map_err(|e| Error::file_system(&self.path, e))?;
+        let token = content.trim();
+        if token.is_empty() {
+            return Err(Error::validation(
+                "token",
+                format!("{} is empty", self.path.display()),
+            ));
+        }
+        let token = Secret::new(token.to_string());
+        *cached = Some((token.clone(), modified));
+        Ok(token)
+    }
+    fn invalidate(&self) -> bool {
+        *self.cached.lock().unwrap() = None;
+        true
+    }
+}
+
+/// OAuth2 client-credentials grant against a token endpoint, caching each token until
+/// shortly before it expires.
+#[cfg(any(feature = "write", feature = "address"))]
+pub struct ClientCredentials {
+    token_url: String,
+    client_id: String,
+    client_secret: Secret<String>,
+    scope: Option<String>,
+    cached: Mutex<Option<CachedToken>>,
+    clients: Arc<Clients>,
+}
+
+#[cfg(any(feature = "write", feature = "address"))]
+struct CachedToken {
+    token: Secret<String>,
+    expires_at: Option<Instant>,
+}
+
+#[cfg(any(feature = "write", feature = "address"))]
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+#[cfg(any(feature = "write", feature = "address"))]
+impl ClientCredentials {
+    pub fn new(
+        token_url: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        Self {
+            token_url: token_url.into(),
+            client_id: client_id.into(),
+            client_secret: Secret::new(client_secret.into()),
+            scope: None,
+            cached: Mutex::new(None),
+            clients: Arc::default(),
+        }
+    }
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+    fn form(&self) -> Vec<(&str, &str)> {
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", &self.client_id),
+            ("client_secret", self.client_secret.expose()),
+        ];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+        form
+    }
+    fn fresh(&self) -> Option<Secret<String>> {
+        let cached = self.cached.lock().unwrap();
+        let cached = cached.as_ref()?;
+        match cached.expires_at {
+            Some(expires_at) if Instant::now() + REFRESH_MARGIN >= expires_at => None,
+            _ => Some(cached.token.clone()),
+        }
+    }
+    fn store(&self, response: TokenResponse) -> Secret<String> {
+        let token = Secret::new(response.access_token);
+        *self.cached.lock().unwrap() = Some(CachedToken {
+            token: token.clone(),
+            expires_at: response
+                .expires_in
+                .map(|seconds| Instant::now() + Duration::from_secs(seconds)),
+        });
+        token
+    }
+}
+
+#[cfg(any(feature = "write", feature = "address"))]
+impl TokenProvider for ClientCredentials {
+    fn token(&self) -> Result<Secret<String>, Error> {
+        use reqwest::{Method, StatusCode};
+        if let Some(token) = self.fresh() {
+            return Ok(token);
+        }
+        let response = self
+            .clients
+            .blocking()
+            .post(&self.token_url)
+            .form(&self.form())
+            .timeout(TIMEOUT)
+            .send()
+            .map_err(|e| Error::reqwest(Method::POST, &self.token_url, e))?;
+        if response.status() != StatusCode::OK {
+            return Err(Error::backend(Method::POST, &self.token_url, response));
+        }
+        let response = response
+            .json()
+            .map_err(|e| Error::reqwest(Method::POST, &self.token_url, e))?;
+        Ok(self.store(response))
+    }
+    #[cfg(feature = "write")]
+    fn token_async(&self) -> TokenFuture<'_> {
+        use reqwest::{Method, StatusCode};
+        Box::pin(async move {
+            if let Some(token) = self.fresh() {
+                return Ok(token);
+            }
+            let response = self
+                .clients
+                .non_blocking()
+                .post(&self.token_url)
+                .form(&self.form())
+                .timeout(TIMEOUT)
+                .send()
+                .await
+                .map_err(|e| Error::reqwest(Method::POST, &self.token_url, e))?;
+            if response.status() != StatusCode::OK {
+                return Err(Error::backend_async(Method::POST, &self.token_url, response).await);
+            }
+            let response = response
+                .json()
+                .await
+                .map_err(|e| Error::reqwest(Method::POST, &self.token_url, e))?;
+            Ok(self.store(response))
+        })
+    }
+    fn invalidate(&self) -> bool {
+        *self.cached.lock().unwrap() = None;
+        true
+    }
+    fn attach(&mut self, backend: &ConfigBackend) {
+        self.clients = backend.clients.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_token_reloads_on_change() {
+        let nanos = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_nanos();
+        let path =
+            std::env::temp_dir().join(format!("menu-test-token-{}-{nanos}", std::process::id()));
+        fs::write(&path, "first\n").unwrap();
+        let provider = FileToken::new(&path);
+        assert_eq!(provider.token().unwrap().expose(), "first");
+
+        fs::write(&path, "second").unwrap();
+        // Coarse mtime resolution may hide the rewrite; invalidating forces a reload.
+        assert!(provider.invalidate());
+        assert_eq!(provider.token().unwrap().expose(), "second");
+
+        fs::write(&path, "").unwrap();
+        provider.invalidate();
+        let error = provider.token().unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.code(), "validation");
+    }
+
+    #[cfg(any(feature = "write", feature = "address"))]
+    #[test]
+    fn test_client_credentials_caches_until_expiry() {
+        let mut server = mockito::Server::new();
+        let token_url = format!("{}/oauth/token", server.url());
+        let form = mockito::Matcher::AllOf(vec![
+            mockito::Matcher::UrlEncoded("grant_type".into(), "client_credentials".into()),
+            mockito::Matcher::UrlEncoded("client_id".into(), "scale".into()),
+            mockito::Matcher::UrlEncoded("client_secret".into(), "shh".into()),
+            mockito::Matcher::UrlEncoded("scope".into(), "configs".into()),
+        ]);
+        let long_lived = server
+            .mock("POST", "/oauth/token")
+            .match_body(form.clone())
+            .with_body(r#"{"access_token":"t1","token_type":"Bearer","expires_in":3600}"#)
+            .expect(1)
+            .create();
+        let provider = ClientCredentials::new(&token_url, "scale", "shh").with_scope("configs");
+        assert_eq!(provider.token().unwrap().expose(), "t1");
+        assert_eq!(provider.token().unwrap().expose(), "t1");
+        long_lived.assert();
+
+        // Tokens inside the refresh margin are replaced before use.
+        provider.invalidate();
+        let short_lived = server
+            .mock("POST", "/oauth/token")
+            .match_body(form)
+            .with_body(r#"{"access_token":"t2","expires_in":10}"#)
+            .expect(2)
+            .create();
+        assert_eq!(provider.token().unwrap().expose(), "t2");
+        assert_eq!(provider.token().unwrap().expose(), "t2");
+        short_lived.assert();
+
+        server
+            .mock("POST", "/oauth/token")
+            .with_status(401)
+            .with_body("bad client")
+            .create();
+        provider.invalidate();
+        assert_eq!(provider.token().unwrap_err().code(), "backend");
+    }
+}
@@ -0,0 +1,85 @@
+//! The default auditor is process-wide, so it is tested in a binary of its own where it
+//! can't leak into other tests.
+#![cfg(feature = "write")]
+
+use menu::audit::{AuditLog, Auditor, Operation, set_default_auditor};
+use menu::backend::ConfigBackend;
+use menu::device::{Device, Model};
+use menu::ichibu::ScaleConfig;
+use menu::libra::Libra;
+use menu::read::Read;
+use menu::snapshot::Snapshots;
+use std::fs;
+
+#[test]
+fn test_default_auditor_records_every_mutation() {
+    let dir = std::env::temp_dir().join("menu-test-default-auditor");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let auditor = Auditor::new(AuditLog::new(dir.join("audit.jsonl")), "ana");
+    set_default_auditor(Some(auditor.clone()));
+
+    let libras = dir.join("libras.yaml");
+    let first = Libra::default();
+    let second = Libra {
+        device: Device::new(Model::LibraV0, "L1"),
+        ..Libra::default()
+    };
+    Libra::new_config_file(vec![first.clone()], &libras).unwrap();
+    second.clone().add_to_config_file(&libras).unwrap();
+    let mut edited = first.clone();
+    edited.config.gain = 2.5;
+    edited.edit_config_file(&libras).unwrap();
+    let original = Snapshots::new(&libras).list().unwrap().remove(0);
+    Libra::restore_from_snapshot(&first.device, &libras, &original).unwrap();
+    Libra::remove_from_config_file(second.device.clone(), &libras).unwrap();
+
+    let scale = dir.join("scale.toml");
+    fs::write(&scale, include_str!("../scale.toml")).unwrap();
+    let mut scale_config = ScaleConfig::read(&scale).unwrap();
+    scale_config.update_coefficients([0.5, -1.25, 3.0e-3, 1.0]);
+    scale_config.edit_config_file(&scale).unwrap();
+    let scale_snapshots = Snapshots::new(&scale);
+    scale_snapshots
+        .restore(&scale_snapshots.list().unwrap()[0])
+        .unwrap();
+
+    let mut server = mockito::Server::new();
+    server.mock("DELETE", "/LibraV0/L1").create();
+    server.mock("POST", "/LibraV0/L1/transfer").create();
+    let backend = ConfigBackend::new(server.url(), "test-token".to_string());
+    backend
+        .transfer_device(second.device.clone(), "Kitchen 2")
+        .unwrap();
+    backend.delete_device(second.device.clone()).unwrap();
+
+    set_default_auditor(None);
+    first.clone().edit_config_file(&libras).unwrap();
+
+    let entries = auditor.log.entries().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let operations: Vec<_> = entries.iter().map(|entry| entry.operation).collect();
+    assert_eq!(
+        operations,
+        [
+            Operation::Add,
+            Operation::Edit,
+            Operation::Edit,
+            Operation::Remove,
+            Operation::Edit,
+            Operation::Edit,
+            Operation::Transfer,
+            Operation::Remove,
+        ]
+    );
+    assert_eq!(entries[2].changes[0].path, "config.gain", "the restore");
+    assert_eq!(entries[4].device, None, "scale files don't name a device");
+    assert_eq!(entries[4].changes[0].path, "coefficients");
+    assert_eq!(entries[5].device, None, "the generic restore");
+    assert_eq!(entries[5].changes[0].path, "coefficients");
+    assert_eq!(entries[5].changes[0].old, entries[4].changes[0].new);
+    assert_eq!(
+        entries[6].changes[0].path, "config.location",
+        "the transfer"
+    );
+}